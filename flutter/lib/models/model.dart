@@ -2785,6 +2785,8 @@ class PeerInfo with ChangeNotifier {
       platformAdditions[kPlatformAdditionsIddImpl] == 'rustdesk_idd';
   bool get isAmyuniIdd =>
       platformAdditions[kPlatformAdditionsIddImpl] == 'amyuni_idd';
+  bool get isXrandrVirtualDisplay =>
+      platformAdditions[kPlatformAdditionsIddImpl] == 'xrandr';
 
   Display? tryGetDisplay({int? display}) {
     if (displays.isEmpty) {
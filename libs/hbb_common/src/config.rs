@@ -63,6 +63,7 @@ lazy_static::lazy_static! {
     pub static ref APP_NAME: RwLock<String> = RwLock::new("Firefox".to_owned());
     static ref KEY_PAIR: Mutex<Option<KeyPair>> = Default::default();
     static ref USER_DEFAULT_CONFIG: RwLock<(UserDefaultConfig, Instant)> = RwLock::new((UserDefaultConfig::load(), Instant::now()));
+    static ref ROOT_CONFIG: RwLock<(RootConfig, Instant)> = RwLock::new((RootConfig::load(), Instant::now()));
     pub static ref NEW_STORED_PEER_CONFIG: Mutex<HashSet<String>> = Default::default();
     pub static ref DEFAULT_SETTINGS: RwLock<HashMap<String, String>> = Default::default();
     pub static ref OVERWRITE_SETTINGS: RwLock<HashMap<String, String>> = Default::default();
@@ -233,6 +234,13 @@ pub struct Resolution {
 pub struct PeerConfig {
     #[serde(default, deserialize_with = "deserialize_vec_u8")]
     pub password: Vec<u8>,
+    // The sign public key of the peer, pinned on the first secure connection
+    #[serde(
+        default,
+        deserialize_with = "deserialize_vec_u8",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pinned_pk: Vec<u8>,
     #[serde(default, deserialize_with = "deserialize_size")]
     pub size: Size,
     #[serde(default, deserialize_with = "deserialize_size")]
@@ -274,6 +282,12 @@ pub struct PeerConfig {
     pub allow_swap_key: AllowSwapKey,
     #[serde(default, deserialize_with = "deserialize_vec_i32_string_i32")]
     pub port_forwards: Vec<(i32, String, i32)>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_vec_port_forward_profile",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub port_forward_profiles: Vec<PortForwardProfile>,
     #[serde(default, deserialize_with = "deserialize_i32")]
     pub direct_failures: i32,
     #[serde(flatten)]
@@ -345,6 +359,7 @@ impl Default for PeerConfig {
     fn default() -> Self {
         Self {
             password: Default::default(),
+            pinned_pk: Default::default(),
             size: Default::default(),
             size_ft: Default::default(),
             size_pf: Default::default(),
@@ -357,6 +372,7 @@ impl Default for PeerConfig {
             privacy_mode: Default::default(),
             allow_swap_key: Default::default(),
             port_forwards: Default::default(),
+            port_forward_profiles: Default::default(),
             direct_failures: Default::default(),
             disable_audio: Default::default(),
             disable_clipboard: Default::default(),
@@ -942,7 +958,10 @@ impl Config {
 
     #[inline]
     fn purify_options(v: &mut HashMap<String, String>) {
-        v.retain(|k, v| is_option_can_save(&OVERWRITE_SETTINGS, k, &DEFAULT_SETTINGS, v));
+        v.retain(|k, v| {
+            !keys::KEYS_ROOT_SETTINGS.contains(&k.as_str())
+                && is_option_can_save(&OVERWRITE_SETTINGS, k, &DEFAULT_SETTINGS, v)
+        });
     }
 
     pub fn set_options(mut v: HashMap<String, String>) {
@@ -966,7 +985,9 @@ impl Config {
     }
 
     pub fn set_option(k: String, v: String) {
-        if !is_option_can_save(&OVERWRITE_SETTINGS, &k, &DEFAULT_SETTINGS, &v) {
+        if keys::KEYS_ROOT_SETTINGS.contains(&k.as_str())
+            || !is_option_can_save(&OVERWRITE_SETTINGS, &k, &DEFAULT_SETTINGS, &v)
+        {
             return;
         }
         let mut config = CONFIG2.write().unwrap();
@@ -1605,6 +1626,31 @@ impl LocalConfig {
     }
 }
 
+/// A named set of forwards carried by one port forwarding session.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PortForwardProfile {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_vec_port_forward_rule")]
+    pub forwards: Vec<PortForwardRule>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PortForwardRule {
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub local_port: i32,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub remote_host: String,
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub remote_port: i32,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub udp: bool,
+    // Listen on `local_port` of the controlled side, and forward to
+    // `remote_host:remote_port` reachable from the controlling side.
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub reverse: bool,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct DiscoveryPeer {
     #[serde(default, deserialize_with = "deserialize_string")]
@@ -1619,6 +1665,9 @@ pub struct DiscoveryPeer {
     pub online: bool,
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     pub ip_mac: HashMap<String, String>,
+    // base64 of the public key signing the discovery replies, empty for unsigned replies
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub pk: String,
 }
 
 impl DiscoveryPeer {
@@ -1767,6 +1816,51 @@ impl UserDefaultConfig {
     }
 }
 
+/// The options in `keys::KEYS_ROOT_SETTINGS`, which grant privileges and so are never set over ipc.
+/// They come from the hard or override settings of the custom client, or `<app>_root.toml` beside
+/// the config, which is ignored unless owned by root and not writable by others.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RootConfig {
+    #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
+    options: HashMap<String, String>,
+}
+
+impl RootConfig {
+    pub fn get_option(k: &str) -> String {
+        for settings in [&*HARD_SETTINGS, &*OVERWRITE_SETTINGS] {
+            if let Some(v) = settings.read().unwrap().get(k) {
+                return v.clone();
+            }
+        }
+        let mut cfg = ROOT_CONFIG.write().unwrap();
+        // edited by hand, reload as UserDefaultConfig does
+        if cfg.1.elapsed() > Duration::from_secs(1) {
+            *cfg = (Self::load(), Instant::now());
+        }
+        cfg.0.options.get(k).cloned().unwrap_or_default()
+    }
+
+    fn load() -> RootConfig {
+        let file = Config::file_("_root");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            match fs::metadata(&file) {
+                Ok(m) if m.uid() == 0 && m.mode() & 0o022 == 0 => {}
+                Ok(_) => {
+                    log::error!(
+                        "Ignored '{}', not owned by root or writable by others",
+                        file.display()
+                    );
+                    return Default::default();
+                }
+                Err(_) => return Default::default(),
+            }
+        }
+        load_path(file)
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct AbPeer {
     #[serde(
@@ -2030,6 +2124,11 @@ deserialize_default!(deserialize_vec_u8, Vec<u8>);
 deserialize_default!(deserialize_vec_string, Vec<String>);
 deserialize_default!(deserialize_vec_i32_string_i32, Vec<(i32, String, i32)>);
 deserialize_default!(deserialize_vec_discoverypeer, Vec<DiscoveryPeer>);
+deserialize_default!(
+    deserialize_vec_port_forward_profile,
+    Vec<PortForwardProfile>
+);
+deserialize_default!(deserialize_vec_port_forward_rule, Vec<PortForwardRule>);
 deserialize_default!(deserialize_vec_abpeer, Vec<AbPeer>);
 deserialize_default!(deserialize_vec_abentry, Vec<AbEntry>);
 deserialize_default!(deserialize_vec_groupuser, Vec<GroupUser>);
@@ -2102,6 +2201,15 @@ pub fn is_disable_tcp_listen() -> bool {
     is_some_hard_opton("disable-tcp-listen")
 }
 
+// Both sides must allow QUIC to use it.
+#[inline]
+pub fn is_quic_allowed() -> bool {
+    option2bool(
+        keys::OPTION_ALLOW_QUIC,
+        &Config::get_option(keys::OPTION_ALLOW_QUIC),
+    )
+}
+
 #[inline]
 pub fn is_disable_settings() -> bool {
     is_some_hard_opton("disable-settings")
@@ -2214,6 +2322,29 @@ pub mod keys {
     pub const OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE: &str =
         "enable-android-software-encoding-half-scale";
     pub const OPTION_ENABLE_TRUSTED_DEVICES: &str = "enable-trusted-devices";
+    pub const OPTION_AUDIT_LOG_FILE: &str = "audit-log-file";
+    pub const OPTION_AUDIT_LOG_MAX_SIZE: &str = "audit-log-max-size";
+    pub const OPTION_AUDIT_LOG_MAX_FILES: &str = "audit-log-max-files";
+    pub const OPTION_AUDIT_SYSLOG: &str = "audit-syslog";
+    pub const OPTION_ACCESS_CONTROL_RULES: &str = "access-control-rules";
+    pub const OPTION_LOGIN_MAX_ATTEMPTS: &str = "login-max-attempts";
+    pub const OPTION_LOGIN_ATTEMPT_WINDOW: &str = "login-attempt-window";
+    pub const OPTION_LOGIN_BAN_DURATION: &str = "login-ban-duration";
+    pub const OPTION_LOGIN_BAN_BACKOFF: &str = "login-ban-backoff";
+    pub const OPTION_LOGIN_MAX_TOTAL_ATTEMPTS: &str = "login-max-total-attempts";
+    pub const OPTION_LAN_DISCOVERY_ALLOWED_KEYS: &str = "lan-discovery-allowed-keys";
+    pub const OPTION_LAN_DISCOVERY_GROUP_KEY: &str = "lan-discovery-group-key";
+    pub const OPTION_ALLOW_WOL_RELAY: &str = "allow-wol-relay";
+    pub const OPTION_VOICE_CALL_AUDIO_MIXING: &str = "voice-call-audio-mixing";
+    pub const OPTION_AUDIO_SYSTEM_VOLUME: &str = "audio-system-volume";
+    pub const OPTION_AUDIO_MIC_VOLUME: &str = "audio-mic-volume";
+    pub const OPTION_CLIPBOARD_POLICY: &str = "clipboard-policy";
+    pub const OPTION_PLUGIN_TRUSTED_KEYS: &str = "plugin-trusted-keys";
+    pub const OPTION_ALLOW_CONTROL_API: &str = "allow-control-api";
+    pub const OPTION_CONTROL_API_ALLOWED_UIDS: &str = "control-api-allowed-uids";
+    pub const OPTION_REQUIRE_ENCRYPTION: &str = "require-encryption";
+    pub const OPTION_ALLOW_QUIC: &str = "allow-quic";
+    pub const OPTION_ALLOW_PER_VIEWER_ENCODING: &str = "allow-per-viewer-encoding";
 
     // buildin options
     pub const OPTION_DISPLAY_NAME: &str = "display-name";
@@ -2360,6 +2491,33 @@ pub mod keys {
         OPTION_ENABLE_DIRECTX_CAPTURE,
         OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE,
         OPTION_ENABLE_TRUSTED_DEVICES,
+        OPTION_ACCESS_CONTROL_RULES,
+        OPTION_LOGIN_MAX_ATTEMPTS,
+        OPTION_LOGIN_ATTEMPT_WINDOW,
+        OPTION_LOGIN_BAN_DURATION,
+        OPTION_LOGIN_BAN_BACKOFF,
+        OPTION_LOGIN_MAX_TOTAL_ATTEMPTS,
+        OPTION_LAN_DISCOVERY_ALLOWED_KEYS,
+        OPTION_LAN_DISCOVERY_GROUP_KEY,
+        OPTION_ALLOW_WOL_RELAY,
+        OPTION_VOICE_CALL_AUDIO_MIXING,
+        OPTION_AUDIO_SYSTEM_VOLUME,
+        OPTION_AUDIO_MIC_VOLUME,
+        OPTION_CLIPBOARD_POLICY,
+        OPTION_REQUIRE_ENCRYPTION,
+        OPTION_ALLOW_QUIC,
+        OPTION_ALLOW_PER_VIEWER_ENCODING,
+    ];
+
+    // HARD_SETTINGS or RootConfig, not in the options
+    pub const KEYS_ROOT_SETTINGS: &[&str] = &[
+        OPTION_PLUGIN_TRUSTED_KEYS,
+        OPTION_ALLOW_CONTROL_API,
+        OPTION_CONTROL_API_ALLOWED_UIDS,
+        OPTION_AUDIT_LOG_FILE,
+        OPTION_AUDIT_LOG_MAX_SIZE,
+        OPTION_AUDIT_LOG_MAX_FILES,
+        OPTION_AUDIT_SYSLOG,
     ];
 
     // BUILDIN_SETTINGS
@@ -2641,6 +2799,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_port_forward_profile_deserialize() {
+        let s = r#"
+        [[port_forward_profiles]]
+        name = "db"
+        [[port_forward_profiles.forwards]]
+        local_port = 5432
+        remote_host = "10.0.0.2"
+        remote_port = 5432
+        [[port_forward_profiles.forwards]]
+        local_port = 5353
+        remote_port = 53
+        udp = true
+        [[port_forward_profiles.forwards]]
+        local_port = 8080
+        remote_host = "127.0.0.1"
+        remote_port = 80
+        reverse = true
+        [[port_forward_profiles]]
+        name = 1
+        forwards = "wrong"
+        "#;
+        let cfg = toml::from_str::<PeerConfig>(s).unwrap();
+        assert_eq!(
+            cfg.port_forward_profiles,
+            vec![
+                PortForwardProfile {
+                    name: "db".to_owned(),
+                    forwards: vec![
+                        PortForwardRule {
+                            local_port: 5432,
+                            remote_host: "10.0.0.2".to_owned(),
+                            remote_port: 5432,
+                            ..Default::default()
+                        },
+                        PortForwardRule {
+                            local_port: 5353,
+                            remote_port: 53,
+                            udp: true,
+                            ..Default::default()
+                        },
+                        PortForwardRule {
+                            local_port: 8080,
+                            remote_host: "127.0.0.1".to_owned(),
+                            remote_port: 80,
+                            reverse: true,
+                            ..Default::default()
+                        },
+                    ],
+                },
+                PortForwardProfile::default(),
+            ]
+        );
+        // from the ui
+        let profile: PortForwardProfile = serde_json::from_str(
+            r#"{"name":"dns","forwards":[{"local_port":53,"remote_port":"x","udp":true}]}"#,
+        )
+        .unwrap();
+        assert_eq!(profile.name, "dns");
+        assert_eq!(
+            profile.forwards,
+            vec![PortForwardRule {
+                local_port: 53,
+                udp: true,
+                ..Default::default()
+            }]
+        );
+        // the profiles are not stored if empty
+        assert!(!toml::to_string(&PeerConfig::default())
+            .unwrap()
+            .contains("port_forward_profiles"));
+    }
+
     #[test]
     fn test_store_load() {
         let peerconfig_id = "123456789";
@@ -2662,4 +2893,27 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_root_options() {
+        let mut options = HashMap::from([
+            (keys::OPTION_PLUGIN_TRUSTED_KEYS.to_owned(), "a".to_owned()),
+            ("b".to_owned(), "c".to_owned()),
+        ]);
+        Config::purify_options(&mut options);
+        assert_eq!(options.len(), 1);
+        assert!(options.contains_key("b"));
+        OVERWRITE_SETTINGS
+            .write()
+            .unwrap()
+            .insert(keys::OPTION_PLUGIN_TRUSTED_KEYS.to_owned(), "a".to_owned());
+        assert_eq!(
+            RootConfig::get_option(keys::OPTION_PLUGIN_TRUSTED_KEYS),
+            "a"
+        );
+        OVERWRITE_SETTINGS
+            .write()
+            .unwrap()
+            .remove(keys::OPTION_PLUGIN_TRUSTED_KEYS);
+    }
 }
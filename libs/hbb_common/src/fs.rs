@@ -5,7 +5,12 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::{fs::File, io::*};
+use sodiumoxide::{base64, crypto::hash::sha256};
+use std::collections::HashMap;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::*,
+};
 
 use crate::{anyhow::anyhow, bail, get_version_number, message_proto::*, ResultType, Stream};
 // https://doc.rust-lang.org/std/os/windows/fs/trait.MetadataExt.html
@@ -14,6 +19,14 @@ use crate::{
     config::Config,
 };
 
+// Files are read and sent in blocks of this size, resuming is aligned to it.
+const BLOCK_SIZE: usize = 128 * 1024;
+// Sync the partial file and its resume state every segment of 8M, which is hashed.
+const RESUME_SYNC_BLOCKS: u64 = 64;
+// Delta sync block size is about sqrt(file size), within this range.
+const MIN_DELTA_BLOCK_SIZE: usize = 2 * 1024;
+const DELTA_STRONG_SUM_LEN: usize = 16;
+
 pub fn read_dir(path: &Path, include_hidden: bool) -> ResultType<FileDirectory> {
     let mut dir = FileDirectory {
         path: get_string(path),
@@ -220,6 +233,68 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    // Reader: blocks to skip as confirmed by the peer.
+    // Writer: blocks kept from the partial download of the next file.
+    resume_blk: u32,
+    // Writer: resume state of the file being written, None if it can not be resumed.
+    #[serde(skip_serializing)]
+    resume: Option<ResumeState>,
+    // Writer: bytes of the current file on the disk.
+    file_written: u64,
+    // Writer: the file which the reader can send as a delta of the existing one.
+    delta_file_num: Option<i32>,
+    // Writer: the existing file to copy the unchanged blocks from.
+    #[serde(skip_serializing)]
+    delta_basis: Option<DeltaBasis>,
+    // Reader: signatures of the existing file on the writer side, received with the digest.
+    #[serde(skip_serializing)]
+    peer_signatures: Option<(i32, FileTransferSignatures)>,
+    // Reader: only the changed blocks are sent if it is not None.
+    #[serde(skip_serializing)]
+    delta: Option<DeltaReader>,
+}
+
+/// Sidecar of a partial download, saved as `<file>.download.state`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+struct ResumeState {
+    #[serde(skip)]
+    file_num: i32,
+    // the source file, a partial download of another version can not be resumed
+    last_modified: u64,
+    file_size: u64,
+    // sha256 of each segment already synced to the disk, checked before resuming
+    #[serde(default)]
+    segments: Vec<String>,
+}
+
+#[inline]
+fn get_resume_state_path(download_path: &str) -> String {
+    format!("{}.state", download_path)
+}
+
+fn load_resume_state(download_path: &str) -> Option<ResumeState> {
+    let content = std::fs::read_to_string(get_resume_state_path(download_path)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn store_resume_state(download_path: &str, state: &ResumeState) {
+    if let Ok(content) = serde_json::to_string(state) {
+        std::fs::write(get_resume_state_path(download_path), content).ok();
+    }
+}
+
+// Hash the segment of the partial file at the current position.
+async fn hash_resume_segment(file: &mut File) -> ResultType<String> {
+    let mut state = sha256::State::new();
+    let mut buf = vec![0; BLOCK_SIZE];
+    for _ in 0..RESUME_SYNC_BLOCKS {
+        file.read_exact(&mut buf).await?;
+        state.update(&buf);
+    }
+    Ok(base64::encode(
+        state.finalize().0,
+        base64::Variant::Original,
+    ))
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -359,13 +434,19 @@ impl TransferJob {
         self.file_num
     }
 
-    pub fn modify_time(&self) {
+    pub fn modify_time(&mut self) {
+        // the basis must be closed before it is replaced,
+        // the one of the next file may be already prepared
+        if self.delta_basis.as_ref().map(|b| b.file_num) == Some(self.file_num) {
+            self.delta_basis.take();
+        }
         let file_num = self.file_num as usize;
         if file_num < self.files.len() {
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
             let download_path = format!("{}.download", get_string(&path));
-            std::fs::rename(download_path, &path).ok();
+            std::fs::rename(&download_path, &path).ok();
+            std::fs::remove_file(get_resume_state_path(&download_path)).ok();
             filetime::set_file_mtime(
                 &path,
                 filetime::FileTime::from_unix_time(entry.modified_time as _, 0),
@@ -380,7 +461,8 @@ impl TransferJob {
             let entry = &self.files[file_num];
             let path = self.join(&entry.name);
             let download_path = format!("{}.download", get_string(&path));
-            std::fs::remove_file(download_path).ok();
+            std::fs::remove_file(&download_path).ok();
+            std::fs::remove_file(get_resume_state_path(&download_path)).ok();
         }
     }
 
@@ -393,9 +475,10 @@ impl TransferJob {
             bail!("Wrong file number");
         }
         if file_num != self.file_num as usize || self.file.is_none() {
-            self.modify_time();
-            if let Some(file) = self.file.as_mut() {
+            // finish the previous file, there's none before the first block
+            if let Some(file) = self.file.take() {
                 file.sync_all().await?;
+                self.modify_time();
             }
             self.file_num = block.file_num;
             let entry = &self.files[file_num];
@@ -404,28 +487,206 @@ impl TransferJob {
                 std::fs::create_dir_all(p).ok();
             }
             let path = format!("{}.download", get_string(&path));
-            self.file = Some(File::create(&path).await?);
+            if self.resume.as_ref().map(|r| r.file_num) != Some(block.file_num) {
+                self.resume = None;
+                self.resume_blk = 0;
+            }
+            self.file_written = 0;
+            if self.resume_blk > 0 {
+                let offset = self.resume_blk as u64 * BLOCK_SIZE as u64;
+                let mut file = OpenOptions::new().write(true).open(&path).await?;
+                file.set_len(offset).await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                log::info!("resume {} from {}", path, offset);
+                self.file = Some(file);
+                self.file_written = offset;
+                self.finished_size += offset;
+            } else {
+                self.file = Some(File::create(&path).await?);
+            }
+            self.resume_blk = 0;
+            if let Some(state) = self.resume.as_mut() {
+                let segments = self.file_written / (RESUME_SYNC_BLOCKS * BLOCK_SIZE as u64);
+                state.segments.truncate(segments as _);
+                store_resume_state(&path, state);
+            }
+            if self.delta_basis.as_ref().map(|b| b.file_num) == Some(block.file_num) {
+                let basis_path = self.join(&self.files[file_num].name);
+                if let Some(basis) = self.delta_basis.as_mut() {
+                    basis.file = Some(File::open(&basis_path).await?);
+                }
+            }
         }
-        if block.compressed {
+        let n = if block.compressed {
             let tmp = decompress(&block.data);
             self.file
                 .as_mut()
                 .ok_or(anyhow!("file is None"))?
                 .write_all(&tmp)
                 .await?;
-            self.finished_size += tmp.len() as u64;
+            tmp.len() as u64
         } else {
             self.file
                 .as_mut()
                 .ok_or(anyhow!("file is None"))?
                 .write_all(&block.data)
                 .await?;
-            self.finished_size += block.data.len() as u64;
-        }
+            block.data.len() as u64
+        };
+        let n = n + self.copy_delta_blocks(&block).await?;
+        self.finished_size += n;
+        self.file_written += n;
         self.transferred += block.data.len() as u64;
+        self.sync_resume_state().await?;
+        Ok(())
+    }
+
+    // Copy the unchanged blocks of the existing file, they follow the data of the block.
+    async fn copy_delta_blocks(&mut self, block: &FileTransferBlock) -> ResultType<u64> {
+        if block.copy_blks.is_empty() {
+            return Ok(0);
+        }
+        let basis = match self.delta_basis.as_mut() {
+            Some(basis) if basis.file_num == block.file_num => basis,
+            _ => bail!("No delta basis for file {}", block.file_num),
+        };
+        let basis_file = basis.file.as_mut().ok_or(anyhow!("basis file is None"))?;
+        let file = self.file.as_mut().ok_or(anyhow!("file is None"))?;
+        let mut buf = vec![0; basis.block_size];
+        for blk in block.copy_blks.iter() {
+            basis_file
+                .seek(SeekFrom::Start(*blk as u64 * basis.block_size as u64))
+                .await?;
+            basis_file.read_exact(&mut buf).await?;
+            file.write_all(&buf).await?;
+        }
+        Ok(block.copy_blks.len() as u64 * basis.block_size as u64)
+    }
+
+    /// Remember whether the reader of `digest` can send the file as a delta.
+    pub fn set_delta_sync(&mut self, digest: &FileTransferDigest) {
+        self.delta_file_num = if digest.delta_sync {
+            Some(digest.file_num)
+        } else {
+            None
+        };
+    }
+
+    /// Signatures of the existing file which will be overwritten, sent to the reader
+    /// so that only changed blocks are transferred.
+    /// Returns None if the reader does not support delta sync.
+    pub async fn new_signatures(&mut self, file_num: i32) -> Option<FileTransferSignatures> {
+        self.delta_basis = None;
+        if self.delta_file_num != Some(file_num) || file_num as usize >= self.files.len() {
+            return None;
+        }
+        let path = self.join(&self.files[file_num as usize].name);
+        if !path.is_file() {
+            return None;
+        }
+        // the whole file is read and hashed
+        let res = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || get_signatures(&path)).await
+        };
+        match res.map_err(|e| anyhow!(e)).and_then(|r| r) {
+            Ok(signatures) => {
+                if signatures.blocks.is_empty() {
+                    return None;
+                }
+                log::info!(
+                    "id: {}, file_num: {}, {} signatures of {:?}",
+                    self.id,
+                    file_num,
+                    signatures.blocks.len(),
+                    path
+                );
+                self.delta_basis = Some(DeltaBasis {
+                    file_num,
+                    block_size: signatures.block_size as _,
+                    file: None,
+                });
+                Some(signatures)
+            }
+            Err(err) => {
+                log::error!("Failed to get signatures of {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+
+    /// Signatures received with the digest of an upload, used if the file is overwritten.
+    pub fn set_peer_signatures(
+        &mut self,
+        file_num: i32,
+        signatures: Option<FileTransferSignatures>,
+    ) {
+        self.peer_signatures = signatures.map(|s| (file_num, s));
+    }
+
+    async fn sync_resume_state(&mut self) -> ResultType<()> {
+        let segment_size = RESUME_SYNC_BLOCKS * BLOCK_SIZE as u64;
+        let segments = self.file_written / segment_size;
+        match self.resume.as_ref() {
+            Some(state) if segments > state.segments.len() as u64 => {}
+            _ => return Ok(()),
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.sync_data().await?;
+        }
+        let file_num = self.file_num as usize;
+        if file_num < self.files.len() {
+            let path = self.join(&self.files[file_num].name);
+            let download_path = format!("{}.download", get_string(&path));
+            if let Some(state) = self.resume.as_mut() {
+                // the synced segments are read back, what is hashed is what is on the disk
+                let mut file = File::open(&download_path).await?;
+                file.seek(SeekFrom::Start(state.segments.len() as u64 * segment_size))
+                    .await?;
+                while (state.segments.len() as u64) < segments {
+                    state.segments.push(hash_resume_segment(&mut file).await?);
+                }
+                store_resume_state(&download_path, state);
+            }
+        }
         Ok(())
     }
 
+    /// Check the partial download of the file in `digest`, which is sent by the reader.
+    /// Returns the number of blocks that needn't be transferred again, only the segments
+    /// matching their hashes are kept.
+    pub async fn resume_offset_blk(&mut self, digest: &FileTransferDigest) -> u32 {
+        self.resume = None;
+        self.resume_blk = 0;
+        let file_num = digest.file_num as usize;
+        if !digest.resumable || file_num >= self.files.len() {
+            return 0;
+        }
+        let path = self.join(&self.files[file_num].name);
+        let download_path = format!("{}.download", get_string(&path));
+        let mut state = ResumeState {
+            file_num: digest.file_num,
+            last_modified: digest.last_modified,
+            file_size: digest.file_size,
+            segments: Vec::new(),
+        };
+        if let Some(saved) = load_resume_state(&download_path) {
+            if saved.last_modified == state.last_modified && saved.file_size == state.file_size {
+                if let Ok(mut file) = File::open(&download_path).await {
+                    for hash in saved.segments {
+                        match hash_resume_segment(&mut file).await {
+                            Ok(h) if h == hash => state.segments.push(h),
+                            _ => break,
+                        }
+                    }
+                }
+            }
+        }
+        self.resume_blk = (state.segments.len() as u64 * RESUME_SYNC_BLOCKS) as _;
+        self.resume = Some(state);
+        self.resume_blk
+    }
+
     #[inline]
     pub fn join(&self, name: &str) -> PathBuf {
         if name.is_empty() {
@@ -464,8 +725,26 @@ impl TransferJob {
             }
             return Ok(None);
         }
-        const BUF_SIZE: usize = 128 * 1024;
-        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        if self.resume_blk > 0 {
+            let offset = self.resume_blk as u64 * BLOCK_SIZE as u64;
+            self.resume_blk = 0;
+            self.file
+                .as_mut()
+                .ok_or(anyhow!("file is None"))?
+                .seek(SeekFrom::Start(offset))
+                .await?;
+            log::info!(
+                "id: {}, file_num: {}, resume from {}",
+                self.id,
+                file_num,
+                offset
+            );
+            self.finished_size += offset;
+        }
+        if self.delta.is_some() {
+            return self.read_delta().await;
+        }
+        let mut buf: Vec<u8> = vec![0; BLOCK_SIZE];
         let mut compressed = false;
         let mut offset: usize = 0;
         loop {
@@ -485,7 +764,7 @@ impl TransferJob {
                 }
                 Ok(n) => {
                     offset += n;
-                    if n == 0 || offset == BUF_SIZE {
+                    if n == 0 || offset == BLOCK_SIZE {
                         break;
                     }
                 }
@@ -517,6 +796,50 @@ impl TransferJob {
         }))
     }
 
+    async fn read_delta(&mut self) -> ResultType<Option<FileTransferBlock>> {
+        let file_num = self.file_num;
+        let res = match (self.delta.as_mut(), self.file.as_mut()) {
+            (Some(delta), Some(file)) => delta.next(file).await,
+            _ => Err(anyhow!("file is None")),
+        };
+        let (mut buf, copy_blks) = match res {
+            Ok(Some(v)) => v,
+            res => {
+                // the file is finished, send an empty block like `read`
+                self.file_num += 1;
+                self.file = None;
+                self.delta = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+                res?;
+                (vec![], vec![])
+            }
+        };
+        let block_size = self
+            .delta
+            .as_ref()
+            .map(|d| d.block_size)
+            .unwrap_or_default();
+        self.finished_size += (buf.len() + copy_blks.len() * block_size) as u64;
+        let mut compressed = false;
+        if !buf.is_empty() && !is_compressed_file(&self.files[file_num as usize].name) {
+            let tmp = compress(&buf);
+            if tmp.len() < buf.len() {
+                buf = tmp;
+                compressed = true;
+            }
+        }
+        self.transferred += buf.len() as u64;
+        Ok(Some(FileTransferBlock {
+            id: self.id,
+            file_num,
+            data: buf.into(),
+            compressed,
+            copy_blks,
+            ..Default::default()
+        }))
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -535,6 +858,8 @@ impl TransferJob {
             file_num: self.file_num,
             last_modified,
             file_size: meta.len(),
+            resumable: true,
+            delta_sync: true,
             ..Default::default()
         });
         msg.set_file_response(resp);
@@ -631,8 +956,21 @@ impl TransferJob {
                         self.set_file_confirmed(true);
                     }
                 }
-                Some(file_transfer_send_confirm_request::Union::OffsetBlk(_offset)) => {
+                Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset)) => {
                     self.set_file_confirmed(true);
+                    self.resume_blk = offset;
+                    let peer_signatures = self
+                        .peer_signatures
+                        .take()
+                        .filter(|(file_num, _)| *file_num == r.file_num)
+                        .map(|(_, s)| s);
+                    self.delta = r
+                        .signatures
+                        .clone()
+                        .into_option()
+                        .or(peer_signatures)
+                        .filter(|s| offset == 0 && s.block_size > 0 && !s.blocks.is_empty())
+                        .map(DeltaReader::new);
                 }
                 _ => {}
             }
@@ -913,3 +1251,349 @@ pub fn serialize_transfer_job(job: &TransferJob, done: bool, cancel: bool, error
     value["error"] = json!(error);
     serde_json::to_string(&value).unwrap_or_default()
 }
+
+#[derive(Debug)]
+struct DeltaBasis {
+    file_num: i32,
+    block_size: usize,
+    file: Option<File>,
+}
+
+// rsync rolling checksum, https://rsync.samba.org/tech_report/node3.html
+#[inline]
+fn weak_sum(data: &[u8]) -> (u32, u32) {
+    let len = data.len() as u32;
+    let mut a: u32 = 0;
+    let mut b: u32 = 0;
+    for (i, x) in data.iter().enumerate() {
+        a = a.wrapping_add(*x as u32);
+        b = b.wrapping_add((len - i as u32).wrapping_mul(*x as u32));
+    }
+    (a & 0xffff, b & 0xffff)
+}
+
+#[inline]
+fn roll_weak_sum((a, b): (u32, u32), out: u8, inn: u8, len: u32) -> (u32, u32) {
+    let a = a.wrapping_sub(out as u32).wrapping_add(inn as u32) & 0xffff;
+    let b = b.wrapping_sub(len.wrapping_mul(out as u32)).wrapping_add(a) & 0xffff;
+    (a, b)
+}
+
+#[inline]
+fn strong_sum(data: &[u8]) -> Vec<u8> {
+    sha256::hash(data).0[..DELTA_STRONG_SUM_LEN].to_vec()
+}
+
+fn get_signatures(path: &Path) -> ResultType<FileTransferSignatures> {
+    use std::io::Read as _;
+    let size = std::fs::metadata(path)?.len();
+    let block_size = ((size as f64).sqrt() as usize)
+        .next_power_of_two()
+        .clamp(MIN_DELTA_BLOCK_SIZE, BLOCK_SIZE);
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0; block_size];
+    let mut blocks = Vec::new();
+    loop {
+        let mut offset = 0;
+        while offset < block_size {
+            let n = file.read(&mut buf[offset..])?;
+            if n == 0 {
+                break;
+            }
+            offset += n;
+        }
+        // the last partial block is always sent as data
+        if offset < block_size {
+            break;
+        }
+        let (a, b) = weak_sum(&buf);
+        blocks.push(FileTransferBlockSignature {
+            weak: a | (b << 16),
+            strong: strong_sum(&buf).into(),
+            ..Default::default()
+        });
+    }
+    Ok(FileTransferSignatures {
+        block_size: block_size as _,
+        blocks,
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Default)]
+struct DeltaReader {
+    block_size: usize,
+    // weak sum -> (block index, strong sum)
+    signatures: HashMap<u32, Vec<(u32, Vec<u8>)>>,
+    // data read but not sent, the window being checked starts at `pos`
+    buf: Vec<u8>,
+    pos: usize,
+    // weak sum of the window at `pos`, if known
+    sum: Option<(u32, u32)>,
+    eof: bool,
+    // the literal data and the existing blocks following it, not returned yet
+    data: Vec<u8>,
+    copy_blks: Vec<u32>,
+}
+
+impl DeltaReader {
+    fn new(signatures: FileTransferSignatures) -> Self {
+        let mut map: HashMap<u32, Vec<(u32, Vec<u8>)>> = HashMap::new();
+        for (i, s) in signatures.blocks.into_iter().enumerate() {
+            map.entry(s.weak)
+                .or_default()
+                .push((i as u32, s.strong.to_vec()));
+        }
+        Self {
+            block_size: signatures.block_size as _,
+            signatures: map,
+            ..Default::default()
+        }
+    }
+
+    // Read ahead enough for a whole `scan`, unless many existing blocks follow.
+    async fn fill(&mut self, file: &mut File) -> ResultType<()> {
+        let mut chunk = vec![0; BLOCK_SIZE];
+        while !self.eof && self.buf.len() < self.pos + self.block_size + BLOCK_SIZE {
+            let n = file.read(&mut chunk).await?;
+            if n == 0 {
+                self.eof = true;
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+        Ok(())
+    }
+
+    fn find(&self, sum: (u32, u32), window: &[u8]) -> Option<u32> {
+        let candidates = self.signatures.get(&(sum.0 | (sum.1 << 16)))?;
+        let strong = strong_sum(window);
+        candidates
+            .iter()
+            .find(|(_, s)| *s == strong)
+            .map(|(i, _)| *i)
+    }
+
+    /// Returns the literal data and the indexes of the existing blocks following it,
+    /// None if the file is finished.
+    async fn next(&mut self, file: &mut File) -> ResultType<Option<(Vec<u8>, Vec<u32>)>> {
+        loop {
+            self.fill(file).await?;
+            // the rolling checksum is CPU bound, keep it off the runtime
+            let mut reader = std::mem::take(self);
+            let (reader, res) = tokio::task::spawn_blocking(move || {
+                let res = reader.scan();
+                (reader, res)
+            })
+            .await?;
+            *self = reader;
+            if let Some(res) = res {
+                return Ok(res);
+            }
+        }
+    }
+
+    // Scan the data read, returns None if more data is needed.
+    fn scan(&mut self) -> Option<Option<(Vec<u8>, Vec<u32>)>> {
+        loop {
+            let end = self.pos + self.block_size;
+            if end > self.buf.len() {
+                if !self.eof {
+                    return None;
+                }
+                if !self.copy_blks.is_empty() {
+                    return Some(Some(self.take()));
+                }
+                // the tail is shorter than a block
+                self.pos = 0;
+                self.sum = None;
+                let data = std::mem::take(&mut self.buf);
+                if data.is_empty() {
+                    return Some(None);
+                }
+                return Some(Some((data, vec![])));
+            }
+            let sum = match self.sum {
+                Some(sum) => sum,
+                None => weak_sum(&self.buf[self.pos..end]),
+            };
+            if let Some(i) = self.find(sum, &self.buf[self.pos..end]) {
+                if self.copy_blks.is_empty() {
+                    self.data = self.buf.drain(..self.pos).collect();
+                }
+                self.copy_blks.push(i);
+                self.buf.drain(..self.block_size);
+                self.pos = 0;
+                self.sum = None;
+                continue;
+            }
+            if !self.copy_blks.is_empty() {
+                self.sum = Some(sum);
+                return Some(Some(self.take()));
+            }
+            self.sum = if end < self.buf.len() {
+                Some(roll_weak_sum(
+                    sum,
+                    self.buf[self.pos],
+                    self.buf[end],
+                    self.block_size as _,
+                ))
+            } else {
+                None
+            };
+            self.pos += 1;
+            if self.pos >= BLOCK_SIZE {
+                self.sum = None;
+                let data = self.buf.drain(..self.pos).collect();
+                self.pos = 0;
+                return Some(Some((data, vec![])));
+            }
+        }
+    }
+
+    #[inline]
+    fn take(&mut self) -> (Vec<u8>, Vec<u32>) {
+        (
+            std::mem::take(&mut self.data),
+            std::mem::take(&mut self.copy_blks),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hbb_fs_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn random_data(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn new_writer(dir: &Path, files: &[(&str, usize)]) -> TransferJob {
+        let files = files
+            .iter()
+            .map(|(name, size)| FileEntry {
+                name: name.to_string(),
+                size: *size as _,
+                ..Default::default()
+            })
+            .collect();
+        TransferJob::new_write(
+            1,
+            "".to_owned(),
+            get_string(dir),
+            0,
+            false,
+            false,
+            files,
+            true,
+        )
+    }
+
+    fn new_block(file_num: i32, data: &[u8], copy_blks: Vec<u32>) -> FileTransferBlock {
+        FileTransferBlock {
+            id: 1,
+            file_num,
+            data: data.to_vec().into(),
+            copy_blks,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delta_sync() {
+        let dir = temp_dir("delta");
+        let plain = random_data(100_000, 1);
+        let old = random_data(300_000, 2);
+        let mut new = old.clone();
+        new.splice(100_000..100_100, random_data(300, 3));
+        new.extend(random_data(5_000, 4));
+        std::fs::write(dir.join("src"), &new).unwrap();
+        std::fs::write(dir.join("b"), &old).unwrap();
+
+        let mut writer = new_writer(&dir, &[("a", plain.len()), ("b", new.len())]);
+        writer.write(new_block(0, &plain, vec![])).await.unwrap();
+        writer.write(new_block(0, &[], vec![])).await.unwrap();
+        writer.set_delta_sync(&FileTransferDigest {
+            id: 1,
+            file_num: 1,
+            delta_sync: true,
+            ..Default::default()
+        });
+        let signatures = writer.new_signatures(1).await.unwrap();
+        let mut reader = DeltaReader::new(signatures);
+        let mut file = File::open(dir.join("src")).await.unwrap();
+        let mut copied = 0;
+        while let Some((data, copy_blks)) = reader.next(&mut file).await.unwrap() {
+            copied += copy_blks.len();
+            writer.write(new_block(1, &data, copy_blks)).await.unwrap();
+        }
+        writer.modify_time();
+        assert!(copied > 0);
+        assert_eq!(std::fs::read(dir.join("a")).unwrap(), plain);
+        let written = std::fs::read(dir.join("b")).unwrap();
+        assert_eq!(sha256::hash(&written), sha256::hash(&new));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let dir = temp_dir("resume");
+        let blocks = 2 * RESUME_SYNC_BLOCKS as usize + 6;
+        let data = random_data(blocks * BLOCK_SIZE + 1000, 5);
+        let digest = FileTransferDigest {
+            id: 1,
+            file_num: 0,
+            last_modified: 1,
+            file_size: data.len() as _,
+            resumable: true,
+            ..Default::default()
+        };
+
+        // interrupted after the resume state is synced
+        let mut writer = new_writer(&dir, &[("f", data.len())]);
+        assert_eq!(writer.resume_offset_blk(&digest).await, 0);
+        for chunk in data.chunks(BLOCK_SIZE).take(blocks - 3) {
+            writer.write(new_block(0, chunk, vec![])).await.unwrap();
+        }
+        drop(writer);
+        assert!(!dir.join("f").exists());
+
+        // the second segment is changed, only the first one is kept
+        let download_path = dir.join("f.download");
+        let mut partial = std::fs::read(&download_path).unwrap();
+        partial[RESUME_SYNC_BLOCKS as usize * BLOCK_SIZE + 10] ^= 1;
+        std::fs::write(&download_path, partial).unwrap();
+        let mut writer = new_writer(&dir, &[("f", data.len())]);
+        let offset_blk = writer.resume_offset_blk(&digest).await;
+        assert_eq!(offset_blk as u64, RESUME_SYNC_BLOCKS);
+
+        // another version of the source is not resumed
+        let other = FileTransferDigest {
+            last_modified: 2,
+            ..digest.clone()
+        };
+        let mut other_writer = new_writer(&dir, &[("f", data.len())]);
+        assert_eq!(other_writer.resume_offset_blk(&other).await, 0);
+        drop(other_writer);
+        for chunk in data.chunks(BLOCK_SIZE).skip(offset_blk as _) {
+            writer.write(new_block(0, chunk, vec![])).await.unwrap();
+        }
+        writer.modify_time();
+        assert_eq!(std::fs::read(dir.join("f")).unwrap(), data);
+        assert!(!dir.join("f.download").exists());
+        assert!(!dir.join("f.download.state").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
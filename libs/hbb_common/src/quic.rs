@@ -0,0 +1,551 @@
+// QUIC transport of peer sessions.
+//
+// QUIC is tried on the addresses the PunchHole/PunchHoleSent flow learns for TCP: both sides use the
+// UDP port with the same number as their TCP one, the controlled side sends a few datagrams to open its
+// gateway, then the controlling side connects, and falls back to TCP if that fails.
+// The UDP mappings are not learned from the rendezvous server, so this is not UDP hole punching,
+// it works on LAN, with public addresses and behind NATs keeping the port numbers only.
+//
+// A session has a main stream carrying everything but video, input and file transfer,
+// each of which has its own stream, so that a lost packet only stalls the stream it belongs to.
+// The sub streams are not read until the login is done on the main stream, and the display switches
+// go with the video, so that the messages the video depends on are not overtaken.
+// The TLS of QUIC is not authenticated, peers are authenticated by the handshake on the main stream
+// as with TCP, the other streams are encrypted with the keys derived from the one of the main stream.
+use crate::{
+    message_proto::{message, Message},
+    tcp::FramedStream,
+    ResultType,
+};
+use bytes::BytesMut;
+use sodiumoxide::crypto::{hash::sha256, secretbox::Key};
+use std::io;
+#[cfg(any(target_os = "android", target_os = "ios"))]
+use std::net::SocketAddr;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub use transport::{accept, connect};
+
+pub const STREAM_MAIN: u8 = 0;
+pub const STREAM_VIDEO: u8 = 1;
+pub const STREAM_INPUT: u8 = 2;
+pub const STREAM_FILE: u8 = 3;
+const SUB_STREAMS: [u8; 3] = [STREAM_VIDEO, STREAM_INPUT, STREAM_FILE];
+
+/// The streams of a QUIC session besides the main one.
+pub struct SubStreams {
+    streams: Vec<(u8, FramedStream)>,
+    ready: bool,
+}
+
+impl SubStreams {
+    fn new(streams: Vec<(u8, FramedStream)>) -> Self {
+        Self {
+            streams,
+            ready: false,
+        }
+    }
+
+    /// Start reading the streams, once the login is done on the main stream.
+    pub fn set_ready(&mut self) {
+        self.ready = true;
+    }
+
+    /// Encrypt the streams if the main stream is secured.
+    pub fn secure_with(&mut self, main: &FramedStream) {
+        let key = match main.2.as_ref() {
+            Some(encrypt) => encrypt.key().clone(),
+            None => return,
+        };
+        for (kind, stream) in self.streams.iter_mut() {
+            let mut data = key.0.to_vec();
+            data.push(*kind);
+            stream.set_key(Key(sha256::hash(&data).0));
+        }
+    }
+
+    fn get(&mut self, kind: u8) -> Option<&mut FramedStream> {
+        self.streams
+            .iter_mut()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, s)| s)
+    }
+}
+
+/// The stream `msg` goes through.
+pub fn kind_of(msg: &Message) -> u8 {
+    match &msg.union {
+        Some(message::Union::VideoFrame(_)) | Some(message::Union::PeerInfo(_)) => STREAM_VIDEO,
+        Some(message::Union::Misc(misc)) if misc.has_switch_display() => STREAM_VIDEO,
+        Some(message::Union::KeyEvent(_))
+        | Some(message::Union::MouseEvent(_))
+        | Some(message::Union::PointerDeviceEvent(_)) => STREAM_INPUT,
+        Some(message::Union::FileResponse(_)) | Some(message::Union::FileAction(_)) => STREAM_FILE,
+        _ => STREAM_MAIN,
+    }
+}
+
+/// The stream of `kind`, the main one if there are no sub streams.
+pub fn select<'a>(
+    main: &'a mut FramedStream,
+    sub: Option<&'a mut SubStreams>,
+    kind: u8,
+) -> &'a mut FramedStream {
+    match sub.and_then(|s| s.get(kind)) {
+        Some(s) => s,
+        None => main,
+    }
+}
+
+/// Send `msg` through the stream it belongs to.
+/// The main stream is used instead if that one is closed, e.g. the cli drops the sub streams.
+pub async fn send(
+    main: &mut FramedStream,
+    sub: Option<&mut SubStreams>,
+    msg: &Message,
+) -> ResultType<()> {
+    let kind = kind_of(msg);
+    if let Some(sub) = sub {
+        if let Some(i) = sub.streams.iter().position(|(k, _)| *k == kind) {
+            if sub.streams[i].1.send(msg).await.is_ok() {
+                return Ok(());
+            }
+            sub.streams.remove(i);
+        }
+    }
+    main.send(msg).await
+}
+
+/// The next message from any of the streams, from the main one only until the sub streams are ready.
+/// A closed sub stream is dropped, the session ends with the main stream only.
+pub async fn next(
+    main: &mut FramedStream,
+    sub: Option<&mut SubStreams>,
+) -> Option<Result<BytesMut, io::Error>> {
+    let sub = match sub {
+        Some(sub) if sub.ready => sub,
+        _ => return main.next().await,
+    };
+    loop {
+        if sub.streams.is_empty() {
+            return main.next().await;
+        }
+        let (res, i) = {
+            let sub_next = futures::future::select_all(
+                sub.streams.iter_mut().map(|(_, s)| Box::pin(s.next())),
+            );
+            tokio::select! {
+                res = main.next() => return res,
+                (res, i, _) = sub_next => (res, i),
+            }
+        };
+        match res {
+            Some(res) => return Some(res),
+            None => {
+                sub.streams.remove(i);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub async fn connect(
+    _local_addr: SocketAddr,
+    _peer: SocketAddr,
+    _ms_timeout: u64,
+) -> ResultType<(FramedStream, SubStreams)> {
+    crate::bail!("QUIC is not supported on this platform");
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub async fn accept(
+    _local_addr: SocketAddr,
+    _peer: SocketAddr,
+    _ms_timeout: u64,
+) -> ResultType<(FramedStream, SubStreams, SocketAddr)> {
+    crate::bail!("QUIC is not supported on this platform");
+}
+
+// The transport is not built for mobile, the sessions there are always TCP.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod transport {
+    use super::{SubStreams, STREAM_MAIN, SUB_STREAMS};
+    use crate::{bail, tcp::FramedStream, ResultType};
+    use quinn::{
+        crypto::rustls::{QuicClientConfig, QuicServerConfig},
+        ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig,
+        TokioRuntime, TransportConfig,
+    };
+    use rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::CryptoProvider,
+        pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    };
+    use std::{
+        convert::{TryFrom, TryInto},
+        io,
+        net::SocketAddr,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::Duration,
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    pub(super) const SERVER_NAME: &str = "rustdesk";
+    const ALPN: &[u8] = b"rustdesk/1";
+    const PUNCH_PACKETS: usize = 3;
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+    const MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    // One bidirectional stream, holding the connection and the endpoint to keep them open.
+    struct BiStream {
+        send: SendStream,
+        recv: RecvStream,
+        _conn: Connection,
+        _endpoint: Endpoint,
+    }
+
+    impl AsyncRead for BiStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+        }
+    }
+
+    impl AsyncWrite for BiStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+        }
+    }
+
+    #[derive(Debug)]
+    struct SkipServerVerification(Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for SkipServerVerification {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    fn transport_config() -> Arc<TransportConfig> {
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        transport.max_idle_timeout(MAX_IDLE_TIMEOUT.try_into().ok());
+        Arc::new(transport)
+    }
+
+    fn client_config() -> ResultType<ClientConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+        config.transport_config(transport_config());
+        Ok(config)
+    }
+
+    fn server_config() -> ResultType<ServerConfig> {
+        let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+        let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.cert.der().clone()], key.into())?;
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+        config.transport_config(transport_config());
+        Ok(config)
+    }
+
+    fn new_endpoint(
+        socket: std::net::UdpSocket,
+        server_config: Option<ServerConfig>,
+    ) -> ResultType<Endpoint> {
+        Ok(Endpoint::new(
+            EndpointConfig::default(),
+            server_config,
+            socket,
+            Arc::new(TokioRuntime),
+        )?)
+    }
+
+    async fn open_stream(
+        conn: &Connection,
+        endpoint: &Endpoint,
+        kind: u8,
+    ) -> ResultType<FramedStream> {
+        let (mut send, recv) = conn.open_bi().await?;
+        // the peer does not see the stream until something is written
+        send.write_all(&[kind]).await?;
+        let stream = BiStream {
+            send,
+            recv,
+            _conn: conn.clone(),
+            _endpoint: endpoint.clone(),
+        };
+        Ok(FramedStream::from(stream, endpoint.local_addr()?))
+    }
+
+    /// Connect to `peer` from the UDP port of `local_addr`.
+    pub async fn connect(
+        local_addr: SocketAddr,
+        peer: SocketAddr,
+        ms_timeout: u64,
+    ) -> ResultType<(FramedStream, SubStreams)> {
+        let socket = crate::udp::new_socket(local_addr, true, 0)?.into_udp_socket();
+        let mut endpoint = new_endpoint(socket, None)?;
+        endpoint.set_default_client_config(client_config()?);
+        let conn = crate::timeout(ms_timeout, endpoint.connect(peer, SERVER_NAME)?).await??;
+        let main = open_stream(&conn, &endpoint, STREAM_MAIN).await?;
+        let mut sub = Vec::new();
+        for kind in SUB_STREAMS {
+            sub.push((kind, open_stream(&conn, &endpoint, kind).await?));
+        }
+        Ok((main, SubStreams::new(sub)))
+    }
+
+    async fn accept_from(endpoint: &Endpoint, peer: SocketAddr) -> ResultType<Connection> {
+        while let Some(incoming) = endpoint.accept().await {
+            if incoming.remote_address().ip() != peer.ip() {
+                log::warn!("Ignore QUIC connection from {}", incoming.remote_address());
+                incoming.ignore();
+                continue;
+            }
+            return Ok(incoming.await?);
+        }
+        bail!("QUIC endpoint closed");
+    }
+
+    /// Accept the connection of `peer` on the UDP port of `local_addr`.
+    pub async fn accept(
+        local_addr: SocketAddr,
+        peer: SocketAddr,
+        ms_timeout: u64,
+    ) -> ResultType<(FramedStream, SubStreams, SocketAddr)> {
+        let socket = crate::udp::new_socket(local_addr, true, 0)?.into_udp_socket();
+        // tell my gateway the incoming packets of the peer are expected
+        for _ in 0..PUNCH_PACKETS {
+            socket.send_to(&[0u8], peer).ok();
+        }
+        let endpoint = new_endpoint(socket, Some(server_config()?))?;
+        let conn = crate::timeout(ms_timeout, accept_from(&endpoint, peer)).await??;
+        let addr = endpoint.local_addr()?;
+        let mut main = None;
+        let mut sub = Vec::new();
+        for _ in 0..=SUB_STREAMS.len() {
+            let (send, mut recv) = crate::timeout(ms_timeout, conn.accept_bi()).await??;
+            let mut kind = [0u8; 1];
+            recv.read_exact(&mut kind).await?;
+            let stream = FramedStream::from(
+                BiStream {
+                    send,
+                    recv,
+                    _conn: conn.clone(),
+                    _endpoint: endpoint.clone(),
+                },
+                addr,
+            );
+            match kind[0] {
+                STREAM_MAIN => main = Some(stream),
+                kind if SUB_STREAMS.contains(&kind) => sub.push((kind, stream)),
+                kind => bail!("Unknown QUIC stream {}", kind),
+            }
+        }
+        match main {
+            Some(main) => Ok((main, SubStreams::new(sub), conn.remote_address())),
+            None => bail!("No main QUIC stream"),
+        }
+    }
+}
+
+#[cfg(all(test, not(any(target_os = "android", target_os = "ios"))))]
+mod tests {
+    use super::*;
+    use crate::message_proto::{
+        FileAction, Misc, MouseEvent, SwitchDisplay, TestDelay, VideoFrame,
+    };
+    use protobuf::Message as _;
+    use sodiumoxide::crypto::secretbox;
+    use std::net::SocketAddr;
+
+    fn free_addr() -> SocketAddr {
+        std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    async fn new_session() -> ((FramedStream, SubStreams), (FramedStream, SubStreams)) {
+        let server_addr = free_addr();
+        let client_addr = free_addr();
+        let (client, server) = tokio::join!(
+            async {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                connect(client_addr, server_addr, 3_000).await
+            },
+            accept(server_addr, client_addr, 3_000)
+        );
+        let (server_main, server_sub, addr) = server.unwrap();
+        assert_eq!(addr.port(), client_addr.port());
+        (client.unwrap(), (server_main, server_sub))
+    }
+
+    fn new_messages() -> Vec<Message> {
+        let mut main = Message::new();
+        main.set_test_delay(TestDelay {
+            time: 1,
+            ..Default::default()
+        });
+        let mut video = Message::new();
+        video.set_video_frame(VideoFrame::new());
+        let mut input = Message::new();
+        input.set_mouse_event(MouseEvent {
+            x: 1,
+            ..Default::default()
+        });
+        let mut file = Message::new();
+        file.set_file_action(FileAction::new());
+        vec![main, video, input, file]
+    }
+
+    async fn recv(stream: &mut FramedStream) -> Message {
+        let bytes = stream.next_timeout(3_000).await.unwrap().unwrap();
+        Message::parse_from_bytes(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streams() {
+        let kinds = [STREAM_MAIN, STREAM_VIDEO, STREAM_INPUT, STREAM_FILE];
+        let msgs = new_messages();
+        for (msg, kind) in msgs.iter().zip(kinds) {
+            assert_eq!(kind_of(msg), kind);
+        }
+        let ((mut cm, mut cs), (mut sm, mut ss)) = new_session().await;
+        for (msg, kind) in msgs.iter().zip(kinds) {
+            send(&mut cm, Some(&mut cs), msg).await.unwrap();
+            assert_eq!(&recv(select(&mut sm, Some(&mut ss), kind)).await, msg);
+        }
+
+        let key = secretbox::gen_key();
+        cm.set_key(key.clone());
+        sm.set_key(key);
+        cs.secure_with(&cm);
+        ss.secure_with(&sm);
+        for kind in SUB_STREAMS {
+            assert!(cs.get(kind).unwrap().is_secured());
+            assert!(ss.get(kind).unwrap().is_secured());
+        }
+        cs.set_ready();
+        ss.set_ready();
+        for msg in msgs.iter() {
+            send(&mut cm, Some(&mut cs), msg).await.unwrap();
+            let bytes = next(&mut sm, Some(&mut ss)).await.unwrap().unwrap();
+            assert_eq!(&Message::parse_from_bytes(&bytes).unwrap(), msg);
+            send(&mut sm, Some(&mut ss), msg).await.unwrap();
+            let bytes = next(&mut cm, Some(&mut cs)).await.unwrap().unwrap();
+            assert_eq!(&Message::parse_from_bytes(&bytes).unwrap(), msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let mut switch_display = Message::new();
+        let mut misc = Misc::new();
+        misc.set_switch_display(SwitchDisplay::new());
+        switch_display.set_misc(misc);
+        assert_eq!(kind_of(&switch_display), STREAM_VIDEO);
+
+        let msgs = new_messages();
+        let ((mut cm, mut cs), (mut sm, mut ss)) = new_session().await;
+        // the video is sent first, but not read before the login
+        send(&mut sm, Some(&mut ss), &msgs[1]).await.unwrap();
+        send(&mut sm, Some(&mut ss), &msgs[0]).await.unwrap();
+        let bytes = next(&mut cm, Some(&mut cs)).await.unwrap().unwrap();
+        assert_eq!(Message::parse_from_bytes(&bytes).unwrap(), msgs[0]);
+        cs.set_ready();
+        let bytes = next(&mut cm, Some(&mut cs)).await.unwrap().unwrap();
+        assert_eq!(Message::parse_from_bytes(&bytes).unwrap(), msgs[1]);
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let msgs = new_messages();
+        let ((mut cm, mut cs), (mut sm, ss)) = new_session().await;
+        cs.set_ready();
+        // e.g. the cli side which only keeps the main stream
+        drop(ss);
+        // the ends of the sub streams are read along with the messages of the main stream
+        loop {
+            sm.send(&msgs[0]).await.unwrap();
+            let bytes = next(&mut cm, Some(&mut cs)).await.unwrap().unwrap();
+            assert_eq!(Message::parse_from_bytes(&bytes).unwrap(), msgs[0]);
+            if cs.streams.is_empty() {
+                break;
+            }
+        }
+        for msg in msgs.iter().skip(1) {
+            send(&mut cm, Some(&mut cs), msg).await.unwrap();
+            assert_eq!(&recv(&mut sm).await, msg);
+        }
+    }
+}
@@ -295,6 +295,11 @@ impl Encrypt {
         Self(key, 0, 0)
     }
 
+    #[inline]
+    pub(crate) fn key(&self) -> &Key {
+        &self.0
+    }
+
     pub fn dec(&mut self, bytes: &mut BytesMut) -> Result<(), Error> {
         if bytes.len() <= 1 {
             return Ok(());
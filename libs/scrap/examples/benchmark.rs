@@ -8,7 +8,7 @@ use scrap::{
     codec::{EncoderApi, EncoderCfg, Quality as Q},
     Capturer, Display, TraitCapturer, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig,
     VpxVideoCodecId::{self, *},
-    STRIDE_ALIGN,
+    YuvBuffer, STRIDE_ALIGN,
 };
 use std::{
     io::Write,
@@ -113,7 +113,7 @@ fn test_vpx(
     let mut vpxs = vec![];
     let start = Instant::now();
     let mut size = 0;
-    let mut yuv = Vec::new();
+    let mut yuv = YuvBuffer::default();
     let mut mid_data = Vec::new();
     let mut counter = 0;
     let mut time_sum = Duration::ZERO;
@@ -191,7 +191,7 @@ fn test_av1(
     let start = Instant::now();
     let mut size = 0;
     let mut av1s: Vec<Vec<u8>> = vec![];
-    let mut yuv = Vec::new();
+    let mut yuv = YuvBuffer::default();
     let mut mid_data = Vec::new();
     let mut counter = 0;
     let mut time_sum = Duration::ZERO;
@@ -283,7 +283,7 @@ mod hw {
         .unwrap();
         let mut size = 0;
 
-        let mut yuv = Vec::new();
+        let mut yuv = YuvBuffer::default();
         let mut mid_data = Vec::new();
         let mut counter = 0;
         let mut time_sum = Duration::ZERO;
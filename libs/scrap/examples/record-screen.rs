@@ -18,7 +18,7 @@ use webm::mux;
 use webm::mux::Track;
 
 use scrap::vpxcodec as vpx_encode;
-use scrap::{Capturer, Display, TraitCapturer, STRIDE_ALIGN};
+use scrap::{Capturer, Display, TraitCapturer, YuvBuffer, STRIDE_ALIGN};
 
 const USAGE: &'static str = "
 Simple WebM screen capture.
@@ -140,7 +140,7 @@ fn main() -> io::Result<()> {
 
     // Capturer object is expensive, avoiding to create it frequently.
     let mut c = Capturer::new(d).unwrap();
-    let mut yuv = Vec::new();
+    let mut yuv = YuvBuffer::default();
     let mut mid_data = Vec::new();
     while !stop.load(Ordering::Acquire) {
         let now = Instant::now();
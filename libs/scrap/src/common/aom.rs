@@ -55,6 +55,7 @@ pub struct AomEncoder {
     height: usize,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    keyframe_requested: bool,
 }
 
 // https://webrtc.googlesource.com/src/+/refs/heads/main/modules/video_coding/codecs/av1/libaom_av1_encoder.cc
@@ -243,6 +244,7 @@ impl EncoderApi for AomEncoder {
                     height: config.height as _,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    keyframe_requested: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -310,6 +312,10 @@ impl EncoderApi for AomEncoder {
     }
 
     fn disable(&self) {}
+
+    fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
 }
 
 impl AomEncoder {
@@ -334,12 +340,17 @@ impl AomEncoder {
             data.as_ptr() as _,
         ));
 
+        let flags = if std::mem::take(&mut self.keyframe_requested) {
+            AOM_EFLAG_FORCE_KF
+        } else {
+            0
+        };
         call_aom!(aom_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags as _,
         ));
 
         Ok(EncodeFrames {
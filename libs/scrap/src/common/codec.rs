@@ -15,7 +15,7 @@ use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
-    CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb,
+    CodecFormat, DamageRect, EncodeInput, EncodeYuvFormat, ImageRgb,
 };
 
 use hbb_common::{
@@ -76,6 +76,14 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    // Only the damaged regions of the next frame changed, None if unknown.
+    fn set_damage(&mut self, _damage: Option<&[DamageRect]>) -> ResultType<()> {
+        Ok(())
+    }
+
+    // The next frame is encoded as a key frame, for the users who start decoding with it.
+    fn request_keyframe(&mut self) {}
 }
 
 pub struct Encoder {
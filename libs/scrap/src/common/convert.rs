@@ -8,16 +8,59 @@ include!(concat!(env!("OUT_DIR"), "/yuv_ffi.rs"));
 
 #[cfg(not(target_os = "ios"))]
 use crate::PixelBuffer;
-use crate::{generate_call_macro, EncodeYuvFormat, TraitPixelBuffer};
+use crate::{generate_call_macro, DamageRect, EncodeYuvFormat, TraitPixelBuffer};
 use hbb_common::{bail, log, ResultType};
 
 generate_call_macro!(call_yuv, false);
 
+/// The yuv of the last converted frame, with the geometry it was converted with, so that only
+/// the damage of the next frame is converted if nothing else changed.
+#[derive(Debug, Default)]
+pub struct YuvBuffer {
+    data: Vec<u8>,
+    geometry: Option<ConvertGeometry>,
+}
+
+impl std::ops::Deref for YuvBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// A crop moved by the capturer is reported as full damage by it, see `PipeWireRecorder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ConvertGeometry {
+    src_pixfmt: crate::Pixfmt,
+    src_width: usize,
+    src_height: usize,
+    src_stride: Vec<usize>,
+    dst_fmt: EncodeYuvFormat,
+}
+
+// The regions to convert, only the damage if `dst` holds the last frame with the same geometry.
+fn convert_rects(
+    damage: Option<&[DamageRect]>,
+    partial: bool,
+    width: usize,
+    height: usize,
+) -> Vec<DamageRect> {
+    match damage {
+        // even for the chroma subsampling
+        Some(damage) if partial => damage
+            .iter()
+            .filter_map(|r| r.align(2).clip(width, height))
+            .collect(),
+        _ => vec![DamageRect::new(0, 0, width, height)],
+    }
+}
+
 #[cfg(not(target_os = "ios"))]
 pub fn convert_to_yuv(
     captured: &PixelBuffer,
     dst_fmt: EncodeYuvFormat,
-    dst: &mut Vec<u8>,
+    dst: &mut YuvBuffer,
     mid_data: &mut Vec<u8>,
 ) -> ResultType<()> {
     let src = captured.data();
@@ -25,6 +68,7 @@ pub fn convert_to_yuv(
     let src_pixfmt = captured.pixfmt();
     let src_width = captured.width();
     let src_height = captured.height();
+    let damage = captured.damage();
     if src_width > dst_fmt.w || src_height > dst_fmt.h {
         bail!(
             "src rect > dst rect: ({src_width}, {src_height}) > ({},{})",
@@ -53,6 +97,17 @@ pub fn convert_to_yuv(
             );
         }
     }
+    let geometry = ConvertGeometry {
+        src_pixfmt,
+        src_width,
+        src_height,
+        src_stride: src_stride.clone(),
+        dst_fmt: dst_fmt.clone(),
+    };
+    // cleared until the conversion succeeds, `dst` may be partially converted on errors
+    let same_geometry = dst.geometry.take().as_ref() == Some(&geometry);
+    let dst_geometry = &mut dst.geometry;
+    let dst = &mut dst.data;
     let align = |x: usize| (x + 63) / 64 * 64;
     let unsupported = format!(
         "unsupported pixfmt conversion: {src_pixfmt:?} -> {:?}",
@@ -65,40 +120,44 @@ pub fn convert_to_yuv(
         | (crate::Pixfmt::RGB565LE, crate::Pixfmt::I420) => {
             let dst_stride_y = dst_fmt.stride[0];
             let dst_stride_uv = dst_fmt.stride[1];
-            dst.resize(dst_fmt.h * dst_stride_y * 2, 0); // waste some memory to ensure memory safety
-            let dst_y = dst.as_mut_ptr();
-            let dst_u = dst[dst_fmt.u..].as_mut_ptr();
-            let dst_v = dst[dst_fmt.v..].as_mut_ptr();
+            let len = dst_fmt.h * dst_stride_y * 2; // waste some memory to ensure memory safety
+            let partial = same_geometry && dst.len() == len;
+            let rects = convert_rects(damage, partial, src_width, src_height);
+            dst.resize(len, 0);
             let f = match src_pixfmt {
                 crate::Pixfmt::BGRA => ARGBToI420,
                 crate::Pixfmt::RGBA => ABGRToI420,
                 crate::Pixfmt::RGB565LE => RGB565ToI420,
                 _ => bail!(unsupported),
             };
-            call_yuv!(f(
-                src.as_ptr(),
-                src_stride[0] as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_u,
-                dst_stride_uv as _,
-                dst_v,
-                dst_stride_uv as _,
-                src_width as _,
-                src_height as _,
-            ));
+            let bpp = src_pixfmt.bytes_per_pixel();
+            for r in rects {
+                let uv = r.y / 2 * dst_stride_uv + r.x / 2;
+                call_yuv!(f(
+                    src[r.y * src_stride[0] + r.x * bpp..].as_ptr(),
+                    src_stride[0] as _,
+                    dst[r.y * dst_stride_y + r.x..].as_mut_ptr(),
+                    dst_stride_y as _,
+                    dst[dst_fmt.u + uv..].as_mut_ptr(),
+                    dst_stride_uv as _,
+                    dst[dst_fmt.v + uv..].as_mut_ptr(),
+                    dst_stride_uv as _,
+                    r.w as _,
+                    r.h as _,
+                ));
+            }
         }
         (crate::Pixfmt::BGRA, crate::Pixfmt::NV12)
         | (crate::Pixfmt::RGBA, crate::Pixfmt::NV12)
         | (crate::Pixfmt::RGB565LE, crate::Pixfmt::NV12) => {
             let dst_stride_y = dst_fmt.stride[0];
             let dst_stride_uv = dst_fmt.stride[1];
-            dst.resize(
-                align(dst_fmt.h) * (align(dst_stride_y) + align(dst_stride_uv / 2)),
-                0,
-            );
-            let dst_y = dst.as_mut_ptr();
-            let dst_uv = dst[dst_fmt.u..].as_mut_ptr();
+            let len = align(dst_fmt.h) * (align(dst_stride_y) + align(dst_stride_uv / 2));
+            // rgb565 is converted to the whole mid data first
+            let partial =
+                same_geometry && dst.len() == len && src_pixfmt != crate::Pixfmt::RGB565LE;
+            let rects = convert_rects(damage, partial, src_width, src_height);
+            dst.resize(len, 0);
             let (input, input_stride) = match src_pixfmt {
                 crate::Pixfmt::BGRA => (src.as_ptr(), src_stride[0]),
                 crate::Pixfmt::RGBA => (src.as_ptr(), src_stride[0]),
@@ -123,16 +182,18 @@ pub fn convert_to_yuv(
                 crate::Pixfmt::RGB565LE => ARGBToNV12,
                 _ => bail!(unsupported),
             };
-            call_yuv!(f(
-                input,
-                input_stride as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_uv,
-                dst_stride_uv as _,
-                src_width as _,
-                src_height as _,
-            ));
+            for r in rects {
+                call_yuv!(f(
+                    input.add(r.y * input_stride + r.x * 4),
+                    input_stride as _,
+                    dst[r.y * dst_stride_y + r.x..].as_mut_ptr(),
+                    dst_stride_y as _,
+                    dst[dst_fmt.u + r.y / 2 * dst_stride_uv + r.x..].as_mut_ptr(),
+                    dst_stride_uv as _,
+                    r.w as _,
+                    r.h as _,
+                ));
+            }
         }
         (crate::Pixfmt::BGRA, crate::Pixfmt::I444)
         | (crate::Pixfmt::RGBA, crate::Pixfmt::I444)
@@ -140,14 +201,12 @@ pub fn convert_to_yuv(
             let dst_stride_y = dst_fmt.stride[0];
             let dst_stride_u = dst_fmt.stride[1];
             let dst_stride_v = dst_fmt.stride[2];
-            dst.resize(
-                align(dst_fmt.h)
-                    * (align(dst_stride_y) + align(dst_stride_u) + align(dst_stride_v)),
-                0,
-            );
-            let dst_y = dst.as_mut_ptr();
-            let dst_u = dst[dst_fmt.u..].as_mut_ptr();
-            let dst_v = dst[dst_fmt.v..].as_mut_ptr();
+            let len = align(dst_fmt.h)
+                * (align(dst_stride_y) + align(dst_stride_u) + align(dst_stride_v));
+            // the others are converted to the whole mid data first
+            let partial = same_geometry && dst.len() == len && src_pixfmt == crate::Pixfmt::BGRA;
+            let rects = convert_rects(damage, partial, src_width, src_height);
+            dst.resize(len, 0);
             let (input, input_stride) = match src_pixfmt {
                 crate::Pixfmt::BGRA => (src.as_ptr(), src_stride[0]),
                 crate::Pixfmt::RGBA => {
@@ -178,22 +237,25 @@ pub fn convert_to_yuv(
                 _ => bail!(unsupported),
             };
 
-            call_yuv!(ARGBToI444(
-                input,
-                input_stride as _,
-                dst_y,
-                dst_stride_y as _,
-                dst_u,
-                dst_stride_u as _,
-                dst_v,
-                dst_stride_v as _,
-                src_width as _,
-                src_height as _,
-            ));
+            for r in rects {
+                call_yuv!(ARGBToI444(
+                    input.add(r.y * input_stride + r.x * 4),
+                    input_stride as _,
+                    dst[r.y * dst_stride_y + r.x..].as_mut_ptr(),
+                    dst_stride_y as _,
+                    dst[dst_fmt.u + r.y * dst_stride_u + r.x..].as_mut_ptr(),
+                    dst_stride_u as _,
+                    dst[dst_fmt.v + r.y * dst_stride_v + r.x..].as_mut_ptr(),
+                    dst_stride_v as _,
+                    r.w as _,
+                    r.h as _,
+                ));
+            }
         }
         _ => {
             bail!(unsupported);
         }
     }
+    *dst_geometry = Some(geometry);
     Ok(())
 }
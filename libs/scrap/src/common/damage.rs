@@ -0,0 +1,205 @@
+// Damaged regions of the captured frames, so that the unchanged regions are neither converted nor encoded.
+use std::io;
+
+// Also a multiple of the macroblock sizes and the chroma subsampling.
+pub const TILE_SIZE: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DamageRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl DamageRect {
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> Self {
+        Self { x, y, w, h }
+    }
+
+    #[inline]
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    // The part inside (0, 0, width, height).
+    pub fn clip(&self, width: usize, height: usize) -> Option<Self> {
+        if self.x >= width || self.y >= height || self.w == 0 || self.h == 0 {
+            return None;
+        }
+        Some(Self {
+            x: self.x,
+            y: self.y,
+            w: self.w.min(width - self.x),
+            h: self.h.min(height - self.y),
+        })
+    }
+
+    // The part inside `area`, relative to the origin of `area`.
+    pub fn crop(&self, area: &DamageRect) -> Option<Self> {
+        if !self.intersects(area) {
+            return None;
+        }
+        let x = self.x.max(area.x);
+        let y = self.y.max(area.y);
+        Some(Self {
+            x: x - area.x,
+            y: y - area.y,
+            w: (self.x + self.w).min(area.x + area.w) - x,
+            h: (self.y + self.h).min(area.y + area.h) - y,
+        })
+    }
+
+    // Grow to the multiples of `n`.
+    pub fn align(&self, n: usize) -> Self {
+        let x = self.x / n * n;
+        let y = self.y / n * n;
+        Self {
+            x,
+            y,
+            w: (self.x + self.w - x + n - 1) / n * n,
+            h: (self.y + self.h - y + n - 1) / n * n,
+        }
+    }
+}
+
+/// Keeps the last frame and finds the changed tiles of the new one.
+#[derive(Default)]
+pub struct DamageTracker {
+    saved: Vec<u8>,
+    rects: Vec<DamageRect>,
+}
+
+impl DamageTracker {
+    /// Compare `data` with the last frame, `WouldBlock` if nothing changed.
+    /// `hint` is the damage reported by the system, only the tiles touching it are compared.
+    pub fn update(
+        &mut self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        bytes_per_pixel: usize,
+        hint: Option<&[DamageRect]>,
+    ) -> io::Result<()> {
+        self.rects.clear();
+        if self.saved.len() != data.len()
+            || data.len() < stride * height
+            || stride < width * bytes_per_pixel
+        {
+            self.saved.resize(data.len(), 0);
+            self.saved.copy_from_slice(data);
+            self.rects.push(DamageRect::new(0, 0, width, height));
+            return Ok(());
+        }
+        for y in (0..height).step_by(TILE_SIZE) {
+            let h = TILE_SIZE.min(height - y);
+            // horizontally adjacent damaged tiles are merged
+            let mut run: Option<DamageRect> = None;
+            for x in (0..width).step_by(TILE_SIZE) {
+                let tile = DamageRect::new(x, y, TILE_SIZE.min(width - x), h);
+                let check = hint.map_or(true, |hint| hint.iter().any(|r| r.intersects(&tile)));
+                if check && self.update_tile(data, &tile, stride, bytes_per_pixel) {
+                    match run.as_mut() {
+                        Some(r) => r.w += tile.w,
+                        None => run = Some(tile),
+                    }
+                } else if let Some(r) = run.take() {
+                    self.rects.push(r);
+                }
+            }
+            if let Some(r) = run.take() {
+                self.rects.push(r);
+            }
+        }
+        if self.rects.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(())
+    }
+
+    // Whether the tile changed, the saved frame is updated if so.
+    fn update_tile(
+        &mut self,
+        data: &[u8],
+        tile: &DamageRect,
+        stride: usize,
+        bytes_per_pixel: usize,
+    ) -> bool {
+        let mut changed = false;
+        for y in tile.y..tile.y + tile.h {
+            let start = y * stride + tile.x * bytes_per_pixel;
+            let end = start + tile.w * bytes_per_pixel;
+            if changed || data[start..end] != self.saved[start..end] {
+                changed = true;
+                self.saved[start..end].copy_from_slice(&data[start..end]);
+            }
+        }
+        changed
+    }
+
+    #[inline]
+    pub fn has_frame(&self) -> bool {
+        !self.saved.is_empty()
+    }
+
+    #[inline]
+    pub fn rects(&self) -> &[DamageRect] {
+        &self.rects
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_damage_tracker() {
+        let (w, h, bpp) = (200, 100, 4);
+        let stride = w * bpp;
+        let mut frame = vec![0u8; stride * h];
+        let mut tracker = DamageTracker::default();
+        assert!(tracker.update(&frame, w, h, stride, bpp, None).is_ok());
+        assert_eq!(tracker.rects(), &[DamageRect::new(0, 0, w, h)]);
+        assert!(tracker.update(&frame, w, h, stride, bpp, None).is_err());
+
+        // two adjacent tiles and the last partial one
+        frame[70 * stride + 10 * bpp] = 1;
+        frame[70 * stride + 100 * bpp] = 1;
+        frame[10 * stride + 199 * bpp] = 1;
+        assert!(tracker.update(&frame, w, h, stride, bpp, None).is_ok());
+        assert_eq!(
+            tracker.rects(),
+            &[
+                DamageRect::new(192, 0, 8, 64),
+                DamageRect::new(0, 64, 128, 36)
+            ]
+        );
+        assert!(tracker.update(&frame, w, h, stride, bpp, None).is_err());
+
+        // changes outside the hint are not found
+        frame[0] = 1;
+        let hint = [DamageRect::new(100, 0, 10, 10)];
+        assert!(tracker
+            .update(&frame, w, h, stride, bpp, Some(&hint))
+            .is_err());
+        assert!(tracker.update(&frame, w, h, stride, bpp, None).is_ok());
+        assert_eq!(tracker.rects(), &[DamageRect::new(0, 0, 64, 64)]);
+    }
+
+    #[test]
+    fn test_rect() {
+        let r = DamageRect::new(3, 5, 4, 4);
+        assert_eq!(r.align(2), DamageRect::new(2, 4, 6, 6));
+        assert_eq!(r.clip(5, 100), Some(DamageRect::new(3, 5, 2, 4)));
+        assert_eq!(r.clip(3, 100), None);
+        assert!(r.intersects(&DamageRect::new(6, 8, 1, 1)));
+        assert!(!r.intersects(&DamageRect::new(7, 8, 1, 1)));
+        let area = DamageRect::new(5, 0, 10, 7);
+        assert_eq!(r.crop(&area), Some(DamageRect::new(0, 5, 2, 2)));
+        assert_eq!(r.crop(&DamageRect::new(7, 0, 10, 10)), None);
+    }
+}
@@ -37,6 +37,7 @@ cfg_if! {
 
 pub mod codec;
 pub mod convert;
+pub mod damage;
 #[cfg(feature = "hwcodec")]
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
@@ -45,6 +46,7 @@ pub mod vpxcodec;
 #[cfg(feature = "vram")]
 pub mod vram;
 pub use self::convert::*;
+pub use self::damage::DamageRect;
 pub const STRIDE_ALIGN: usize = 64; // commonly used in libvpx vpx_img_alloc caller
 pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 
@@ -151,6 +153,11 @@ pub trait TraitPixelBuffer {
     fn stride(&self) -> Vec<usize>;
 
     fn pixfmt(&self) -> Pixfmt;
+
+    // The changed regions since the last frame of the capturer, None if unknown.
+    fn damage(&self) -> Option<&[DamageRect]> {
+        None
+    }
 }
 
 #[cfg(not(any(target_os = "ios")))]
@@ -168,10 +175,18 @@ impl Frame<'_> {
         }
     }
 
+    pub fn damage(&self) -> Option<&[DamageRect]> {
+        match self {
+            Frame::PixelBuffer(pixelbuffer) => pixelbuffer.damage(),
+            Frame::Texture(_) => None,
+        }
+    }
+
+    // `yuv` should be the one converted from the last frame, only the damage is updated if so.
     pub fn to<'a>(
         &'a self,
         yuvfmt: EncodeYuvFormat,
-        yuv: &'a mut Vec<u8>,
+        yuv: &'a mut YuvBuffer,
         mid_data: &mut Vec<u8>,
     ) -> ResultType<EncodeInput> {
         match self {
@@ -230,7 +245,7 @@ impl Pixfmt {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EncodeYuvFormat {
     pub pixfmt: Pixfmt,
     pub w: usize,
@@ -3,7 +3,9 @@ use crate::CodecFormat;
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono, log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{
+        message, misc, video_frame, AudioFormat, AudioFrame, EncodedVideoFrame, Message,
+    },
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
@@ -16,9 +18,11 @@ use std::{
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+// Opus always decodes at 48k, the original sample rate is only kept in the OpusHead.
+const OPUS_SAMPLE_RATE: i32 = 48000;
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -29,6 +33,7 @@ pub struct RecorderContext {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    pub audio_format: Option<AudioFormat>,
     pub tx: Option<Sender<RecordState>>,
 }
 
@@ -67,6 +72,9 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    fn write_audio(&mut self, _frame: &AudioFrame, _pts: i64) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -81,6 +89,8 @@ pub struct Recorder {
     pub inner: Box<dyn RecorderApi>,
     ctx: RecorderContext,
     pts: Option<i64>,
+    // (time, pts) of the first video frame, audio pts are derived from it.
+    audio_clock: Option<(Instant, i64)>,
 }
 
 impl Deref for Recorder {
@@ -105,12 +115,14 @@ impl Recorder {
                 inner: Box::new(WebmRecorder::new(ctx.clone())?),
                 ctx,
                 pts: None,
+                audio_clock: None,
             },
             #[cfg(feature = "hwcodec")]
             _ => Recorder {
                 inner: Box::new(HwRecorder::new(ctx.clone())?),
                 ctx,
                 pts: None,
+                audio_clock: None,
             },
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
@@ -132,15 +144,53 @@ impl Recorder {
         };
         self.ctx = ctx;
         self.pts = None;
+        self.audio_clock = None;
         self.send_state(RecordState::NewFile(self.ctx.filename.clone()));
         Ok(())
     }
 
     pub fn write_message(&mut self, msg: &Message) {
-        if let Some(message::Union::VideoFrame(vf)) = &msg.union {
-            if let Some(frame) = &vf.union {
-                self.write_frame(frame).ok();
+        match &msg.union {
+            Some(message::Union::VideoFrame(vf)) => {
+                if let Some(frame) = &vf.union {
+                    self.write_frame(frame).ok();
+                }
             }
+            Some(message::Union::AudioFrame(af)) => {
+                self.write_audio(af);
+            }
+            Some(message::Union::Misc(misc)) => {
+                if let Some(misc::Union::AudioFormat(f)) = &misc.union {
+                    self.set_audio_format(f).ok();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_audio_format(&mut self, format: &AudioFormat) -> ResultType<()> {
+        if self.ctx.audio_format.as_ref() == Some(format) {
+            return Ok(());
+        }
+        // The audio track must be added before the first frame is written, start a new file.
+        log::info!(
+            "audio format {:?} -> {:?}, change record filename",
+            self.ctx.audio_format,
+            format
+        );
+        self.change(RecorderContext {
+            audio_format: Some(format.clone()),
+            ..self.ctx.clone()
+        })
+    }
+
+    pub fn write_audio(&mut self, frame: &AudioFrame) {
+        // Audio frames have no pts, use the elapsed time since the first video frame.
+        // Never go behind the last video frame, the muxer rejects frames older than the cluster.
+        if let Some((start, video_pts)) = self.audio_clock {
+            let pts = video_pts + start.elapsed().as_millis() as i64;
+            let pts = pts.max(self.pts.unwrap_or_default());
+            self.inner.write_audio(frame, pts);
         }
     }
 
@@ -222,6 +272,9 @@ impl Recorder {
             log::info!("pts {:?} -> {}, change record filename", old_pts, pts);
             self.change(self.ctx.clone())?;
         }
+        if self.audio_clock.is_none() {
+            self.audio_clock = Some((Instant::now(), pts));
+        }
         Ok(())
     }
 
@@ -232,6 +285,7 @@ impl Recorder {
 
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<AudioTrack>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
@@ -274,8 +328,22 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        let mut at = None;
+        if let Some(format) = &ctx.audio_format {
+            let track = webm.add_audio_track(
+                OPUS_SAMPLE_RATE,
+                format.channels as _,
+                None,
+                mux::AudioCodecId::Opus,
+            );
+            if !webm.set_codec_private(track.track_number(), &opus_head(format)) {
+                bail!("Failed to set audio codec private");
+            }
+            at = Some(track);
+        }
         Ok(WebmRecorder {
             vt,
+            at,
             webm: Some(webm),
             ctx,
             key: false,
@@ -300,6 +368,29 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, frame: &AudioFrame, pts: i64) -> bool {
+        // Drop audio until the first key frame, the same as video.
+        if !self.key {
+            return false;
+        }
+        match self.at.as_mut() {
+            Some(at) => at.add_frame(&frame.data, pts as u64 * 1_000_000, true),
+            None => false,
+        }
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7845#section-5.1
+fn opus_head(format: &AudioFormat) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(format.channels as u8);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&format.sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
 }
 
 impl Drop for WebmRecorder {
@@ -323,6 +414,9 @@ struct HwRecorder {
     start: Instant,
 }
 
+// The hardware muxer only has a video stream. While audio is recorded, the video service records
+// the frames of its own VP9 encoder with the WebM recorder, the default `write_audio` only drops
+// the frames that arrive before the switch.
 #[cfg(feature = "hwcodec")]
 impl RecorderApi for HwRecorder {
     fn new(ctx: RecorderContext) -> ResultType<Self> {
@@ -371,3 +465,24 @@ impl Drop for HwRecorder {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_opus_head() {
+        let head = opus_head(&AudioFormat {
+            sample_rate: 44100,
+            channels: 2,
+            ..Default::default()
+        });
+        assert_eq!(head.len(), 19);
+        assert_eq!(&head[..8], b"OpusHead");
+        assert_eq!(head[8], 1);
+        assert_eq!(head[9], 2);
+        assert_eq!(&head[10..12], &[0, 0]);
+        assert_eq!(&head[12..16], &44100u32.to_le_bytes());
+        assert_eq!(&head[16..], &[0, 0, 0]);
+    }
+}
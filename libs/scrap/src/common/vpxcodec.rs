@@ -8,7 +8,7 @@ use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, V
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi, Quality};
-use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
+use crate::{DamageRect, EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
 use crate::{generate_call_macro, generate_call_ptr_macro, Error, Result};
@@ -41,6 +41,11 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    active_map: Vec<u8>,
+    active_map_enabled: bool,
+    // the last frame is not encoded, so the damage is not enough for the next one
+    dropped: bool,
+    keyframe_requested: bool,
 }
 
 pub struct VpxDecoder {
@@ -177,6 +182,10 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    active_map: Vec::new(),
+                    active_map_enabled: false,
+                    dropped: false,
+                    keyframe_requested: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -185,6 +194,7 @@ impl EncoderApi for VpxEncoder {
 
     fn encode_to_message(&mut self, input: EncodeInput, ms: i64) -> ResultType<VideoFrame> {
         let mut frames = Vec::new();
+        self.dropped = true;
         for ref frame in self
             .encode(ms, input.yuv()?, STRIDE_ALIGN)
             .with_context(|| "Failed to encode")?
@@ -197,6 +207,7 @@ impl EncoderApi for VpxEncoder {
 
         // to-do: flush periodically, e.g. 1 second
         if frames.len() > 0 {
+            self.dropped = false;
             Ok(VpxEncoder::create_video_frame(self.id, frames))
         } else {
             Err(anyhow!("no valid frame"))
@@ -248,6 +259,49 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    // The macroblocks outside the damage are skipped, libvpx ignores the active map on key frames.
+    fn set_damage(&mut self, damage: Option<&[DamageRect]>) -> ResultType<()> {
+        let damage = if self.dropped { None } else { damage };
+        let cols = (self.width + 15) / 16;
+        let rows = (self.height + 15) / 16;
+        let mut map = vpx_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: rows as _,
+            cols: cols as _,
+        };
+        match damage {
+            Some(damage) => {
+                self.active_map.clear();
+                self.active_map.resize(rows * cols, 0);
+                for r in damage {
+                    for row in r.y / 16..((r.y + r.h + 15) / 16).min(rows) {
+                        for col in r.x / 16..((r.x + r.w + 15) / 16).min(cols) {
+                            self.active_map[row * cols + col] = 1;
+                        }
+                    }
+                }
+                map.active_map = self.active_map.as_mut_ptr();
+            }
+            None => {
+                if !self.active_map_enabled {
+                    return Ok(());
+                }
+                // null map means all active
+            }
+        }
+        self.active_map_enabled = damage.is_some();
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_ACTIVEMAP as _,
+            &mut map as *mut vpx_active_map_t
+        ));
+        Ok(())
+    }
 }
 
 impl VpxEncoder {
@@ -272,12 +326,17 @@ impl VpxEncoder {
             data.as_ptr() as _,
         ));
 
+        let flags = if std::mem::take(&mut self.keyframe_requested) {
+            VPX_EFLAG_FORCE_KF
+        } else {
+            0
+        };
         call_vpx!(vpx_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags as _,
             VPX_DL_REALTIME as _,
         ));
 
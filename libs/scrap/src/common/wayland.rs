@@ -42,19 +42,17 @@ impl Capturer {
 
 impl TraitCapturer for Capturer {
     fn frame<'a>(&'a mut self, timeout: Duration) -> io::Result<Frame<'a>> {
-        match self.1.capture(timeout.as_millis() as _).map_err(map_err)? {
-            PixelProvider::BGR0(w, h, x) => Ok(Frame::PixelBuffer(PixelBuffer::new(
-                x,
-                crate::Pixfmt::BGRA,
-                w,
-                h,
-            ))),
-            PixelProvider::RGB0(w, h, x) => Ok(Frame::PixelBuffer(PixelBuffer::new(
-                x,
-                crate::Pixfmt::RGBA,
-                w,
-                h,
-            ))),
+        let (pixel_provider, damage) = self
+            .1
+            .capture_damage(timeout.as_millis() as _)
+            .map_err(map_err)?;
+        match pixel_provider {
+            PixelProvider::BGR0(w, h, x) => Ok(Frame::PixelBuffer(
+                PixelBuffer::new(x, crate::Pixfmt::BGRA, w, h).with_damage(damage),
+            )),
+            PixelProvider::RGB0(w, h, x) => Ok(Frame::PixelBuffer(
+                PixelBuffer::new(x, crate::Pixfmt::RGBA, w, h).with_damage(damage),
+            )),
             PixelProvider::NONE => Err(std::io::ErrorKind::WouldBlock.into()),
             _ => Err(map_err("Invalid data")),
         }
@@ -1,3 +1,4 @@
+use crate::DamageRect;
 use std::boxed::Box;
 use std::error::Error;
 
@@ -25,6 +26,14 @@ impl<'a> PixelProvider<'a> {
 
 pub trait Recorder {
     fn capture(&mut self, timeout_ms: u64) -> Result<PixelProvider, Box<dyn Error>>;
+
+    // Also the changed regions since the last capture, None if unknown.
+    fn capture_damage(
+        &mut self,
+        timeout_ms: u64,
+    ) -> Result<(PixelProvider, Option<&[DamageRect]>), Box<dyn Error>> {
+        Ok((self.capture(timeout_ms)?, None))
+    }
 }
 
 pub trait BoxCloneCapturable {
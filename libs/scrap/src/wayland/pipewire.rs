@@ -19,6 +19,8 @@ use gstreamer_app::AppSink;
 
 use hbb_common::config;
 
+use crate::damage::{DamageRect, DamageTracker};
+
 use super::capturable::PixelProvider;
 use super::capturable::{Capturable, Recorder};
 use super::remote_desktop_portal::OrgFreedesktopPortalRemoteDesktop as remote_desktop_portal;
@@ -209,7 +211,9 @@ pub struct PipeWireRecorder {
     appsink: AppSink,
     width: usize,
     height: usize,
-    saved_raw_data: Vec<u8>, // for faster compare and copy
+    damage: DamageTracker,
+    cropped_damage: Vec<DamageRect>,
+    last_crop: Option<(u32, u32, u32, u32)>,
 }
 
 impl PipeWireRecorder {
@@ -256,13 +260,22 @@ impl PipeWireRecorder {
             height: 0,
             buffer_cropped: vec![],
             is_cropped: false,
-            saved_raw_data: Vec::new(),
+            damage: Default::default(),
+            cropped_damage: vec![],
+            last_crop: None,
         })
     }
 }
 
 impl Recorder for PipeWireRecorder {
     fn capture(&mut self, timeout_ms: u64) -> Result<PixelProvider, Box<dyn Error>> {
+        Ok(self.capture_damage(timeout_ms)?.0)
+    }
+
+    fn capture_damage(
+        &mut self,
+        timeout_ms: u64,
+    ) -> Result<(PixelProvider, Option<&[DamageRect]>), Box<dyn Error>> {
         if let Some(sample) = self
             .appsink
             .try_pull_sample(gst::ClockTime::from_mseconds(timeout_ms))
@@ -294,9 +307,6 @@ impl Recorder for PipeWireRecorder {
             let buf = buf
                 .into_mapped_buffer_readable()
                 .map_err(|_| GStreamerError("Failed to map buffer.".into()))?;
-            if let Err(..) = crate::would_block_if_equal(&mut self.saved_raw_data, buf.as_slice()) {
-                return Ok(PixelProvider::NONE);
-            }
             let buf_size = buf.get_size();
             // BGRx is 4 bytes per pixel
             if buf_size != (w * h * 4) {
@@ -311,6 +321,34 @@ impl Recorder for PipeWireRecorder {
                     h
                 );
             } else {
+                // The SPA_META_VideoDamage of PipeWire is not forwarded by pipewiresrc, only the
+                // crop meta is, so the tiles are compared instead.
+                let crop_changed = crop != self.last_crop;
+                let unchanged = self
+                    .damage
+                    .update(buf.as_slice(), w, h, w * 4, 4, None)
+                    .is_err();
+                if unchanged && !crop_changed {
+                    return Ok((PixelProvider::NONE, None));
+                }
+                self.last_crop = crop;
+                self.cropped_damage = match crop {
+                    // the whole frame moves with the crop offset
+                    _ if crop_changed => {
+                        let (w, h) = crop.map_or((w, h), |(_, _, w, h)| (w as _, h as _));
+                        vec![DamageRect::new(0, 0, w, h)]
+                    }
+                    Some((x_off, y_off, w_crop, h_crop)) => {
+                        let area =
+                            DamageRect::new(x_off as _, y_off as _, w_crop as _, h_crop as _);
+                        self.damage
+                            .rects()
+                            .iter()
+                            .filter_map(|r| r.crop(&area))
+                            .collect()
+                    }
+                    None => self.damage.rects().to_vec(),
+                };
                 // Copy region specified by crop into self.buffer_cropped
                 // TODO: Figure out if ffmpeg provides a zero copy alternative
                 if let Some((x_off, y_off, w_crop, h_crop)) = crop {
@@ -336,7 +374,7 @@ impl Recorder for PipeWireRecorder {
                 self.buffer = Some(buf);
             }
         } else {
-            return Ok(PixelProvider::NONE);
+            return Ok((PixelProvider::NONE, None));
         }
         if self.buffer.is_none() {
             return Err(Box::new(GStreamerError("No buffer available!".into())));
@@ -349,9 +387,10 @@ impl Recorder for PipeWireRecorder {
                 .ok_or("Failed to get buffer as ref")?
                 .as_slice()
         };
+        let damage = Some(self.cropped_damage.as_slice());
         match self.pix_fmt.as_str() {
-            "BGRx" => Ok(PixelProvider::BGR0(self.width, self.height, buf)),
-            "RGBx" => Ok(PixelProvider::RGB0(self.width, self.height, buf)),
+            "BGRx" => Ok((PixelProvider::BGR0(self.width, self.height, buf), damage)),
+            "RGBx" => Ok((PixelProvider::RGB0(self.width, self.height, buf), damage)),
             _ => Err(Box::new(GStreamerError(format!(
                 "Unreachable! Unknown pix_fmt, {}",
                 &self.pix_fmt
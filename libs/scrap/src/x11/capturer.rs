@@ -1,6 +1,8 @@
+use super::damage::Damage;
 use super::ffi::*;
 use super::Display;
-use hbb_common::libc;
+use crate::damage::{DamageRect, DamageTracker};
+use hbb_common::{libc, log};
 use std::{io, ptr, slice};
 
 pub struct Capturer {
@@ -10,7 +12,9 @@ pub struct Capturer {
     buffer: *const u8,
 
     size: usize,
-    saved_raw_data: Vec<u8>, // for faster compare and copy
+    damage: DamageTracker,
+    xdamage: Option<Damage>,
+    hint: Vec<DamageRect>,
 }
 
 impl Capturer {
@@ -57,13 +61,17 @@ impl Capturer {
             );
         }
 
+        let xdamage = Damage::new(server, display.root());
+        log::info!("x11 capturer xdamage: {}", xdamage.is_some());
         let c = Capturer {
             display,
             shmid,
             xcbid,
             buffer,
             size,
-            saved_raw_data: Vec::new(),
+            damage: Default::default(),
+            xdamage,
+            hint: Vec::new(),
         };
         Ok(c)
     }
@@ -93,16 +101,56 @@ impl Capturer {
         }
     }
 
-    pub fn frame<'b>(&'b mut self) -> std::io::Result<&'b [u8]> {
+    // The damage reported by the X server, relative to the display.
+    fn take_xdamage(&mut self) -> Option<&[DamageRect]> {
+        let rect = self.display.rect();
+        let rects = self.xdamage.as_mut()?.take()?;
+        self.hint.clear();
+        for r in rects {
+            let x0 = (r.x as i32 - rect.x as i32).max(0);
+            let y0 = (r.y as i32 - rect.y as i32).max(0);
+            let x1 = (r.x as i32 + r.width as i32 - rect.x as i32).min(rect.w as i32);
+            let y1 = (r.y as i32 + r.height as i32 - rect.y as i32).min(rect.h as i32);
+            if x1 > x0 && y1 > y0 {
+                self.hint.push(DamageRect::new(
+                    x0 as _,
+                    y0 as _,
+                    (x1 - x0) as _,
+                    (y1 - y0) as _,
+                ));
+            }
+        }
+        Some(&self.hint)
+    }
+
+    pub fn frame<'b>(&'b mut self) -> std::io::Result<(&'b [u8], &'b [DamageRect])> {
+        let has_hint = match self.take_xdamage().map(|hint| hint.len()) {
+            // no need to grab, except the first frame
+            Some(0) if self.damage.has_frame() => {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            Some(_) => true,
+            None => false,
+        };
         self.get_image();
         let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
-        crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
-        Ok(result)
+        let rect = self.display.rect();
+        let (w, h) = (rect.w as usize, rect.h as usize);
+        let bpp = self.display.pixfmt().bytes_per_pixel();
+        let hint = if has_hint {
+            Some(self.hint.as_slice())
+        } else {
+            None
+        };
+        self.damage.update(result, w, h, w * bpp, bpp, hint)?;
+        Ok((result, self.damage.rects()))
     }
 }
 
 impl Drop for Capturer {
     fn drop(&mut self) {
+        // before the connection of the display is closed
+        self.xdamage = None;
         unsafe {
             // Detach segment from XCB.
             xcb_shm_detach(self.display.server().raw(), self.xcbid);
@@ -0,0 +1,152 @@
+// XDamage reports the changed regions of the root window, so that an idle screen is not grabbed.
+// libxcb-damage and libxcb-xfixes are loaded at runtime, without them the whole frame is compared.
+use super::ffi::*;
+use hbb_common::{libc, log};
+use libloading::Library;
+use std::{ptr, slice};
+
+struct Api {
+    _damage: Library,
+    _xfixes: Library,
+    damage_query_version: xcb_query_version_t,
+    damage_query_version_reply: xcb_query_version_reply_t,
+    damage_create: xcb_damage_create_t,
+    damage_destroy: xcb_damage_destroy_t,
+    damage_subtract: xcb_damage_subtract_t,
+    xfixes_query_version: xcb_query_version_t,
+    xfixes_query_version_reply: xcb_query_version_reply_t,
+    xfixes_create_region: xcb_xfixes_create_region_t,
+    xfixes_destroy_region: xcb_xfixes_destroy_region_t,
+    xfixes_fetch_region: xcb_xfixes_fetch_region_t,
+    xfixes_fetch_region_reply: xcb_xfixes_fetch_region_reply_t,
+    xfixes_fetch_region_rectangles: xcb_xfixes_fetch_region_rectangles_t,
+    xfixes_fetch_region_rectangles_length: xcb_xfixes_fetch_region_rectangles_length_t,
+}
+
+impl Api {
+    fn load() -> Result<Self, libloading::Error> {
+        unsafe {
+            let damage = Library::new("libxcb-damage.so.0")?;
+            let xfixes = Library::new("libxcb-xfixes.so.0")?;
+            Ok(Self {
+                damage_query_version: *damage.get(b"xcb_damage_query_version\0")?,
+                damage_query_version_reply: *damage.get(b"xcb_damage_query_version_reply\0")?,
+                damage_create: *damage.get(b"xcb_damage_create\0")?,
+                damage_destroy: *damage.get(b"xcb_damage_destroy\0")?,
+                damage_subtract: *damage.get(b"xcb_damage_subtract\0")?,
+                xfixes_query_version: *xfixes.get(b"xcb_xfixes_query_version\0")?,
+                xfixes_query_version_reply: *xfixes.get(b"xcb_xfixes_query_version_reply\0")?,
+                xfixes_create_region: *xfixes.get(b"xcb_xfixes_create_region\0")?,
+                xfixes_destroy_region: *xfixes.get(b"xcb_xfixes_destroy_region\0")?,
+                xfixes_fetch_region: *xfixes.get(b"xcb_xfixes_fetch_region\0")?,
+                xfixes_fetch_region_reply: *xfixes.get(b"xcb_xfixes_fetch_region_reply\0")?,
+                xfixes_fetch_region_rectangles: *xfixes
+                    .get(b"xcb_xfixes_fetch_region_rectangles\0")?,
+                xfixes_fetch_region_rectangles_length: *xfixes
+                    .get(b"xcb_xfixes_fetch_region_rectangles_length\0")?,
+                _damage: damage,
+                _xfixes: xfixes,
+            })
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref API: Option<Api> = match Api::load() {
+        Ok(api) => Some(api),
+        Err(e) => {
+            log::info!("XDamage is not available: {}", e);
+            None
+        }
+    };
+}
+
+pub struct Damage {
+    conn: *mut xcb_connection_t,
+    damage: xcb_damage_damage_t,
+    region: xcb_xfixes_region_t,
+    rects: Vec<xcb_rectangle_t>,
+}
+
+impl Damage {
+    pub fn new(conn: *mut xcb_connection_t, root: xcb_window_t) -> Option<Self> {
+        let api = API.as_ref()?;
+        unsafe {
+            // the versions must be negotiated before any other request of the extensions
+            let reply = (api.xfixes_query_version_reply)(
+                conn,
+                (api.xfixes_query_version)(conn, 2, 0),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                log::info!("XFixes is not supported by the X server");
+                return None;
+            }
+            libc::free(reply);
+            let reply = (api.damage_query_version_reply)(
+                conn,
+                (api.damage_query_version)(conn, 1, 1),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                log::info!("XDamage is not supported by the X server");
+                return None;
+            }
+            libc::free(reply);
+            let damage = xcb_generate_id(conn);
+            (api.damage_create)(conn, damage, root, XCB_DAMAGE_REPORT_LEVEL_NON_EMPTY);
+            let region = xcb_generate_id(conn);
+            (api.xfixes_create_region)(conn, region, 0, ptr::null());
+            Some(Self {
+                conn,
+                damage,
+                region,
+                rects: Vec::new(),
+            })
+        }
+    }
+
+    /// The damage since the last call in root coordinates, None if failed to get it.
+    pub fn take(&mut self) -> Option<&[xcb_rectangle_t]> {
+        let api = API.as_ref()?;
+        unsafe {
+            // the notify events are not used, the damage is taken as a region instead
+            loop {
+                let event = xcb_poll_for_event(self.conn);
+                if event.is_null() {
+                    break;
+                }
+                libc::free(event);
+            }
+            (api.damage_subtract)(self.conn, self.damage, XCB_NONE, self.region);
+            let reply = (api.xfixes_fetch_region_reply)(
+                self.conn,
+                (api.xfixes_fetch_region)(self.conn, self.region),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                return None;
+            }
+            let rects = (api.xfixes_fetch_region_rectangles)(reply);
+            let len = (api.xfixes_fetch_region_rectangles_length)(reply);
+            self.rects.clear();
+            if !rects.is_null() && len > 0 {
+                self.rects
+                    .extend_from_slice(slice::from_raw_parts(rects, len as usize));
+            }
+            libc::free(reply);
+        }
+        Some(&self.rects)
+    }
+}
+
+impl Drop for Damage {
+    fn drop(&mut self) {
+        if let Some(api) = API.as_ref() {
+            unsafe {
+                (api.damage_destroy)(self.conn, self.damage);
+                (api.xfixes_destroy_region)(self.conn, self.region);
+            }
+        }
+    }
+}
@@ -100,8 +100,63 @@ extern "C" {
         e: *mut *mut xcb_generic_error_t,
     ) -> *mut xcb_get_geometry_reply_t;
 
+    pub fn xcb_poll_for_event(c: *mut xcb_connection_t) -> *mut xcb_generic_event_t;
 }
 
+// libxcb-damage and libxcb-xfixes, loaded at runtime
+pub type xcb_query_version_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    client_major_version: u32,
+    client_minor_version: u32,
+) -> xcb_query_version_cookie_t;
+pub type xcb_query_version_reply_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    cookie: xcb_query_version_cookie_t,
+    e: *mut *mut xcb_generic_error_t,
+) -> *mut c_void;
+pub type xcb_damage_create_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    damage: xcb_damage_damage_t,
+    drawable: xcb_drawable_t,
+    level: u8,
+) -> xcb_void_cookie_t;
+pub type xcb_damage_destroy_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    damage: xcb_damage_damage_t,
+) -> xcb_void_cookie_t;
+pub type xcb_damage_subtract_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    damage: xcb_damage_damage_t,
+    repair: xcb_xfixes_region_t,
+    parts: xcb_xfixes_region_t,
+) -> xcb_void_cookie_t;
+pub type xcb_xfixes_create_region_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    region: xcb_xfixes_region_t,
+    rectangles_len: u32,
+    rectangles: *const xcb_rectangle_t,
+) -> xcb_void_cookie_t;
+pub type xcb_xfixes_destroy_region_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    region: xcb_xfixes_region_t,
+) -> xcb_void_cookie_t;
+pub type xcb_xfixes_fetch_region_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    region: xcb_xfixes_region_t,
+) -> xcb_xfixes_fetch_region_cookie_t;
+pub type xcb_xfixes_fetch_region_reply_t = unsafe extern "C" fn(
+    c: *mut xcb_connection_t,
+    cookie: xcb_xfixes_fetch_region_cookie_t,
+    e: *mut *mut xcb_generic_error_t,
+) -> *mut c_void;
+pub type xcb_xfixes_fetch_region_rectangles_t =
+    unsafe extern "C" fn(reply: *const c_void) -> *mut xcb_rectangle_t;
+pub type xcb_xfixes_fetch_region_rectangles_length_t =
+    unsafe extern "C" fn(reply: *const c_void) -> i32;
+
+pub const XCB_NONE: u32 = 0;
+pub const XCB_DAMAGE_REPORT_LEVEL_NON_EMPTY: u8 = 3;
+
 pub const XCB_IMAGE_FORMAT_Z_PIXMAP: u8 = 2;
 
 pub type xcb_atom_t = u32;
@@ -113,6 +168,9 @@ pub type xcb_timestamp_t = u32;
 pub type xcb_colormap_t = u32;
 pub type xcb_shm_seg_t = u32;
 pub type xcb_drawable_t = u32;
+pub type xcb_damage_damage_t = u32;
+pub type xcb_xfixes_region_t = u32;
+pub type xcb_generic_event_t = c_void;
 pub type xcb_get_atom_name_cookie_t = u32;
 pub type xcb_get_atom_name_reply_t = u32;
 pub type xcb_get_atom_name_request_t = xcb_get_atom_name_reply_t;
@@ -213,6 +271,28 @@ pub struct xcb_get_geometry_cookie_t {
     pub sequence: u32,
 }
 
+// the same for the query version requests of the extensions
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_query_version_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_xfixes_fetch_region_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_rectangle_t {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
 #[repr(C)]
 pub struct xcb_generic_error_t {
     pub response_type: u8,
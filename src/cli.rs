@@ -1,18 +1,47 @@
 use crate::client::*;
 use async_trait::async_trait;
 use hbb_common::{
+    allow_err, bail,
     config::PeerConfig,
     config::READ_TIMEOUT,
+    fs::{self, can_enable_overwrite_detection, DigestCheckResult},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
-    Stream,
+    timeout,
+    tokio::{self, sync::mpsc, time},
+    ResultType, Stream,
 };
 use std::sync::{Arc, RwLock};
 
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_CONNECT_FAILED: i32 = 1;
+pub const EXIT_LOGIN_FAILED: i32 = 2;
+pub const EXIT_TRANSFER_FAILED: i32 = 3;
+pub const EXIT_WAKE_FAILED: i32 = 4;
+// The password of the peer, if `--password-file` is not given.
+pub const PASSWORD_ENV: &str = "RUSTDESK_PASSWORD";
+
+/// Read the password of the peer from the file, `-` for stdin, or from `PASSWORD_ENV`.
+/// Only the first line is taken. None if neither is given, it is prompted then if not saved.
+pub fn read_password(file: Option<&str>) -> ResultType<Option<String>> {
+    match file {
+        Some("-") => read_password_line(std::io::stdin().lock()).map(Some),
+        Some(path) => {
+            read_password_line(std::io::BufReader::new(std::fs::File::open(path)?)).map(Some)
+        }
+        None => Ok(std::env::var(PASSWORD_ENV).ok()),
+    }
+}
+
+fn read_password_line(mut reader: impl std::io::BufRead) -> ResultType<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
 #[derive(Clone)]
 pub struct Session {
     id: String,
@@ -23,8 +52,17 @@ pub struct Session {
 
 impl Session {
     pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
-        let mut password = "".to_owned();
-        if PeerConfig::load(id).password.is_empty() {
+        Self::new_with_conn_type(id, sender, ConnType::PORT_FORWARD, None)
+    }
+
+    pub fn new_with_conn_type(
+        id: &str,
+        sender: mpsc::UnboundedSender<Data>,
+        conn_type: ConnType,
+        password: Option<String>,
+    ) -> Self {
+        let mut password = password.unwrap_or_default();
+        if password.is_empty() && PeerConfig::load(id).password.is_empty() {
             password = rpassword::prompt_password("Enter password: ").unwrap();
         }
         let session = Self {
@@ -33,21 +71,18 @@ impl Session {
             password,
             lc: Default::default(),
         };
-        session.lc.write().unwrap().initialize(
-            id.to_owned(),
-            ConnType::PORT_FORWARD,
-            None,
-            false,
-            None,
-            None,
-        );
+        session
+            .lc
+            .write()
+            .unwrap()
+            .initialize(id.to_owned(), conn_type, None, false, None, None);
         session
     }
 }
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
@@ -55,14 +90,20 @@ impl Interface for Session {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -87,6 +128,8 @@ impl Interface for Session {
         self.lc.write().unwrap().handle_peer_info(&pi);
     }
 
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
@@ -131,7 +174,7 @@ pub async fn connect_test(id: &str, key: String, token: String) {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
         }
-        Ok((mut stream, direct)) => {
+        Ok(((mut stream, direct, _pk, _), _)) => {
             log::info!("direct: {}", direct);
             // rpassword::prompt_password("Input anything to exit").ok();
             loop {
@@ -191,3 +234,539 @@ pub async fn start_one_port_forward(
     }
     log::info!("port forward (:{}) exit", port);
 }
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_socks(
+    id: String,
+    port: i32,
+    password: Option<String>,
+    key: String,
+    token: String,
+) {
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, ConnType::PORT_FORWARD, password);
+    if let Err(err) = crate::port_forward::listen_socks(
+        handler.id.clone(),
+        handler.password.clone(),
+        port,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+    )
+    .await
+    {
+        log::error!("Failed to listen on {}: {}", port, err);
+    }
+    log::info!("SOCKS5 (:{}) exit", port);
+}
+
+pub enum FileTransferCommand {
+    List(String),
+    Download { remote: String, local: String },
+    Upload { local: String, remote: String },
+    Remove(String),
+}
+
+/// Run one file transfer command without GUI.
+///
+/// Returns the exit code of the process, see `EXIT_*`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn file_transfer(
+    id: String,
+    command: FileTransferCommand,
+    password: Option<String>,
+    key: String,
+    token: String,
+) -> i32 {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&id, sender, ConnType::FILE_TRANSFER, password);
+    let ((mut stream, direct, _pk, _), (feedback, rendezvous_server)) =
+        match Client::start(&id, &key, &token, ConnType::FILE_TRANSFER, handler.clone()).await {
+            Ok(res) => res,
+            Err(err) => {
+                log::error!("Failed to connect {}: {}", &id, err);
+                return EXIT_CONNECT_FAILED;
+            }
+        };
+    log::info!("direct: {}", direct);
+    let _keep_it = hc_connection(feedback, rendezvous_server, &token).await;
+    let pi = match login(&handler, &mut receiver, &mut stream).await {
+        Ok(Some(Logon::PeerInfo(pi))) => pi,
+        Ok(_) => return EXIT_LOGIN_FAILED,
+        Err(err) => {
+            log::error!("Failed to login {}: {}", &id, err);
+            return EXIT_LOGIN_FAILED;
+        }
+    };
+    let mut transfer = CliFileTransfer {
+        id: 1,
+        is_windows: pi.platform == "Windows",
+        od: can_enable_overwrite_detection(hbb_common::get_version_number(&pi.version)),
+        read_jobs: Vec::new(),
+        write_jobs: Vec::new(),
+        remove_files: Vec::new(),
+        remove_dir: None,
+    };
+    match transfer.run(command, &mut stream).await {
+        Ok(()) => EXIT_OK,
+        Err(err) => {
+            log::error!("File transfer failed: {}", err);
+            EXIT_TRANSFER_FAILED
+        }
+    }
+}
+
+/// Ask the online peer `relay_id` to wake `target_id` in its LAN.
+///
+/// The mac addresses known locally are sent along with the target ID,
+/// in case the relay has not discovered the target.
+/// The request is sent with the login, the relay answers it without starting a session.
+#[tokio::main(flavor = "current_thread")]
+pub async fn wake_on_lan(
+    relay_id: String,
+    target_id: String,
+    mut macs: Vec<String>,
+    password: Option<String>,
+    key: String,
+    token: String,
+) -> i32 {
+    macs.extend(crate::lan::get_lan_peer_macs(&target_id));
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new_with_conn_type(&relay_id, sender, ConnType::DEFAULT_CONN, password);
+    handler.lc.write().unwrap().wake_on_lan = Some(WakeOnLan {
+        id: target_id.clone(),
+        macs,
+        ..Default::default()
+    });
+    let ((mut stream, direct, _pk, _), (feedback, rendezvous_server)) = match Client::start(
+        &relay_id,
+        &key,
+        &token,
+        ConnType::DEFAULT_CONN,
+        handler.clone(),
+    )
+    .await
+    {
+        Ok(res) => res,
+        Err(err) => {
+            log::error!("Failed to connect {}: {}", &relay_id, err);
+            return EXIT_CONNECT_FAILED;
+        }
+    };
+    log::info!("direct: {}", direct);
+    let _keep_it = hc_connection(feedback, rendezvous_server, &token).await;
+    let res = match login(&handler, &mut receiver, &mut stream).await {
+        Ok(Some(Logon::WakeOnLan(res))) => res,
+        Ok(_) => return EXIT_LOGIN_FAILED,
+        Err(err) => {
+            log::error!("Failed to login {}: {}", &relay_id, err);
+            return EXIT_LOGIN_FAILED;
+        }
+    };
+    if res.error.is_empty() {
+        println!(
+            "Sent {} magic packets to wake {} via {}",
+            res.sent, target_id, relay_id
+        );
+        return EXIT_OK;
+    }
+    log::error!(
+        "Failed to wake {} via {}: {}",
+        target_id,
+        relay_id,
+        res.error
+    );
+    EXIT_WAKE_FAILED
+}
+
+// What the login ends with.
+enum Logon {
+    PeerInfo(PeerInfo),
+    // a Wake-on-LAN only login is answered without a session
+    WakeOnLan(WakeOnLanResponse),
+}
+
+async fn login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+) -> ResultType<Option<Logon>> {
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => {
+                    bail!("Timeout");
+                }
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            handler.handle_hash(&handler.password, hash, stream).await;
+                        }
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    return Ok(None);
+                                }
+                            }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi.clone());
+                                return Ok(Some(Logon::PeerInfo(pi)));
+                            }
+                            _ => {}
+                        }
+                        Some(message::Union::Misc(misc)) => {
+                            if let Some(misc::Union::WakeOnLanResponse(res)) = misc.union {
+                                return Ok(Some(Logon::WakeOnLan(res)));
+                            }
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    bail!("Connection closed: {}", err);
+                }
+                _ => {
+                    bail!("Reset by the peer");
+                }
+            },
+            d = receiver.recv() => {
+                match d {
+                    Some(Data::Login((os_username, os_password, password, remember))) => {
+                        handler.handle_login_from_ui(os_username, os_password, password, remember, stream).await;
+                    }
+                    Some(Data::Message(msg)) => {
+                        allow_err!(stream.send(&msg).await);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+struct CliFileTransfer {
+    id: i32,
+    is_windows: bool,
+    // overwrite detection, the peer sends digests before files
+    od: bool,
+    read_jobs: Vec<fs::TransferJob>,
+    write_jobs: Vec<fs::TransferJob>,
+    // remote files waiting to be removed, the directory is removed after them
+    remove_files: Vec<String>,
+    remove_dir: Option<String>,
+}
+
+impl CliFileTransfer {
+    async fn run(&mut self, command: FileTransferCommand, stream: &mut Stream) -> ResultType<()> {
+        match command {
+            FileTransferCommand::List(path) => {
+                let mut action = FileAction::new();
+                action.set_read_dir(ReadDir {
+                    path,
+                    include_hidden: true,
+                    ..Default::default()
+                });
+                self.send_action(action, stream).await?;
+            }
+            FileTransferCommand::Download { remote, local } => {
+                self.write_jobs.push(fs::TransferJob::new_write(
+                    self.id,
+                    remote.clone(),
+                    local,
+                    0,
+                    false,
+                    true,
+                    Vec::new(),
+                    self.od,
+                ));
+                stream
+                    .send(&fs::new_send(self.id, remote, 0, false))
+                    .await?;
+            }
+            FileTransferCommand::Upload { local, remote } => {
+                let job = fs::TransferJob::new_read(
+                    self.id,
+                    remote.clone(),
+                    local,
+                    0,
+                    false,
+                    true,
+                    self.od,
+                )?;
+                stream
+                    .send(&fs::new_receive(
+                        self.id,
+                        remote,
+                        0,
+                        job.files().to_vec(),
+                        job.total_size(),
+                    ))
+                    .await?;
+                self.read_jobs.push(job);
+            }
+            FileTransferCommand::Remove(path) => {
+                // Get all files first, a directory can only be removed after its files.
+                self.remove_dir = Some(path.clone());
+                let mut action = FileAction::new();
+                action.set_all_files(ReadAllFiles {
+                    id: self.id,
+                    path,
+                    include_hidden: true,
+                    ..Default::default()
+                });
+                self.send_action(action, stream).await?;
+            }
+        }
+        let mut timer = crate::rustdesk_interval(time::interval(MILLI1));
+        loop {
+            tokio::select! {
+                res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                    Err(_) => {
+                        bail!("Timeout");
+                    }
+                    Ok(Some(Ok(bytes))) => {
+                        let msg_in = Message::parse_from_bytes(&bytes)?;
+                        if self.handle_msg(msg_in, stream).await? {
+                            return Ok(());
+                        }
+                    }
+                    Ok(Some(Err(err))) => {
+                        bail!("Connection closed: {}", err);
+                    }
+                    _ => {
+                        bail!("Reset by the peer");
+                    }
+                },
+                _ = timer.tick(), if !self.read_jobs.is_empty() => {
+                    fs::handle_read_jobs(&mut self.read_jobs, stream).await?;
+                }
+            }
+        }
+    }
+
+    // Returns true if the command is finished.
+    async fn handle_msg(&mut self, msg: Message, stream: &mut Stream) -> ResultType<bool> {
+        match msg.union {
+            Some(message::Union::FileResponse(fr)) => match fr.union {
+                Some(file_response::Union::Dir(fd)) => {
+                    let mut entries = fd.entries.to_vec();
+                    if self.is_windows && !cfg!(windows) {
+                        fs::transform_windows_path(&mut entries);
+                    }
+                    if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) {
+                        job.set_files(entries);
+                    } else if let Some(dir) = self.remove_dir.clone() {
+                        return self.remove(dir, entries, stream).await;
+                    } else {
+                        print_dir(&fd.path, &entries);
+                        return Ok(true);
+                    }
+                }
+                Some(file_response::Union::Digest(digest)) => {
+                    // Overwrite changed files, skip identical ones.
+                    let skip = file_transfer_send_confirm_request::Union::Skip(true);
+                    let union = if digest.is_upload {
+                        if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
+                            job.set_peer_signatures(
+                                digest.file_num,
+                                digest.signatures.clone().into_option(),
+                            );
+                        }
+                        // the peer has compared the uploading file with its own one
+                        if digest.is_identical {
+                            skip
+                        } else {
+                            file_transfer_send_confirm_request::Union::OffsetBlk(0)
+                        }
+                    } else {
+                        let job = match fs::get_job(digest.id, &mut self.write_jobs) {
+                            Some(job) => job,
+                            None => return Ok(false),
+                        };
+                        job.set_delta_sync(&digest);
+                        let path = match job.files().get(digest.file_num as usize) {
+                            Some(file) => fs::get_string(&job.join(&file.name)),
+                            None => return Ok(false),
+                        };
+                        match fs::is_write_need_confirmation(&path, &digest)? {
+                            DigestCheckResult::IsSame => skip,
+                            DigestCheckResult::NeedConfirm(d) if d.is_identical => skip,
+                            DigestCheckResult::NeedConfirm(_) => {
+                                file_transfer_send_confirm_request::Union::OffsetBlk(0)
+                            }
+                            DigestCheckResult::NoSuchFile => {
+                                file_transfer_send_confirm_request::Union::OffsetBlk(
+                                    job.resume_offset_blk(&digest).await,
+                                )
+                            }
+                        }
+                    };
+                    let mut req = FileTransferSendConfirmRequest {
+                        id: digest.id,
+                        file_num: digest.file_num,
+                        union: Some(union),
+                        ..Default::default()
+                    };
+                    if let Some(job) = fs::get_job(digest.id, &mut self.write_jobs) {
+                        job.confirm(&req);
+                        if req.offset_blk() == 0 && !req.has_skip() {
+                            req.signatures = job.new_signatures(digest.file_num).await.into();
+                        }
+                    }
+                    if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
+                        job.confirm(&req);
+                    }
+                    stream.send(&fs::new_send_confirm(req)).await?;
+                }
+                Some(file_response::Union::Block(block)) => {
+                    if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
+                        job.write(block).await?;
+                    }
+                }
+                Some(file_response::Union::Done(d)) => {
+                    if let Some(job) = fs::get_job(d.id, &mut self.write_jobs) {
+                        job.modify_time();
+                        log::info!("downloaded {} bytes", job.finished_size());
+                        fs::remove_job(d.id, &mut self.write_jobs);
+                        return Ok(true);
+                    }
+                    if self.remove_dir.is_some() {
+                        return self.remove_next(stream).await;
+                    }
+                    // the peer has written all uploaded files
+                    return Ok(true);
+                }
+                Some(file_response::Union::Error(e)) => {
+                    if let Some(job) = fs::get_job(e.id, &mut self.write_jobs) {
+                        job.remove_download_file();
+                    }
+                    bail!("{}", e.error);
+                }
+                _ => {}
+            },
+            Some(message::Union::FileAction(action)) => match action.union {
+                Some(file_action::Union::SendConfirm(c)) => {
+                    if let Some(job) = fs::get_job(c.id, &mut self.read_jobs) {
+                        job.confirm(&c);
+                    }
+                }
+                _ => {}
+            },
+            Some(message::Union::TestDelay(t)) => {
+                handle_test_delay(t, stream).await;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    async fn remove(
+        &mut self,
+        dir: String,
+        entries: Vec<FileEntry>,
+        stream: &mut Stream,
+    ) -> ResultType<bool> {
+        // A single file is listed with an empty name.
+        if entries.len() == 1 && entries[0].name.is_empty() {
+            self.remove_dir = None;
+            self.remove_files.push(dir);
+        } else {
+            let sep = if self.is_windows { "\\" } else { "/" };
+            let prefix = dir.trim_end_matches(sep).to_owned();
+            for entry in entries {
+                self.remove_files.push(format!(
+                    "{}{}{}",
+                    prefix,
+                    sep,
+                    entry.name.replace('/', sep)
+                ));
+            }
+        }
+        self.remove_next(stream).await
+    }
+
+    async fn remove_next(&mut self, stream: &mut Stream) -> ResultType<bool> {
+        let mut action = FileAction::new();
+        if let Some(path) = self.remove_files.pop() {
+            log::info!("remove file {}", path);
+            action.set_remove_file(FileRemoveFile {
+                id: self.id,
+                path,
+                file_num: self.remove_files.len() as _,
+                ..Default::default()
+            });
+        } else if let Some(path) = self.remove_dir.take() {
+            log::info!("remove dir {}", path);
+            action.set_remove_dir(FileRemoveDir {
+                id: self.id,
+                path,
+                recursive: true,
+                ..Default::default()
+            });
+        } else {
+            return Ok(true);
+        }
+        self.send_action(action, stream).await?;
+        Ok(false)
+    }
+
+    async fn send_action(&self, action: FileAction, stream: &mut Stream) -> ResultType<()> {
+        let mut msg = Message::new();
+        msg.set_file_action(action);
+        stream.send(&msg).await
+    }
+}
+
+fn print_dir(path: &str, entries: &[FileEntry]) {
+    println!("{}", path);
+    for entry in entries {
+        let entry_type = match entry.entry_type.enum_value() {
+            Ok(FileType::Dir) | Ok(FileType::DirLink) | Ok(FileType::DirDrive) => "d",
+            Ok(FileType::FileLink) => "l",
+            _ => "-",
+        };
+        let modified = chrono::DateTime::from_timestamp(entry.modified_time as _, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!(
+            "{} {:>12} {} {}",
+            entry_type, entry.size, modified, entry.name
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_password() {
+        let read = |s: &str| read_password_line(s.as_bytes()).unwrap();
+        assert_eq!(read("secret\n"), "secret");
+        assert_eq!(read("secret\r\nanother\n"), "secret");
+        assert_eq!(read("with space \n"), "with space ");
+        assert_eq!(read("secret"), "secret");
+        assert_eq!(read(""), "");
+
+        let path = std::env::temp_dir().join(format!("rustdesk_password_{}", std::process::id()));
+        std::fs::write(&path, "secret\n").unwrap();
+        assert_eq!(
+            read_password(path.to_str()).unwrap(),
+            Some("secret".to_owned())
+        );
+        std::fs::remove_file(&path).ok();
+        assert!(read_password(path.to_str()).is_err());
+
+        std::env::remove_var(PASSWORD_ENV);
+        assert_eq!(read_password(None).unwrap(), None);
+        std::env::set_var(PASSWORD_ENV, "secret");
+        assert_eq!(read_password(None).unwrap(), Some("secret".to_owned()));
+        std::env::remove_var(PASSWORD_ENV);
+    }
+}
@@ -42,7 +42,7 @@ use hbb_common::{
     get_version_number, log,
     message_proto::{option_message::BoolOption, *},
     protobuf::{Message as _, MessageField},
-    rand,
+    quic, rand,
     rendezvous_proto::*,
     socket_client::{connect_tcp, connect_tcp_local, ipv4_to_ipv6},
     sodiumoxide::{base64, crypto::sign},
@@ -104,6 +104,8 @@ pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
 pub const REQUIRE_2FA: &'static str = "2FA Required";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const LOGIN_MSG_ENCRYPTION_REQUIRED: &str = "Encryption required";
+pub const PEER_KEY_MISMATCH: &str = "Peer key mismatch";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -148,6 +150,8 @@ lazy_static::lazy_static! {
 }
 
 const PUBLIC_SERVER: &str = "public";
+// QUIC is tried before TCP, do not wait long for the peers not supporting it
+const QUIC_CONNECT_TIMEOUT: u64 = 3_000;
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub fn get_key_state(key: enigo::Key) -> bool {
@@ -227,7 +231,10 @@ impl Client {
         token: &str,
         conn_type: ConnType,
         interface: impl Interface,
-    ) -> ResultType<((Stream, bool, Option<Vec<u8>>), (i32, String))> {
+    ) -> ResultType<(
+        (Stream, bool, Option<Vec<u8>>, Option<quic::SubStreams>),
+        (i32, String),
+    )> {
         debug_assert!(peer == interface.get_id());
         interface.update_direct(None);
         interface.update_received(false);
@@ -251,17 +258,27 @@ impl Client {
         token: &str,
         conn_type: ConnType,
         interface: impl Interface,
-    ) -> ResultType<((Stream, bool, Option<Vec<u8>>), (i32, String))> {
+    ) -> ResultType<(
+        (Stream, bool, Option<Vec<u8>>, Option<quic::SubStreams>),
+        (i32, String),
+    )> {
         if config::is_incoming_only() {
             bail!("Incoming only mode");
         }
         // to-do: remember the port for each peer, so that we can retry easier
+        if (hbb_common::is_ip_str(peer) || hbb_common::is_domain_port_str(peer))
+            && interface.get_lch().read().unwrap().is_encryption_required()
+        {
+            // no key to secure the direct connection with
+            bail!(LOGIN_MSG_ENCRYPTION_REQUIRED);
+        }
         if hbb_common::is_ip_str(peer) {
             return Ok((
                 (
                     connect_tcp(check_port(peer, RELAY_PORT + 1), CONNECT_TIMEOUT).await?,
                     true,
                     None,
+                    None,
                 ),
                 (0, "".to_owned()),
             ));
@@ -269,7 +286,7 @@ impl Client {
         // Allow connect to {domain}:{port}
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
-                (connect_tcp(peer, CONNECT_TIMEOUT).await?, true, None),
+                (connect_tcp(peer, CONNECT_TIMEOUT).await?, true, None, None),
                 (0, "".to_owned()),
             ));
         }
@@ -402,9 +419,10 @@ impl Client {
                         )
                         .await?;
                         feedback = rr.feedback;
-                        let pk =
-                            Self::secure_connection(peer, signed_id_pk, key, &mut conn).await?;
-                        return Ok(((conn, false, pk), (feedback, rendezvous_server)));
+                        let lc = interface.get_lch();
+                        let pk = Self::secure_connection(peer, signed_id_pk, key, &mut conn, &lc)
+                            .await?;
+                        return Ok(((conn, false, pk, None), (feedback, rendezvous_server)));
                     }
                     _ => {
                         log::error!("Unexpected protobuf msg received: {:?}", msg_in);
@@ -465,7 +483,7 @@ impl Client {
         token: &str,
         conn_type: ConnType,
         interface: impl Interface,
-    ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
+    ) -> ResultType<(Stream, bool, Option<Vec<u8>>, Option<quic::SubStreams>)> {
         let direct_failures = interface.get_lch().read().unwrap().direct_failures;
         let mut connect_timeout = 0;
         const MIN: u64 = 1000;
@@ -500,8 +518,27 @@ impl Client {
         }
         log::info!("peer address: {}, timeout: {}", peer, connect_timeout);
         let start = std::time::Instant::now();
+        let mut sub_streams = None;
         // NOTICE: Socks5 is be used event in intranet. Which may be not a good way.
-        let mut conn = connect_tcp_local(peer, Some(local_addr), connect_timeout).await;
+        let use_quic = config::is_quic_allowed()
+            && !interface.is_force_relay()
+            && Config::get_socks().is_none();
+        let mut conn = if use_quic {
+            let ms_timeout = connect_timeout.min(QUIC_CONNECT_TIMEOUT);
+            match quic::connect(local_addr, peer, ms_timeout).await {
+                Ok((stream, sub)) => {
+                    log::info!("QUIC connection to {}", peer);
+                    sub_streams = Some(sub);
+                    Ok(stream)
+                }
+                Err(err) => {
+                    log::info!("Failed to connect via QUIC: {}, fall back to TCP", err);
+                    connect_tcp_local(peer, Some(local_addr), connect_timeout).await
+                }
+            }
+        } else {
+            connect_tcp_local(peer, Some(local_addr), connect_timeout).await
+        };
         let mut direct = !conn.is_err();
         interface.update_direct(Some(direct));
         if interface.is_force_relay() || conn.is_err() {
@@ -532,17 +569,30 @@ impl Client {
         }
         let mut conn = conn?;
         log::info!("{:?} used to establish connection", start.elapsed());
-        let pk = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn).await?;
-        Ok((conn, direct, pk))
+        let lc = interface.get_lch();
+        let pk = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn, &lc).await?;
+        if let Some(sub_streams) = sub_streams.as_mut() {
+            sub_streams.secure_with(&conn);
+        }
+        Ok((conn, direct, pk, sub_streams))
     }
 
     /// Establish secure connection with the server.
+    ///
+    /// The key of the peer is pinned on the first secure connection,
+    /// after that, or if encryption is required, the connection never falls back to non-secure.
     async fn secure_connection(
         peer_id: &str,
         signed_id_pk: Vec<u8>,
         key: &str,
         conn: &mut Stream,
+        lc: &RwLock<LoginConfigHandler>,
     ) -> ResultType<Option<Vec<u8>>> {
+        let (pinned_pk, require_encryption) = {
+            let lc = lc.read().unwrap();
+            (lc.config.pinned_pk.clone(), lc.is_encryption_required())
+        };
+        let strict = require_encryption || !pinned_pk.is_empty();
         let rs_pk = get_rs_pk(if key.is_empty() {
             config::RS_PUB_KEY
         } else {
@@ -563,9 +613,23 @@ impl Client {
                 log::error!("Handshake failed: invalid public key from rendezvous server");
             }
         }
+        if !pinned_pk.is_empty() {
+            if let Some(pk) = option_pk.as_ref() {
+                if *pk != pinned_pk {
+                    log::error!(
+                        "Handshake failed: public key of {} differs from the pinned one",
+                        peer_id
+                    );
+                    bail!(PEER_KEY_MISMATCH);
+                }
+            }
+        }
         let sign_pk = match sign_pk {
             Some(v) => v,
             None => {
+                if strict {
+                    bail!(LOGIN_MSG_ENCRYPTION_REQUIRED);
+                }
                 // send an empty message out in case server is setting up secure and waiting for first message
                 conn.send(&Message::new()).await?;
                 return Ok(option_pk);
@@ -588,10 +652,20 @@ impl Client {
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
                                 conn.set_key(key);
+                                if pinned_pk.is_empty() {
+                                    log::info!("Pin the public key of {}", peer_id);
+                                    lc.write().unwrap().set_pinned_pk(sign_pk.0.to_vec());
+                                }
                             } else {
                                 log::error!("Handshake failed: sign failure");
+                                if strict {
+                                    bail!(LOGIN_MSG_ENCRYPTION_REQUIRED);
+                                }
                                 conn.send(&Message::new()).await?;
                             }
+                        } else if strict {
+                            log::error!("Handshake failed: pk mismatch");
+                            bail!(PEER_KEY_MISMATCH);
                         } else {
                             // fall back to non-secure connection in case pk mismatch
                             log::info!("pk mismatch, fall back to non-secure");
@@ -601,10 +675,16 @@ impl Client {
                         }
                     } else {
                         log::error!("Handshake failed: invalid message type");
+                        if strict {
+                            bail!(LOGIN_MSG_ENCRYPTION_REQUIRED);
+                        }
                         conn.send(&Message::new()).await?;
                     }
                 } else {
                     log::error!("Handshake failed: invalid message format");
+                    if strict {
+                        bail!(LOGIN_MSG_ENCRYPTION_REQUIRED);
+                    }
                     conn.send(&Message::new()).await?;
                 }
             }
@@ -897,6 +977,8 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     ready: Arc<std::sync::Mutex<bool>>,
+    // called with the decoded audio before playing it
+    on_decoded: Option<fn(&[f32])>,
 }
 
 #[cfg(not(any(target_os = "android", target_os = "linux")))]
@@ -1054,6 +1136,9 @@ impl AudioHandler {
             if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
                 let channels = self.channels;
                 let n = n * (channels as usize);
+                if let Some(on_decoded) = self.on_decoded {
+                    on_decoded(&buffer[0..n]);
+                }
                 #[cfg(not(any(target_os = "android", target_os = "linux")))]
                 {
                     let sample_rate0 = self.sample_rate.0;
@@ -1149,6 +1234,7 @@ pub struct VideoHandler {
     pub texture: *mut c_void,
     recorder: Arc<Mutex<Option<Recorder>>>,
     record: bool,
+    audio_format: Option<AudioFormat>,
     _display: usize, // useful for debug
     fail_counter: usize,
     first_frame: bool,
@@ -1175,6 +1261,7 @@ impl VideoHandler {
             texture: std::ptr::null_mut(),
             recorder: Default::default(),
             record: false,
+            audio_format: None,
             _display,
             fail_counter: 0,
             first_frame: true,
@@ -1259,6 +1346,7 @@ impl VideoHandler {
                 width: w as _,
                 height: h as _,
                 format: scrap::CodecFormat::VP9,
+                audio_format: self.audio_format.clone(),
                 tx: None,
             })
             .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
@@ -1268,6 +1356,29 @@ impl VideoHandler {
 
         self.record = start;
     }
+
+    /// Set the audio format of the peer, used to add the audio track of the record.
+    pub fn set_audio_format(&mut self, format: AudioFormat) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.set_audio_format(&format));
+        }
+        self.audio_format = Some(format);
+    }
+
+    /// Write an audio frame to the record.
+    pub fn record_audio_frame(&mut self, frame: &AudioFrame) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_audio(frame));
+        }
+    }
 }
 
 // The source of sent password
@@ -1327,6 +1438,10 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    // Forwards of a profile are opened after login, see `port_forward::listen_profile`.
+    pub port_forward_multiplex: bool,
+    // Sent instead of starting a session, see `cli::wake_on_lan`.
+    pub wake_on_lan: Option<WakeOnLan>,
     pub version: i64,
     features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
@@ -1347,6 +1462,8 @@ pub struct LoginConfigHandler {
     password_source: PasswordSource, // where the sent password comes from
     shared_password: Option<String>, // Store the shared password
     pub enable_trusted_devices: bool,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub clipboard_history: crate::clipboard::ClipboardHistory,
 }
 
 impl Deref for LoginConfigHandler {
@@ -1481,6 +1598,19 @@ impl LoginConfigHandler {
         self.save_config(config);
     }
 
+    /// Whether the connection must be encrypted, by the global option or the option of the peer.
+    pub fn is_encryption_required(&self) -> bool {
+        Config::get_option(config::keys::OPTION_REQUIRE_ENCRYPTION) == "Y"
+            || self.get_toggle_option(config::keys::OPTION_REQUIRE_ENCRYPTION)
+    }
+
+    /// Pin the public key of the peer, an empty key removes the pin.
+    pub fn set_pinned_pk(&mut self, pk: Vec<u8>) {
+        let mut config = self.load_config();
+        config.pinned_pk = pk;
+        self.save_config(config);
+    }
+
     //to-do: too many dup code below.
 
     /// Save view style to the current config.
@@ -1781,6 +1911,9 @@ impl LoginConfigHandler {
         if self.get_toggle_option("disable-audio") {
             msg.disable_audio = BoolOption::Yes.into();
         }
+        if let Some(p) = Self::get_audio_profile_enum(&self.get_option("audio-profile")) {
+            msg.audio_profile = p.into();
+        }
         if !view_only && self.get_toggle_option(config::keys::OPTION_ENABLE_FILE_COPY_PASTE) {
             msg.enable_file_transfer = BoolOption::Yes.into();
         }
@@ -1930,6 +2063,38 @@ impl LoginConfigHandler {
         res
     }
 
+    /// Parse the audio profile option, "voice", "music" or "low-bandwidth".
+    fn get_audio_profile_enum(p: &str) -> Option<AudioProfile> {
+        match p {
+            "voice" => Some(AudioProfile::Voice),
+            "music" => Some(AudioProfile::Music),
+            "low-bandwidth" => Some(AudioProfile::LowBandwidth),
+            _ => None,
+        }
+    }
+
+    /// Save the given audio profile to the config.
+    /// Return a [`Message`] that contains the audio profile,
+    /// or [`AudioProfile::AudioProfileDefault`] to restore the default one if the profile is not valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The audio profile.
+    pub fn save_audio_profile(&mut self, value: String) -> Message {
+        let p = Self::get_audio_profile_enum(&value).unwrap_or(AudioProfile::AudioProfileDefault);
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            audio_profile: p.into(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        let mut config = self.load_config();
+        config.options.insert("audio-profile".to_owned(), value);
+        self.save_config(config);
+        msg_out
+    }
+
     /// Create a [`Message`] for saving custom fps.
     ///
     /// # Arguments
@@ -2184,9 +2349,14 @@ impl LoginConfigHandler {
             ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
+                multiplex: self.port_forward_multiplex,
                 ..Default::default()
             }),
-            _ => {}
+            _ => {
+                if let Some(wol) = self.wake_on_lan.clone() {
+                    lr.set_wake_on_lan(wol);
+                }
+            }
         }
 
         let mut msg_out = Message::new();
@@ -2273,6 +2443,7 @@ where
         sync_cpu_usage();
         get_hwcodec_config();
         let mut handler_controller_map = HashMap::new();
+        let mut audio_format: Option<AudioFormat> = None;
         let mut count = 0;
         let mut duration = std::time::Duration::ZERO;
         loop {
@@ -2303,10 +2474,14 @@ where
                         let start = std::time::Instant::now();
                         let format = CodecFormat::from(&vf);
                         if !handler_controller_map.contains_key(&display) {
+                            let mut handler = VideoHandler::new(format, display);
+                            if let Some(f) = &audio_format {
+                                handler.set_audio_format(f.clone());
+                            }
                             handler_controller_map.insert(
                                 display,
                                 VideoHandlerController {
-                                    handler: VideoHandler::new(format, display),
+                                    handler,
                                     skip_beginning: 0,
                                 },
                             );
@@ -2414,6 +2589,17 @@ where
                             }
                         }
                     }
+                    MediaData::AudioFormat(f) => {
+                        for (_, handler_controler) in handler_controller_map.iter_mut() {
+                            handler_controler.handler.set_audio_format(f.clone());
+                        }
+                        audio_format = Some(f);
+                    }
+                    MediaData::AudioFrame(af) => {
+                        for (_, handler_controler) in handler_controller_map.iter_mut() {
+                            handler_controler.handler.record_audio_frame(&af);
+                        }
+                    }
                     _ => {}
                 }
             } else {
@@ -2435,9 +2621,17 @@ where
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_(None)
+}
+
+/// Start an audio thread, `on_decoded` is called with the decoded audio before playing it.
+pub fn start_audio_thread_(on_decoded: Option<fn(&[f32])>) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
+        let mut audio_handler = AudioHandler {
+            on_decoded,
+            ..Default::default()
+        };
         loop {
             if let Ok(data) = audio_receiver.recv() {
                 match data {
@@ -3163,6 +3357,8 @@ pub enum Data {
     CancelJob(i32),
     RemovePortForward(i32),
     AddPortForward((i32, String, i32)),
+    StartPortForwardProfile(String),
+    StopPortForwardProfile(String),
     #[cfg(not(feature = "flutter"))]
     ToggleClipboardFile,
     NewRDP,
@@ -3534,3 +3730,131 @@ pub mod peer_online {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::{
+        sodiumoxide::crypto::box_,
+        tokio::{self, net::TcpListener},
+    };
+
+    fn sign_id_pk(id: &str, pk: &[u8], sk: &sign::SecretKey) -> Vec<u8> {
+        let id_pk = IdPk {
+            id: id.to_owned(),
+            pk: Bytes::from(pk.to_vec()),
+            ..Default::default()
+        };
+        sign::sign(&id_pk.write_to_bytes().unwrap(), sk)
+    }
+
+    // Connect to a peer signing its id with `sk` like the controlled side,
+    // `pk` is the key of the peer signed by the rendezvous server.
+    async fn connect(
+        lc: &RwLock<LoginConfigHandler>,
+        id: &str,
+        pk: Option<&sign::PublicKey>,
+        sk: &sign::SecretKey,
+    ) -> ResultType<Option<Vec<u8>>> {
+        let (rs_pk, rs_sk) = sign::gen_keypair();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (peer_id, peer_sk) = (id.to_owned(), sk.clone());
+        tokio::spawn(async move {
+            if let Ok((socket, addr)) = listener.accept().await {
+                let mut stream = Stream::from(socket, addr);
+                let (box_pk, _) = box_::gen_keypair();
+                let mut msg_out = Message::new();
+                msg_out.set_signed_id(SignedId {
+                    id: sign_id_pk(&peer_id, &box_pk.0, &peer_sk).into(),
+                    ..Default::default()
+                });
+                stream.send(&msg_out).await.ok();
+                // wait for the public key
+                stream.next().await;
+            }
+        });
+        let mut conn = Stream::new(addr, None, CONNECT_TIMEOUT).await?;
+        let signed_id_pk = pk
+            .map(|pk| sign_id_pk(id, &pk.0, &rs_sk))
+            .unwrap_or_default();
+        let key = crate::encode64(rs_pk);
+        Client::secure_connection(id, signed_id_pk, &key, &mut conn, lc).await
+    }
+
+    fn err<T>(res: ResultType<T>) -> String {
+        res.err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_pin_peer_key() {
+        let id = "test-pin-peer-key";
+        PeerConfig::remove(id);
+        let lc = RwLock::new(LoginConfigHandler {
+            id: id.to_owned(),
+            ..Default::default()
+        });
+        let (pk, sk) = sign::gen_keypair();
+        let (pk2, sk2) = sign::gen_keypair();
+        // falls back to non-secure without a pinned key
+        let res = connect(&lc, id, Some(&pk), &sk2).await.unwrap();
+        assert_eq!(res, Some(pk.0.to_vec()));
+        assert!(lc.read().unwrap().config.pinned_pk.is_empty());
+        // pinned on the first secure connection
+        let res = connect(&lc, id, Some(&pk), &sk).await.unwrap();
+        assert_eq!(res, Some(pk.0.to_vec()));
+        assert_eq!(lc.read().unwrap().config.pinned_pk, pk.0.to_vec());
+        assert_eq!(PeerConfig::load(id).pinned_pk, pk.0.to_vec());
+        // never falls back to non-secure once pinned
+        assert_eq!(
+            err(connect(&lc, id, Some(&pk2), &sk2).await),
+            PEER_KEY_MISMATCH
+        );
+        assert_eq!(
+            err(connect(&lc, id, Some(&pk), &sk2).await),
+            PEER_KEY_MISMATCH
+        );
+        assert_eq!(
+            err(connect(&lc, id, None, &sk).await),
+            LOGIN_MSG_ENCRYPTION_REQUIRED
+        );
+        // the new key is pinned after forgetting the old one
+        crate::ui_interface::forget_pinned_key(id.to_owned());
+        assert!(PeerConfig::load(id).pinned_pk.is_empty());
+        let lc = RwLock::new(LoginConfigHandler {
+            id: id.to_owned(),
+            config: PeerConfig::load(id),
+            ..Default::default()
+        });
+        assert!(connect(&lc, id, Some(&pk2), &sk2).await.is_ok());
+        assert_eq!(lc.read().unwrap().config.pinned_pk, pk2.0.to_vec());
+        PeerConfig::remove(id);
+    }
+
+    #[tokio::test]
+    async fn test_require_encryption() {
+        let id = "test-require-encryption";
+        PeerConfig::remove(id);
+        let mut lc = LoginConfigHandler {
+            id: id.to_owned(),
+            ..Default::default()
+        };
+        lc.config.options.insert(
+            config::keys::OPTION_REQUIRE_ENCRYPTION.to_owned(),
+            "Y".to_owned(),
+        );
+        let lc = RwLock::new(lc);
+        let (pk, sk) = sign::gen_keypair();
+        let (_, sk2) = sign::gen_keypair();
+        assert_eq!(
+            err(connect(&lc, id, None, &sk).await),
+            LOGIN_MSG_ENCRYPTION_REQUIRED
+        );
+        assert_eq!(
+            err(connect(&lc, id, Some(&pk), &sk2).await),
+            PEER_KEY_MISMATCH
+        );
+        assert!(connect(&lc, id, Some(&pk), &sk).await.is_ok());
+        PeerConfig::remove(id);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     num::NonZeroI64,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -34,6 +34,7 @@ use hbb_common::{
     get_time, log,
     message_proto::{permission_info::Permission, *},
     protobuf::Message as _,
+    quic,
     rendezvous_proto::ConnType,
     timeout,
     tokio::{
@@ -74,6 +75,10 @@ pub struct Remote<T: InvokeUiSession> {
     decode_fps: Arc<RwLock<Option<usize>>>,
     chroma: Arc<RwLock<Option<Chroma>>>,
     peer_info: ParsedPeerInfo,
+    // Displays being recorded, audio frames are also sent to their recorders.
+    recording_displays: HashSet<usize>,
+    // the video, input and file transfer streams of QUIC
+    sub_streams: Option<quic::SubStreams>,
 }
 
 #[derive(Default)]
@@ -129,6 +134,8 @@ impl<T: InvokeUiSession> Remote<T> {
             decode_fps,
             chroma,
             peer_info: Default::default(),
+            recording_displays: Default::default(),
+            sub_streams: None,
         }
     }
 
@@ -150,7 +157,8 @@ impl<T: InvokeUiSession> Remote<T> {
         )
         .await
         {
-            Ok(((mut peer, direct, pk), (feedback, rendezvous_server))) => {
+            Ok(((mut peer, direct, pk, sub_streams), (feedback, rendezvous_server))) => {
+                self.sub_streams = sub_streams;
                 self.handler
                     .connection_round_state
                     .lock()
@@ -193,7 +201,7 @@ impl<T: InvokeUiSession> Remote<T> {
 
                 loop {
                     tokio::select! {
-                        res = peer.next() => {
+                        res = quic::next(&mut peer, self.sub_streams.as_mut()) => {
                             if let Some(res) = res {
                                 match res {
                                     Err(err) => {
@@ -240,7 +248,8 @@ impl<T: InvokeUiSession> Remote<T> {
                                 break;
                             }
                             if !self.read_jobs.is_empty() {
-                                if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, &mut peer).await {
+                                let stream = quic::select(&mut peer, self.sub_streams.as_mut(), quic::STREAM_FILE);
+                                if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, stream).await {
                                     self.handler.msgbox("error", "Connection Error", &err.to_string(), "");
                                     break;
                                 }
@@ -498,7 +507,17 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.check_clipboard_file_context();
             }
             Data::Message(msg) => {
-                allow_err!(peer.send(&msg).await);
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                match &msg.union {
+                    Some(message::Union::Clipboard(cb)) => {
+                        self.push_clipboard_history(vec![cb.clone()], ClipboardSide::Client);
+                    }
+                    Some(message::Union::MultiClipboards(mcb)) => {
+                        self.push_clipboard_history(mcb.clipboards.clone(), ClipboardSide::Client);
+                    }
+                    _ => {}
+                }
+                allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg).await);
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
@@ -516,8 +535,12 @@ impl<T: InvokeUiSession> Remote<T> {
                         od,
                     ));
                     allow_err!(
-                        peer.send(&fs::new_send(id, path, file_num, include_hidden))
-                            .await
+                        quic::send(
+                            peer,
+                            self.sub_streams.as_mut(),
+                            &fs::new_send(id, path, file_num, include_hidden)
+                        )
+                        .await
                     );
                 } else {
                     match fs::TransferJob::new_read(
@@ -560,8 +583,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             self.read_jobs.push(job);
                             self.timer = crate::rustdesk_interval(time::interval(MILLI1));
                             allow_err!(
-                                peer.send(&fs::new_receive(id, to, file_num, files, total_size))
-                                    .await
+                                quic::send(
+                                    peer,
+                                    self.sub_streams.as_mut(),
+                                    &fs::new_receive(id, to, file_num, files, total_size)
+                                )
+                                .await
                             );
                         }
                     }
@@ -627,29 +654,21 @@ impl<T: InvokeUiSession> Remote<T> {
                 if is_remote {
                     if let Some(job) = get_job(id, &mut self.write_jobs) {
                         job.is_last_job = false;
-                        allow_err!(
-                            peer.send(&fs::new_send(
-                                id,
-                                job.remote.clone(),
-                                job.file_num,
-                                job.show_hidden
-                            ))
-                            .await
-                        );
+                        let msg =
+                            fs::new_send(id, job.remote.clone(), job.file_num, job.show_hidden);
+                        allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg).await);
                     }
                 } else {
                     if let Some(job) = get_job(id, &mut self.read_jobs) {
                         job.is_last_job = false;
-                        allow_err!(
-                            peer.send(&fs::new_receive(
-                                id,
-                                job.path.to_string_lossy().to_string(),
-                                job.file_num,
-                                job.files.clone(),
-                                job.total_size(),
-                            ))
-                            .await
+                        let msg = fs::new_receive(
+                            id,
+                            job.path.to_string_lossy().to_string(),
+                            job.file_num,
+                            job.files.clone(),
+                            job.total_size(),
                         );
+                        allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg).await);
                     }
                 }
             }
@@ -694,7 +713,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                         let mut msg = Message::new();
                         let mut file_action = FileAction::new();
-                        let req = FileTransferSendConfirmRequest {
+                        let mut req = FileTransferSendConfirmRequest {
                             id,
                             file_num,
                             union: if need_override {
@@ -705,9 +724,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             ..Default::default()
                         };
                         job.confirm(&req);
+                        if need_override {
+                            req.signatures = job.new_signatures(file_num).await.into();
+                        }
                         file_action.set_send_confirm(req);
                         msg.set_file_action(file_action);
-                        allow_err!(peer.send(&msg).await);
+                        allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg).await);
                     }
                 }
             }
@@ -723,7 +745,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
                     self.remove_jobs
                         .insert(id, RemoveJob::new(Vec::new(), path, sep, is_remote));
                 } else {
@@ -753,7 +775,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     ..Default::default()
                 });
                 msg_out.set_file_action(file_action);
-                allow_err!(peer.send(&msg_out).await);
+                allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
                 if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
                     job.remove_download_file();
                     fs::remove_job(id, &mut self.write_jobs);
@@ -771,7 +793,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     ..Default::default()
                 });
                 msg_out.set_file_action(file_action);
-                allow_err!(peer.send(&msg_out).await);
+                allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
             }
             Data::RemoveFile((id, path, file_num, is_remote)) => {
                 if is_remote {
@@ -784,7 +806,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
                 } else {
                     match fs::remove_file(&path) {
                         Err(err) => {
@@ -806,7 +828,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
                 } else {
                     match fs::create_dir(&path) {
                         Err(err) => {
@@ -829,7 +851,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         ..Default::default()
                     });
                     msg_out.set_file_action(file_action);
-                    allow_err!(peer.send(&msg_out).await);
+                    allow_err!(quic::send(peer, self.sub_streams.as_mut(), &msg_out).await);
                 } else {
                     let err = fs::rename_file(&path, &new_name)
                         .err()
@@ -838,6 +860,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
             Data::RecordScreen(start, display, w, h, id) => {
+                if start {
+                    self.recording_displays.insert(display);
+                } else {
+                    self.recording_displays.remove(&display);
+                }
                 let _ = self
                     .video_sender
                     .send(MediaData::RecordScreen(start, display, w, h, id));
@@ -1172,6 +1199,9 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(login_response::Union::PeerInfo(pi)) => {
+                        if let Some(sub_streams) = self.sub_streams.as_mut() {
+                            sub_streams.set_ready();
+                        }
                         let peer_version = pi.version.clone();
                         let peer_platform = pi.platform.clone();
                         self.set_peer_info(&pi);
@@ -1240,6 +1270,8 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        self.push_clipboard_history(vec![cb.clone()], ClipboardSide::Host);
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Client);
                         #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1257,6 +1289,8 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        self.push_clipboard_history(_mcb.clipboards.clone(), ClipboardSide::Host);
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(_mcb.clipboards, ClipboardSide::Client);
                     }
@@ -1290,6 +1324,10 @@ impl<T: InvokeUiSession> Remote<T> {
                         Some(file_response::Union::Digest(digest)) => {
                             if digest.is_upload {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
+                                    job.set_peer_signatures(
+                                        digest.file_num,
+                                        digest.signatures.clone().into_option(),
+                                    );
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let read_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
@@ -1308,7 +1346,10 @@ impl<T: InvokeUiSession> Remote<T> {
                                             };
                                             job.confirm(&req);
                                             let msg = new_send_confirm(req);
-                                            allow_err!(peer.send(&msg).await);
+                                            allow_err!(
+                                                quic::send(peer, self.sub_streams.as_mut(), &msg)
+                                                    .await
+                                            );
                                         } else {
                                             self.handler.override_file_confirm(
                                                 digest.id,
@@ -1322,6 +1363,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             } else {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.write_jobs) {
+                                    job.set_delta_sync(&digest);
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let write_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
@@ -1336,23 +1378,44 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     };
                                                     job.confirm(&req);
                                                     let msg = new_send_confirm(req);
-                                                    allow_err!(peer.send(&msg).await);
+                                                    allow_err!(
+                                                        quic::send(
+                                                            peer,
+                                                            self.sub_streams.as_mut(),
+                                                            &msg
+                                                        )
+                                                        .await
+                                                    );
                                                 }
                                                 DigestCheckResult::NeedConfirm(digest) => {
                                                     if let Some(overwrite) = overwrite_strategy {
-                                                        let req = FileTransferSendConfirmRequest {
-                                                            id: digest.id,
-                                                            file_num: digest.file_num,
-                                                            union: Some(if overwrite {
-                                                                file_transfer_send_confirm_request::Union::OffsetBlk(0)
-                                                            } else {
-                                                                file_transfer_send_confirm_request::Union::Skip(true)
-                                                            }),
-                                                            ..Default::default()
-                                                        };
+                                                        let mut req =
+                                                            FileTransferSendConfirmRequest {
+                                                                id: digest.id,
+                                                                file_num: digest.file_num,
+                                                                union: Some(if overwrite {
+                                                                    file_transfer_send_confirm_request::Union::OffsetBlk(0)
+                                                                } else {
+                                                                    file_transfer_send_confirm_request::Union::Skip(true)
+                                                                }),
+                                                                ..Default::default()
+                                                            };
                                                         job.confirm(&req);
+                                                        if overwrite {
+                                                            req.signatures = job
+                                                                .new_signatures(digest.file_num)
+                                                                .await
+                                                                .into();
+                                                        }
                                                         let msg = new_send_confirm(req);
-                                                        allow_err!(peer.send(&msg).await);
+                                                        allow_err!(
+                                                            quic::send(
+                                                                peer,
+                                                                self.sub_streams.as_mut(),
+                                                                &msg
+                                                            )
+                                                            .await
+                                                        );
                                                     } else {
                                                         self.handler.override_file_confirm(
                                                             digest.id,
@@ -1364,15 +1427,24 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     }
                                                 }
                                                 DigestCheckResult::NoSuchFile => {
+                                                    let offset_blk =
+                                                        job.resume_offset_blk(&digest).await;
                                                     let req = FileTransferSendConfirmRequest {
                                                         id: digest.id,
                                                         file_num: digest.file_num,
-                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset_blk)),
                                                         ..Default::default()
                                                     };
                                                     job.confirm(&req);
                                                     let msg = new_send_confirm(req);
-                                                    allow_err!(peer.send(&msg).await);
+                                                    allow_err!(
+                                                        quic::send(
+                                                            peer,
+                                                            self.sub_streams.as_mut(),
+                                                            &msg
+                                                        )
+                                                        .await
+                                                    );
                                                 }
                                             },
                                             Err(err) => {
@@ -1411,6 +1483,9 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::AudioFormat(f)) => {
+                        self.video_sender
+                            .send(MediaData::AudioFormat(f.clone()))
+                            .ok();
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
                     Some(misc::Union::ChatMessage(c)) => {
@@ -1605,6 +1680,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if !self.recording_displays.is_empty() {
+                            self.video_sender
+                                .send(MediaData::AudioFrame(Box::new(frame.clone())))
+                                .ok();
+                        }
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -1860,6 +1940,16 @@ impl<T: InvokeUiSession> Remote<T> {
         true
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn push_clipboard_history(&self, clipboards: Vec<Clipboard>, side: ClipboardSide) {
+        self.handler
+            .lc
+            .write()
+            .unwrap()
+            .clipboard_history
+            .push(clipboards, side);
+    }
+
     fn check_clipboard_file_context(&self) {
         #[cfg(any(
             target_os = "windows",
@@ -373,7 +373,7 @@ pub fn get_current_clipboard_msg(
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ClipboardSide {
     Host,
     Client,
@@ -406,6 +406,158 @@ impl std::fmt::Display for ClipboardSide {
     }
 }
 
+fn is_image_clipboard(c: &Clipboard) -> bool {
+    use hbb_common::message_proto::ClipboardFormat;
+    matches!(
+        c.format.enum_value(),
+        Ok(ClipboardFormat::ImageRgba)
+            | Ok(ClipboardFormat::ImagePng)
+            | Ok(ClipboardFormat::ImageSvg)
+    )
+}
+
+// Entries kept in the clipboard history of a session.
+const CLIPBOARD_HISTORY_MAX_ENTRIES: usize = 20;
+// Total size of the (compressed) contents in the history, images are large.
+const CLIPBOARD_HISTORY_MAX_BYTES: usize = 32 * 1024 * 1024;
+const CLIPBOARD_HISTORY_PREVIEW_LEN: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct ClipboardHistoryEntry {
+    pub id: u64,
+    // ms since epoch
+    pub time: i64,
+    // the side the content was copied on
+    pub side: ClipboardSide,
+    pub clipboards: Vec<Clipboard>,
+}
+
+impl ClipboardHistoryEntry {
+    pub fn is_image(&self) -> bool {
+        self.clipboards.iter().any(is_image_clipboard)
+    }
+
+    fn size(&self) -> usize {
+        self.clipboards.iter().map(|c| c.content.len()).sum()
+    }
+
+    fn is_same_content(&self, clipboards: &[Clipboard]) -> bool {
+        self.clipboards.len() == clipboards.len()
+            && self
+                .clipboards
+                .iter()
+                .zip(clipboards.iter())
+                .all(|(a, b)| a.format == b.format && a.content == b.content)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        use hbb_common::message_proto::ClipboardFormat;
+        let formats: Vec<&str> = self
+            .clipboards
+            .iter()
+            .filter_map(|c| match c.format.enum_value() {
+                _ if is_image_clipboard(c) => Some("image"),
+                Ok(ClipboardFormat::Text) => Some("text"),
+                Ok(ClipboardFormat::Rtf) => Some("rtf"),
+                Ok(ClipboardFormat::Html) => Some("html"),
+                _ => None,
+            })
+            .collect();
+        let preview = self
+            .clipboards
+            .iter()
+            .find(|c| c.format.enum_value() == Ok(ClipboardFormat::Text))
+            .map(|c| {
+                let data = if c.compress {
+                    hbb_common::compress::decompress(&c.content)
+                } else {
+                    c.content.to_vec()
+                };
+                String::from_utf8_lossy(&data)
+                    .chars()
+                    .take(CLIPBOARD_HISTORY_PREVIEW_LEN)
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        let image = self.clipboards.iter().find(|c| c.width > 0 && c.height > 0);
+        serde_json::json!({
+            "id": self.id,
+            "time": self.time,
+            "side": self.side.to_string(),
+            "formats": formats,
+            "preview": preview,
+            "width": image.map(|c| c.width).unwrap_or_default(),
+            "height": image.map(|c| c.height).unwrap_or_default(),
+            "size": self.size(),
+        })
+    }
+}
+
+/// The clipboard contents copied on either side during a session, the latest last.
+#[derive(Debug, Default)]
+pub struct ClipboardHistory {
+    entries: std::collections::VecDeque<ClipboardHistoryEntry>,
+    next_id: u64,
+}
+
+impl ClipboardHistory {
+    /// Add the content, or move it to the latest if it is already in the history.
+    pub fn push(&mut self, clipboards: Vec<Clipboard>, side: ClipboardSide) {
+        use hbb_common::message_proto::ClipboardFormat;
+        // The owner flag differs between the sides, it is not part of the content.
+        let clipboards: Vec<Clipboard> = clipboards
+            .into_iter()
+            .filter(|c| {
+                !(c.format.enum_value() == Ok(ClipboardFormat::Special)
+                    && c.special_name == RUSTDESK_CLIPBOARD_OWNER_FORMAT)
+            })
+            .collect();
+        if clipboards.is_empty() {
+            return;
+        }
+        if let Some(pos) = self
+            .entries
+            .iter()
+            .position(|e| e.is_same_content(&clipboards))
+        {
+            if let Some(mut entry) = self.entries.remove(pos) {
+                entry.time = hbb_common::get_time();
+                self.entries.push_back(entry);
+            }
+            return;
+        }
+        self.next_id += 1;
+        self.entries.push_back(ClipboardHistoryEntry {
+            id: self.next_id,
+            time: hbb_common::get_time(),
+            side,
+            clipboards,
+        });
+        let mut size: usize = self.entries.iter().map(|e| e.size()).sum();
+        while self.entries.len() > CLIPBOARD_HISTORY_MAX_ENTRIES
+            || (size > CLIPBOARD_HISTORY_MAX_BYTES && self.entries.len() > 1)
+        {
+            if let Some(e) = self.entries.pop_front() {
+                size -= e.size();
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<&ClipboardHistoryEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The summaries of the entries as a json array, the latest first.
+    pub fn to_json(&self) -> String {
+        serde_json::Value::Array(self.entries.iter().rev().map(|e| e.to_json()).collect())
+            .to_string()
+    }
+}
+
 pub fn start_clipbard_master_thread(
     handler: impl ClipboardHandler + Send + 'static,
     tx_start_res: Sender<(Option<Shutdown>, String)>,
@@ -597,3 +749,109 @@ mod proto {
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Clipboard {
+        Clipboard {
+            format: hbb_common::message_proto::ClipboardFormat::Text.into(),
+            content: s.as_bytes().to_vec().into(),
+            ..Default::default()
+        }
+    }
+
+    fn owner(side: ClipboardSide) -> Clipboard {
+        Clipboard {
+            format: hbb_common::message_proto::ClipboardFormat::Special.into(),
+            special_name: RUSTDESK_CLIPBOARD_OWNER_FORMAT.to_owned(),
+            content: side.get_owner_data().into(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(history: &ClipboardHistory) -> Vec<u64> {
+        history.entries.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn test_clipboard_history_dedupe() {
+        let mut history = ClipboardHistory::default();
+        history.push(vec![text("a")], ClipboardSide::Host);
+        history.push(vec![text("b")], ClipboardSide::Client);
+        history.push(vec![text("a")], ClipboardSide::Client);
+        // moved to the latest, the id and the side are kept
+        assert_eq!(ids(&history), vec![2, 1]);
+        assert_eq!(history.get(1).unwrap().side, ClipboardSide::Host);
+        history.push(vec![text("a"), text("b")], ClipboardSide::Host);
+        assert_eq!(ids(&history), vec![2, 1, 3]);
+        history.push(vec![], ClipboardSide::Host);
+        assert_eq!(ids(&history), vec![2, 1, 3]);
+        history.clear();
+        assert!(history.get(1).is_none());
+    }
+
+    #[test]
+    fn test_clipboard_history_eviction() {
+        let mut history = ClipboardHistory::default();
+        for i in 0..CLIPBOARD_HISTORY_MAX_ENTRIES + 5 {
+            history.push(vec![text(&i.to_string())], ClipboardSide::Host);
+        }
+        assert_eq!(history.entries.len(), CLIPBOARD_HISTORY_MAX_ENTRIES);
+        assert!(history.get(5).is_none());
+        assert!(history.get(6).is_some());
+
+        let mut history = ClipboardHistory::default();
+        let large = |c: char| text(&c.to_string().repeat(CLIPBOARD_HISTORY_MAX_BYTES / 2));
+        history.push(vec![large('a')], ClipboardSide::Host);
+        history.push(vec![large('b')], ClipboardSide::Host);
+        assert_eq!(ids(&history), vec![1, 2]);
+        history.push(vec![text("c")], ClipboardSide::Host);
+        assert_eq!(ids(&history), vec![2, 3]);
+        // the latest one is kept even if it is too large
+        history.push(
+            vec![text(&"d".repeat(CLIPBOARD_HISTORY_MAX_BYTES + 1))],
+            ClipboardSide::Host,
+        );
+        assert_eq!(ids(&history), vec![4]);
+    }
+
+    #[test]
+    fn test_clipboard_history_owner() {
+        let mut history = ClipboardHistory::default();
+        history.push(vec![owner(ClipboardSide::Host)], ClipboardSide::Host);
+        assert!(history.entries.is_empty());
+        history.push(
+            vec![text("a"), owner(ClipboardSide::Host)],
+            ClipboardSide::Host,
+        );
+        history.push(
+            vec![text("a"), owner(ClipboardSide::Client)],
+            ClipboardSide::Client,
+        );
+        assert_eq!(ids(&history), vec![1]);
+        assert_eq!(history.get(1).unwrap().clipboards.len(), 1);
+    }
+
+    #[test]
+    fn test_clipboard_history_image() {
+        let image = Clipboard {
+            format: hbb_common::message_proto::ClipboardFormat::ImagePng.into(),
+            content: vec![0; 16].into(),
+            width: 2,
+            height: 2,
+            ..Default::default()
+        };
+        let mut history = ClipboardHistory::default();
+        history.push(vec![text("a")], ClipboardSide::Host);
+        history.push(vec![text("b"), image], ClipboardSide::Host);
+        assert!(!history.get(1).unwrap().is_image());
+        let entry = history.get(2).unwrap();
+        assert!(entry.is_image());
+        assert_eq!(
+            entry.to_json()["formats"],
+            serde_json::json!(["text", "image"])
+        );
+    }
+}
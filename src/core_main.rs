@@ -336,6 +336,9 @@ pub fn core_main() -> Option<Vec<String>> {
         } else if args[0] == "--get-id" {
             println!("{}", crate::ipc::get_id());
             return None;
+        } else if args[0] == "--get-discovery-key" {
+            println!("{}", crate::lan::get_discovery_key());
+            return None;
         } else if args[0] == "--set-id" {
             if args.len() == 2 {
                 if crate::platform::is_installed() && is_root() {
@@ -387,6 +390,34 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--list-login-bans" {
+            if crate::platform::is_installed() && is_root() {
+                match crate::ipc::get_login_bans() {
+                    Ok(bans) => {
+                        for ban in bans {
+                            let until = if ban.until == 0 {
+                                "locked".to_owned()
+                            } else {
+                                format!("{}s", (ban.until - hbb_common::get_time()) / 1000)
+                            };
+                            println!("{}\t{}\t{}\t{}", ban.ip, ban.kind, until, ban.total);
+                        }
+                    }
+                    Err(err) => println!("{}", err),
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
+        } else if args[0] == "--clear-login-bans" {
+            if crate::platform::is_installed() && is_root() {
+                if let Err(err) = crate::ipc::clear_login_bans(args.get(1).cloned()) {
+                    println!("{}", err);
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--assign" {
             if crate::platform::is_installed() && is_root() {
                 let max = args.len() - 1;
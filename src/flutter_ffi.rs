@@ -361,6 +361,42 @@ pub fn session_set_image_quality(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_get_clipboard_history(_session_id: SessionID) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return session.get_clipboard_history();
+    }
+    "[]".to_owned()
+}
+
+pub fn session_push_clipboard_history(_session_id: SessionID, _id: u64, _to_peer: bool) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.push_clipboard_history(_id, _to_peer);
+    }
+}
+
+pub fn session_clear_clipboard_history(_session_id: SessionID) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.clear_clipboard_history();
+    }
+}
+
+pub fn session_get_audio_profile(session_id: SessionID) -> Option<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        Some(session.get_audio_profile())
+    } else {
+        None
+    }
+}
+
+pub fn session_set_audio_profile(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.save_audio_profile(value);
+    }
+}
+
 pub fn session_get_keyboard_mode(session_id: SessionID) -> Option<String> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_keyboard_mode())
@@ -1066,6 +1102,10 @@ pub fn main_forget_password(id: String) {
     forget_password(id)
 }
 
+pub fn main_forget_pinned_key(id: String) {
+    forget_pinned_key(id)
+}
+
 pub fn main_peer_has_password(id: String) -> bool {
     peer_has_password(id)
 }
@@ -1323,6 +1363,41 @@ pub fn session_remove_port_forward(session_id: SessionID, local_port: i32) {
     }
 }
 
+pub fn session_get_port_forward_profiles(session_id: SessionID) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_port_forward_profiles()
+    } else {
+        "".to_owned()
+    }
+}
+
+pub fn session_save_port_forward_profile(session_id: SessionID, profile: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        match serde_json::from_str(&profile) {
+            Ok(profile) => session.save_port_forward_profile(profile),
+            Err(err) => log::error!("Invalid port forward profile {}: {}", profile, err),
+        }
+    }
+}
+
+pub fn session_remove_port_forward_profile(session_id: SessionID, name: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.remove_port_forward_profile(name);
+    }
+}
+
+pub fn session_start_port_forward_profile(session_id: SessionID, name: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.start_port_forward_profile(name);
+    }
+}
+
+pub fn session_stop_port_forward_profile(session_id: SessionID, name: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.stop_port_forward_profile(name);
+    }
+}
+
 pub fn session_new_rdp(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.new_rdp();
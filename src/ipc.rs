@@ -7,6 +7,8 @@ use parity_tokio_ipc::{
     Connection as Conn, ConnectionClient as ConnClient, Endpoint, Incoming, SecurityAttributes,
 };
 use serde_derive::{Deserialize, Serialize};
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex};
 use std::{
     collections::HashMap,
     sync::atomic::{AtomicBool, Ordering},
@@ -80,6 +82,7 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        copy_blks: Vec<u32>,
     },
     WriteDone {
         id: i32,
@@ -101,6 +104,8 @@ pub enum FS {
         file_size: u64,
         last_modified: u64,
         is_upload: bool,
+        resumable: bool,
+        delta_sync: bool,
     },
     Rename {
         id: i32,
@@ -270,6 +275,8 @@ pub enum Data {
     HwCodecConfig(Option<String>),
     RemoveTrustedDevices(Vec<Bytes>),
     ClearTrustedDevices,
+    LoginBans(Option<Vec<crate::server::login_failure::LoginBan>>),
+    ClearLoginBans(Option<String>),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -336,7 +343,7 @@ pub async fn new_listener(postfix: &str) -> ResultType<Incoming> {
     }
 }
 
-pub struct CheckIfRestart(String, Vec<String>, String, String);
+pub struct CheckIfRestart(String, Vec<String>, String, String, Vec<String>);
 
 impl CheckIfRestart {
     pub fn new() -> CheckIfRestart {
@@ -345,6 +352,7 @@ impl CheckIfRestart {
             Config::get_rendezvous_servers(),
             Config::get_option("audio-input"),
             Config::get_option("voice-call-input"),
+            crate::audio_service::get_mix_options(),
         )
     }
 }
@@ -355,7 +363,9 @@ impl Drop for CheckIfRestart {
         {
             RendezvousMediator::restart();
         }
-        if self.2 != Config::get_option("audio-input") {
+        if self.2 != Config::get_option("audio-input")
+            || self.4 != crate::audio_service::get_mix_options()
+        {
             crate::audio_service::restart();
         }
         if self.3 != Config::get_option("voice-call-input") {
@@ -529,8 +539,15 @@ async fn handle(data: Data, stream: &mut Connection) {
                 let v = Config::get_options();
                 allow_err!(stream.send(&Data::Options(Some(v))).await);
             }
-            Some(value) => {
+            Some(mut value) => {
                 let _chk = CheckIfRestart::new();
+                value.retain(|k, _| {
+                    let root = config::keys::KEYS_ROOT_SETTINGS.contains(&k.as_str());
+                    if root {
+                        log::warn!("Refused to set the root option {} over ipc", k);
+                    }
+                    !root
+                });
                 if let Some(v) = value.get("privacy-mode-impl-key") {
                     crate::privacy_mode::switch(v);
                 }
@@ -657,6 +674,14 @@ async fn handle(data: Data, stream: &mut Connection) {
         Data::ClearTrustedDevices => {
             Config::clear_trusted_devices();
         }
+        Data::LoginBans(None) => {
+            let bans = crate::server::login_failure::list_bans();
+            allow_err!(stream.send(&Data::LoginBans(Some(bans))).await);
+        }
+        Data::ClearLoginBans(ip) => {
+            crate::server::login_failure::clear_bans(ip.as_deref());
+            allow_err!(stream.send(&Data::ClearLoginBans(None)).await);
+        }
         _ => {}
     }
 }
@@ -680,10 +705,23 @@ pub async fn start_pa() {
                         Ok(stream) => {
                             let mut stream = Connection::new(stream);
                             let mut device: String = "".to_owned();
-                            if let Some(Ok(Some(Data::Config((_, Some(x)))))) =
+                            // the microphone mixed with the device, and the volumes of both
+                            let mut mix: Option<(String, f32, f32)> = None;
+                            if let Some(Ok(Some(Data::Config((name, Some(x)))))) =
                                 stream.next_timeout2(1000).await
                             {
-                                device = x;
+                                if name == "audio-mix" {
+                                    let v: serde_json::Value =
+                                        serde_json::from_str(&x).unwrap_or_default();
+                                    let get =
+                                        |k: &str| v[k].as_str().unwrap_or_default().to_owned();
+                                    let volume =
+                                        |i: usize| v["volumes"][i].as_f64().unwrap_or(1.) as f32;
+                                    device = get("input");
+                                    mix = Some((get("mix_input"), volume(0), volume(1)));
+                                } else {
+                                    device = x;
+                                }
                             }
                             if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
@@ -694,35 +732,52 @@ pub async fn start_pa() {
                             if device.is_empty() {
                                 continue;
                             }
-                            let spec = pulse::sample::Spec {
-                                format: pulse::sample::Format::F32le,
-                                channels: 2,
-                                rate: crate::platform::PA_SAMPLE_RATE,
-                            };
                             log::info!("pa monitor: {:?}", device);
                             // systemctl --user status pulseaudio.service
                             let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
-                            match psimple::Simple::new(
-                                None,                             // Use the default server
-                                &crate::get_app_name(),           // Our application’s name
-                                pulse::stream::Direction::Record, // We want a record stream
-                                Some(&device),                    // Use the default device
-                                "record",                         // Description of our stream
-                                &spec,                            // Our sample format
-                                None,                             // Use default channel map
-                                None, // Use default buffering attributes
-                            ) {
-                                Ok(s) => loop {
+                            let mix = mix.map(|(mix_device, volume, mix_volume)| {
+                                let mixer =
+                                    Arc::new(Mutex::new(crate::audio_service::Mixer::new([
+                                        volume, mix_volume,
+                                    ])));
+                                let stop = Arc::new(AtomicBool::new(false));
+                                start_pa_mix_input(mix_device, mixer.clone(), stop.clone());
+                                (
+                                    mixer,
+                                    crate::common::SimpleCallOnReturn {
+                                        b: true,
+                                        f: Box::new(move || stop.store(true, Ordering::SeqCst)),
+                                    },
+                                )
+                            });
+                            match new_pa_record(&device) {
+                                Ok(s) => 'record: loop {
                                     if let Ok(_) = s.read(&mut buf) {
-                                        let out =
-                                            if buf.iter().filter(|x| **x != 0).next().is_none() {
+                                        let mut frames = vec![];
+                                        match mix.as_ref() {
+                                            Some((mixer, _)) => {
+                                                let data = pa_bytes_to_f32(&buf);
+                                                for frame in mixer.lock().unwrap().push(0, &data) {
+                                                    frames.push(
+                                                        frame
+                                                            .iter()
+                                                            .flat_map(|x| x.to_le_bytes())
+                                                            .collect::<Vec<u8>>(),
+                                                    );
+                                                }
+                                            }
+                                            None => frames.push(buf.clone()),
+                                        }
+                                        for frame in frames {
+                                            let out = if frame.iter().all(|x| *x == 0) {
                                                 vec![]
                                             } else {
-                                                buf.clone()
+                                                frame
                                             };
-                                        if let Err(err) = stream.send_raw(out.into()).await {
-                                            log::error!("Failed to send audio data:{}", err);
-                                            break;
+                                            if let Err(err) = stream.send_raw(out.into()).await {
+                                                log::error!("Failed to send audio data:{}", err);
+                                                break 'record;
+                                            }
                                         }
                                     }
                                 },
@@ -744,6 +799,64 @@ pub async fn start_pa() {
     }
 }
 
+// Record the microphone mixed with the system audio in a separate thread, the system audio paces
+// the mix, the microphone buffered ahead of it is dropped by the mixer.
+#[cfg(target_os = "linux")]
+fn start_pa_mix_input(
+    device: String,
+    mixer: Arc<Mutex<crate::audio_service::Mixer>>,
+    stop: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        // empty for the default source
+        let device = crate::platform::linux::get_pa_source_name(&device);
+        log::info!("pa mix: {:?}", device);
+        let s = match new_pa_record(&device) {
+            Ok(s) => s,
+            Err(err) => {
+                log::error!("Could not create simple pulse: {}", err);
+                return;
+            }
+        };
+        let mut buf: Vec<u8> = vec![0; crate::audio_service::AUDIO_DATA_SIZE_U8];
+        while !stop.load(Ordering::SeqCst) {
+            if s.read(&mut buf).is_ok() {
+                mixer.lock().unwrap().push(1, &pa_bytes_to_f32(&buf));
+            }
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn new_pa_record(device: &str) -> Result<psimple::Simple, pulse::error::PAErr> {
+    let spec = pulse::sample::Spec {
+        format: pulse::sample::Format::F32le,
+        channels: 2,
+        rate: crate::platform::PA_SAMPLE_RATE,
+    };
+    psimple::Simple::new(
+        None,                             // Use the default server
+        &crate::get_app_name(),           // Our application’s name
+        pulse::stream::Direction::Record, // We want a record stream
+        if device.is_empty() {
+            None // Use the default device
+        } else {
+            Some(device)
+        },
+        "record", // Description of our stream
+        &spec,    // Our sample format
+        None,     // Use default channel map
+        None,     // Use default buffering attributes
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn pa_bytes_to_f32(buf: &[u8]) -> Vec<f32> {
+    buf.chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect()
+}
+
 #[inline]
 #[cfg(not(windows))]
 fn get_pid_file(postfix: &str) -> String {
@@ -987,6 +1100,24 @@ pub fn clear_trusted_devices() {
     allow_err!(set_data(&Data::ClearTrustedDevices));
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_login_bans() -> ResultType<Vec<crate::server::login_failure::LoginBan>> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::LoginBans(None)).await?;
+    if let Some(Data::LoginBans(Some(bans))) = c.next_timeout(1000).await? {
+        return Ok(bans);
+    }
+    bail!("Failed to get login bans");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn clear_login_bans(ip: Option<String>) -> ResultType<()> {
+    let mut c = connect(1000, "").await?;
+    c.send(&Data::ClearLoginBans(ip)).await?;
+    c.next_timeout(1000).await?;
+    Ok(())
+}
+
 pub fn get_id() -> String {
     if let Ok(Some(v)) = get_config("id") {
         // update salt also, so that next time reinstallation not causing first-time auto-login failure
@@ -2,10 +2,15 @@ use hbb_common::config::Config;
 use hbb_common::{
     allow_err,
     anyhow::bail,
-    config::{self, RENDEZVOUS_PORT},
-    log,
+    base64::{engine::general_purpose::STANDARD, Engine as _},
+    config::{self, keys, RENDEZVOUS_PORT},
+    get_time, log,
     protobuf::Message as _,
     rendezvous_proto::*,
+    sodiumoxide::{
+        crypto::{auth, hash, sign},
+        randombytes::randombytes,
+    },
     tokio::{
         self,
         sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
@@ -13,21 +18,41 @@ use hbb_common::{
     ResultType,
 };
 
+use mdns_sd::{ServiceDaemon, ServiceEvent};
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 type Message = RendezvousMessage;
 
+// DNS-SD service advertised besides answering the broadcast ping,
+// for networks dropping broadcast or without ipv4.
+const MDNS_SERVICE_TYPE: &str = "_rustdesk._tcp.local.";
+const MDNS_BROWSE_TIMEOUT: Duration = Duration::from_secs(3);
+#[cfg(not(target_os = "ios"))]
+const MDNS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+// Pings older than this are rejected when replies are restricted.
+const PING_TIME_TOLERANCE_MS: i64 = 30_000;
+// The mac addresses woken at once, they may come from the peer relaying Wake-on-LAN.
+const MAX_WOL_MACS: usize = 16;
+
 #[cfg(not(target_os = "ios"))]
 pub(super) fn start_listening() -> ResultType<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
     let socket = std::net::UdpSocket::bind(addr)?;
     socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
     log::info!("lan discovery listener started");
+    let mut mdns: Option<MdnsService> = None;
+    let mut mdns_refresh_time: Option<Instant> = None;
+    // nonces of the accepted pings, to reject replayed ones
+    let mut ping_nonces: HashMap<Vec<u8>, i64> = HashMap::new();
     loop {
+        if mdns_refresh_time.map_or(true, |t| t.elapsed() >= MDNS_REFRESH_INTERVAL) {
+            mdns_refresh_time = Some(Instant::now());
+            refresh_mdns(&mut mdns);
+        }
         let mut buf = [0; 2048];
         if let Ok((len, addr)) = socket.recv_from(&mut buf) {
             if let Ok(msg_in) = Message::parse_from_bytes(&buf[0..len]) {
@@ -43,22 +68,37 @@ pub(super) fn start_listening() -> ResultType<()> {
                             if p.id == id {
                                 continue;
                             }
+                            let allowed_keys = get_allowed_keys();
+                            let group_key = get_group_key();
+                            if !is_ping_allowed(
+                                &p,
+                                &mut ping_nonces,
+                                &allowed_keys,
+                                group_key.as_ref(),
+                            ) {
+                                log::debug!("Ignore unauthorized discovery ping from {}", addr);
+                                continue;
+                            }
+                            // Only the authorized pings get the username.
+                            let username = if allowed_keys.is_empty() && group_key.is_none() {
+                                "".to_owned()
+                            } else {
+                                crate::platform::get_active_username()
+                            };
                             if let Some(self_addr) = get_ipaddr_by_peer(&addr) {
                                 let mut msg_out = Message::new();
-                                let mut hostname = whoami::hostname();
-                                // The default hostname is "localhost" which is a bit confusing
-                                if hostname == "localhost" {
-                                    hostname = "unknown".to_owned();
-                                }
-                                let peer = PeerDiscovery {
+                                let mut peer = PeerDiscovery {
                                     cmd: "pong".to_owned(),
                                     mac: get_mac(&self_addr),
                                     id,
-                                    hostname,
-                                    username: crate::platform::get_active_username(),
+                                    hostname: get_hostname(),
+                                    username,
                                     platform: whoami::platform().to_string(),
+                                    nonce: p.nonce,
+                                    time: get_time(),
                                     ..Default::default()
                                 };
+                                sign_discovery(&mut peer);
                                 msg_out.set_peer_discovery(peer);
                                 socket.send_to(&msg_out.write_to_bytes()?, addr).ok();
                             }
@@ -73,8 +113,16 @@ pub(super) fn start_listening() -> ResultType<()> {
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
-    let sockets = send_query()?;
-    let rx = spawn_wait_responses(sockets);
+    let (tx, rx) = unbounded_channel::<_>();
+    let (ping, nonce) = new_ping()?;
+    // Do not give up if there is no ipv4, mdns may still work.
+    match send_query(&ping) {
+        Ok(sockets) => spawn_wait_responses(sockets, nonce.clone(), tx.clone()),
+        Err(err) => log::error!("Failed to send discover ping: {}", err),
+    }
+    std::thread::spawn(move || {
+        allow_err!(browse_mdns(tx, ping, nonce));
+    });
     handle_received_peers(rx).await?;
 
     log::info!("discover ping done");
@@ -82,24 +130,72 @@ pub async fn discover() -> ResultType<()> {
 }
 
 pub fn send_wol(id: String) {
+    send_wol_to_macs(&get_lan_peer_macs(&id));
+}
+
+/// The mac addresses of the LAN peer discovered before.
+pub fn get_lan_peer_macs(id: &str) -> Vec<String> {
+    get_peer_macs(&config::LanPeers::load().peers, id)
+}
+
+fn get_peer_macs(peers: &[config::DiscoveryPeer], id: &str) -> Vec<String> {
+    if id.is_empty() {
+        return vec![];
+    }
+    match peers.iter().find(|x| x.id == id) {
+        Some(peer) => normalize_macs(peer.ip_mac.values()),
+        None => vec![],
+    }
+}
+
+/// The valid mac addresses as `aa:bb:cc:dd:ee:ff`, without duplicates, at most `MAX_WOL_MACS`.
+pub fn normalize_macs<'a>(macs: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    for mac in macs {
+        let octets: Vec<&str> = mac.trim().split(|c| c == ':' || c == '-').collect();
+        if octets.len() != 6
+            || octets
+                .iter()
+                .any(|x| x.len() != 2 || !x.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            continue;
+        }
+        let mac = octets.join(":").to_lowercase();
+        if !res.contains(&mac) {
+            res.push(mac);
+            if res.len() == MAX_WOL_MACS {
+                break;
+            }
+        }
+    }
+    res
+}
+
+/// Send the magic packets from all the ipv4 interfaces, returns the number of packets sent.
+/// The invalid mac addresses are ignored.
+pub fn send_wol_to_macs(macs: &[String]) -> usize {
+    let macs = normalize_macs(macs);
+    if macs.is_empty() {
+        return 0;
+    }
     let interfaces = default_net::get_interfaces();
-    for peer in &config::LanPeers::load().peers {
-        if peer.id == id {
-            for (_, mac) in peer.ip_mac.iter() {
-                if let Ok(mac_addr) = mac.parse() {
-                    for interface in &interfaces {
-                        for ipv4 in &interface.ipv4 {
-                            // remove below mask check to avoid unexpected bug
-                            // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
-                            log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
-                            allow_err!(wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))));
-                        }
+    let mut sent = 0;
+    for mac in &macs {
+        if let Ok(mac_addr) = mac.parse() {
+            for interface in &interfaces {
+                for ipv4 in &interface.ipv4 {
+                    // remove below mask check to avoid unexpected bug
+                    // if (u32::from(ipv4.addr) & u32::from(ipv4.netmask)) == (u32::from(peer_ip) & u32::from(ipv4.netmask))
+                    log::info!("Send wol to {mac_addr} of {}", ipv4.addr);
+                    match wol::send_wol(mac_addr, None, Some(IpAddr::V4(ipv4.addr))) {
+                        Ok(_) => sent += 1,
+                        Err(err) => log::error!("Failed to send wol to {}: {}", mac, err),
                     }
                 }
             }
-            break;
         }
     }
+    sent
 }
 
 #[inline]
@@ -107,6 +203,246 @@ fn get_broadcast_port() -> u16 {
     (RENDEZVOUS_PORT + 3) as _
 }
 
+fn get_hostname() -> String {
+    let hostname = whoami::hostname();
+    // The default hostname is "localhost" which is a bit confusing
+    if hostname == "localhost" {
+        "unknown".to_owned()
+    } else {
+        hostname
+    }
+}
+
+// The bytes signed, i.e. the message without the signature and the group tag.
+fn signing_bytes(p: &PeerDiscovery) -> Vec<u8> {
+    let mut p = p.clone();
+    p.signature = Default::default();
+    p.group_tag = Default::default();
+    p.write_to_bytes().unwrap_or_default()
+}
+
+fn get_group_key() -> Option<auth::Key> {
+    let key = Config::get_option(keys::OPTION_LAN_DISCOVERY_GROUP_KEY);
+    if key.is_empty() {
+        return None;
+    }
+    auth::Key::from_slice(&hash::sha256::hash(key.as_bytes()).0)
+}
+
+// base64 public keys separated by comma
+fn get_allowed_keys() -> Vec<Vec<u8>> {
+    Config::get_option(keys::OPTION_LAN_DISCOVERY_ALLOWED_KEYS)
+        .split(',')
+        .filter_map(|x| STANDARD.decode(x.trim()).ok())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// The public key the discovery messages of this device are signed with.
+pub fn get_discovery_key() -> String {
+    STANDARD.encode(Config::get_key_pair().1)
+}
+
+fn sign_discovery(p: &mut PeerDiscovery) {
+    let (sk, pk) = Config::get_key_pair();
+    sign_discovery_with(p, &sk, pk, get_group_key().as_ref());
+}
+
+fn sign_discovery_with(
+    p: &mut PeerDiscovery,
+    sk: &[u8],
+    pk: Vec<u8>,
+    group_key: Option<&auth::Key>,
+) {
+    p.pk = pk.into();
+    let data = signing_bytes(p);
+    if let Some(key) = group_key {
+        p.group_tag = auth::authenticate(&data, key).0.to_vec().into();
+    }
+    if let Some(sk) = sign::SecretKey::from_slice(sk) {
+        // the signed message is the signature followed by the data
+        let signed = sign::sign(&data, &sk);
+        p.signature = signed[..sign::SIGNATUREBYTES].to_vec().into();
+    }
+}
+
+fn verify_signature(p: &PeerDiscovery) -> bool {
+    let pk = match sign::PublicKey::from_slice(&p.pk) {
+        Some(pk) => pk,
+        None => return false,
+    };
+    if p.signature.len() != sign::SIGNATUREBYTES {
+        return false;
+    }
+    let mut signed = p.signature.to_vec();
+    signed.extend(signing_bytes(p));
+    sign::verify(&signed, &pk).is_ok()
+}
+
+fn verify_group_tag(p: &PeerDiscovery, key: &auth::Key) -> bool {
+    match auth::Tag::from_slice(&p.group_tag) {
+        Some(tag) => auth::verify(&tag, &signing_bytes(p), key),
+        None => false,
+    }
+}
+
+fn is_ping_allowed(
+    p: &PeerDiscovery,
+    nonces: &mut HashMap<Vec<u8>, i64>,
+    allowed_keys: &[Vec<u8>],
+    group_key: Option<&auth::Key>,
+) -> bool {
+    if allowed_keys.is_empty() && group_key.is_none() {
+        return true;
+    }
+    let now = get_time();
+    nonces.retain(|_, t| now - *t <= PING_TIME_TOLERANCE_MS);
+    if (now - p.time).abs() > PING_TIME_TOLERANCE_MS
+        || p.nonce.is_empty()
+        || nonces.contains_key(&p.nonce[..])
+    {
+        return false;
+    }
+    let allowed = (verify_signature(p) && allowed_keys.iter().any(|k| k[..] == p.pk[..]))
+        || group_key.map_or(false, |k| verify_group_tag(p, k));
+    if allowed {
+        nonces.insert(p.nonce.to_vec(), now);
+    }
+    allowed
+}
+
+// The unsigned replies of older versions are rejected, the key is pinned on first use later.
+fn is_pong_trusted(p: &PeerDiscovery, nonce: &[u8], group_key: Option<&auth::Key>) -> bool {
+    if p.signature.is_empty() || p.nonce[..] != nonce[..] {
+        return false;
+    }
+    verify_signature(p) && group_key.map_or(true, |k| verify_group_tag(p, k))
+}
+
+fn new_discovery_peer(p: &PeerDiscovery, ip_mac: HashMap<String, String>) -> config::DiscoveryPeer {
+    config::DiscoveryPeer {
+        id: p.id.clone(),
+        ip_mac,
+        username: p.username.clone(),
+        hostname: p.hostname.clone(),
+        platform: p.platform.clone(),
+        online: true,
+        pk: if p.pk.is_empty() {
+            "".to_owned()
+        } else {
+            STANDARD.encode(&p.pk)
+        },
+    }
+}
+
+#[cfg(not(target_os = "ios"))]
+struct MdnsService {
+    daemon: ServiceDaemon,
+    fullname: String,
+    properties: HashMap<String, String>,
+}
+
+#[cfg(not(target_os = "ios"))]
+impl MdnsService {
+    fn register(properties: HashMap<String, String>) -> ResultType<Self> {
+        let id = properties.get("id").cloned().unwrap_or_default();
+        let daemon = ServiceDaemon::new()?;
+        // Addresses of all the interfaces, both ipv4 and ipv6, are filled by the daemon.
+        let info = mdns_sd::ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &id,
+            &format!("{}.local.", id),
+            "",
+            crate::rendezvous_mediator::get_direct_port() as _,
+            properties.clone(),
+        )?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_owned();
+        daemon.register(info)?;
+        log::info!("mdns service {} registered", fullname);
+        Ok(Self {
+            daemon,
+            fullname,
+            properties,
+        })
+    }
+}
+
+#[cfg(not(target_os = "ios"))]
+impl Drop for MdnsService {
+    fn drop(&mut self) {
+        self.daemon.unregister(&self.fullname).ok();
+        self.daemon.shutdown().ok();
+    }
+}
+
+// Register, update or unregister the mdns service according to the option and the current info.
+#[cfg(not(target_os = "ios"))]
+fn refresh_mdns(mdns: &mut Option<MdnsService>) {
+    if !config::option2bool(
+        "enable-lan-discovery",
+        &Config::get_option("enable-lan-discovery"),
+    ) {
+        mdns.take();
+        return;
+    }
+    // The records are public, only the id is published, the browsers have to send a ping to
+    // get the details.
+    let properties = HashMap::from([("id".to_owned(), Config::get_id())]);
+    if mdns.as_ref().map(|x| &x.properties) == Some(&properties) {
+        return;
+    }
+    // drop the old one first to unregister it
+    mdns.take();
+    match MdnsService::register(properties) {
+        Ok(service) => *mdns = Some(service),
+        Err(err) => log::error!("Failed to register mdns service: {}", err),
+    }
+}
+
+// Browse the mdns services and ping the found ones directly to get the details.
+fn browse_mdns(
+    tx: UnboundedSender<config::DiscoveryPeer>,
+    ping: Vec<u8>,
+    nonce: Vec<u8>,
+) -> ResultType<()> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let self_id = crate::ui_interface::get_id();
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let start = Instant::now();
+    while start.elapsed() < MDNS_BROWSE_TIMEOUT {
+        let info = match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(ServiceEvent::ServiceResolved(info)) => info,
+            _ => continue,
+        };
+        let id = info.get_property_val_str("id").unwrap_or_default();
+        if id.is_empty() {
+            continue;
+        }
+        #[cfg(any(target_os = "android", target_os = "ios"))]
+        if id == self_id {
+            continue;
+        }
+        for ip in info.get_addresses() {
+            if ip.is_ipv4() {
+                let addr = SocketAddr::new(*ip, get_broadcast_port());
+                allow_err!(socket.send_to(&ping, addr));
+            }
+        }
+    }
+    daemon.stop_browse(MDNS_SERVICE_TYPE).ok();
+    daemon.shutdown().ok();
+    log::info!("mdns browse done");
+    wait_response(
+        socket,
+        Some(std::time::Duration::from_millis(10)),
+        &nonce,
+        tx,
+    )
+}
+
 fn get_mac(_ip: &IpAddr) -> String {
     #[cfg(not(target_os = "ios"))]
     if let Ok(mac) = get_mac_by_ip(_ip) {
@@ -183,12 +519,8 @@ fn create_broadcast_sockets() -> Vec<UdpSocket> {
     sockets
 }
 
-fn send_query() -> ResultType<Vec<UdpSocket>> {
-    let sockets = create_broadcast_sockets();
-    if sockets.is_empty() {
-        bail!("Found no bindable ipv4 addresses");
-    }
-
+// The signed ping and its nonce.
+fn new_ping() -> ResultType<(Vec<u8>, Vec<u8>)> {
     let mut msg_out = Message::new();
     // We may not be able to get the mac address on mobile platforms.
     // So we need to use the id to avoid discovering ourselves.
@@ -201,16 +533,27 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
     // We can use the mac address to identify the device.
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     let id = "".to_owned();
-    let peer = PeerDiscovery {
+    let nonce = randombytes(16);
+    let mut peer = PeerDiscovery {
         cmd: "ping".to_owned(),
         id,
+        nonce: nonce.clone().into(),
+        time: get_time(),
         ..Default::default()
     };
+    sign_discovery(&mut peer);
     msg_out.set_peer_discovery(peer);
-    let out = msg_out.write_to_bytes()?;
+    Ok((msg_out.write_to_bytes()?, nonce))
+}
+
+fn send_query(out: &[u8]) -> ResultType<Vec<UdpSocket>> {
+    let sockets = create_broadcast_sockets();
+    if sockets.is_empty() {
+        bail!("Found no bindable ipv4 addresses");
+    }
     let maddr = SocketAddr::from(([255, 255, 255, 255], get_broadcast_port()));
     for socket in &sockets {
-        allow_err!(socket.send_to(&out, maddr));
+        allow_err!(socket.send_to(out, maddr));
     }
     log::info!("discover ping sent");
     Ok(sockets)
@@ -219,6 +562,7 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
 fn wait_response(
     socket: UdpSocket,
     timeout: Option<std::time::Duration>,
+    nonce: &[u8],
     tx: UnboundedSender<config::DiscoveryPeer>,
 ) -> ResultType<()> {
     let mut last_recv_time = Instant::now();
@@ -229,6 +573,7 @@ fn wait_response(
         Ok(addr) => addr.ip().is_unspecified(),
     };
     let mut mac: Option<String> = None;
+    let group_key = get_group_key();
 
     socket.set_read_timeout(timeout)?;
     loop {
@@ -239,6 +584,10 @@ fn wait_response(
                     Some(rendezvous_message::Union::PeerDiscovery(p)) => {
                         last_recv_time = Instant::now();
                         if p.cmd == "pong" {
+                            if !is_pong_trusted(&p, nonce, group_key.as_ref()) {
+                                log::warn!("Ignore untrusted discovery reply from {}", addr);
+                                continue;
+                            }
                             let local_mac = if try_get_ip_by_peer {
                                 if let Some(self_addr) = get_ipaddr_by_peer(&addr) {
                                     get_mac(&self_addr)
@@ -261,16 +610,10 @@ fn wait_response(
                             };
 
                             if local_mac.is_empty() && p.mac.is_empty() || local_mac != p.mac {
-                                allow_err!(tx.send(config::DiscoveryPeer {
-                                    id: p.id.clone(),
-                                    ip_mac: HashMap::from([
-                                        (addr.ip().to_string(), p.mac.clone(),)
-                                    ]),
-                                    username: p.username.clone(),
-                                    hostname: p.hostname.clone(),
-                                    platform: p.platform.clone(),
-                                    online: true,
-                                }));
+                                allow_err!(tx.send(new_discovery_peer(
+                                    &p,
+                                    HashMap::from([(addr.ip().to_string(), p.mac.clone())]),
+                                )));
                             }
                         }
                     }
@@ -285,19 +628,23 @@ fn wait_response(
     Ok(())
 }
 
-fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::DiscoveryPeer> {
-    let (tx, rx) = unbounded_channel::<_>();
+fn spawn_wait_responses(
+    sockets: Vec<UdpSocket>,
+    nonce: Vec<u8>,
+    tx: UnboundedSender<config::DiscoveryPeer>,
+) {
     for socket in sockets {
         let tx_clone = tx.clone();
+        let nonce = nonce.clone();
         std::thread::spawn(move || {
             allow_err!(wait_response(
                 socket,
                 Some(std::time::Duration::from_millis(10)),
+                &nonce,
                 tx_clone
             ));
         });
     }
-    rx
 }
 
 async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>) -> ResultType<()> {
@@ -312,6 +659,13 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
         tokio::select! {
             data = rx.recv() => match data {
                 Some(mut peer) => {
+                    // The key is pinned the first time a peer is discovered.
+                    if let Some(old) = peers.iter().find(|x| x.id == peer.id && !x.pk.is_empty()) {
+                        if old.pk != peer.pk {
+                            log::warn!("Ignore discovery reply of {} with a different key", peer.id);
+                            continue;
+                        }
+                    }
                     let in_response_set = !response_set.insert(peer.id.clone());
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
@@ -340,3 +694,122 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_signed(cmd: &str, nonce: &[u8], group_key: Option<&auth::Key>) -> PeerDiscovery {
+        let (pk, sk) = sign::gen_keypair();
+        let mut p = PeerDiscovery {
+            cmd: cmd.to_owned(),
+            id: "123456789".to_owned(),
+            hostname: "host".to_owned(),
+            nonce: nonce.to_vec().into(),
+            time: get_time(),
+            ..Default::default()
+        };
+        sign_discovery_with(&mut p, &sk.0, pk.0.to_vec(), group_key);
+        p
+    }
+
+    #[test]
+    fn test_pong_trusted() {
+        let nonce = randombytes(16);
+        let key = auth::gen_key();
+        let p = new_signed("pong", &nonce, None);
+        assert!(is_pong_trusted(&p, &nonce, None));
+        assert!(!is_pong_trusted(&p, &randombytes(16), None));
+        assert!(!is_pong_trusted(&p, &nonce, Some(&key)));
+        let mut tampered = p.clone();
+        tampered.hostname = "other".to_owned();
+        assert!(!is_pong_trusted(&tampered, &nonce, None));
+        let mut unsigned = p.clone();
+        unsigned.signature = Default::default();
+        assert!(!is_pong_trusted(&unsigned, &nonce, None));
+        let mut other_pk = p.clone();
+        other_pk.pk = sign::gen_keypair().0 .0.to_vec().into();
+        assert!(!is_pong_trusted(&other_pk, &nonce, None));
+
+        let p = new_signed("pong", &nonce, Some(&key));
+        assert!(is_pong_trusted(&p, &nonce, Some(&key)));
+        assert!(is_pong_trusted(&p, &nonce, None));
+        assert!(!is_pong_trusted(&p, &nonce, Some(&auth::gen_key())));
+    }
+
+    #[test]
+    fn test_ping_allowed() {
+        let mut nonces = HashMap::new();
+        let key = auth::gen_key();
+        let p = new_signed("ping", &randombytes(16), None);
+        assert!(is_ping_allowed(&p, &mut nonces, &[], None));
+        assert!(!is_ping_allowed(&p, &mut nonces, &[vec![0; 32]], None));
+        let allowed_keys = [p.pk.to_vec()];
+        assert!(is_ping_allowed(&p, &mut nonces, &allowed_keys, None));
+        // replayed
+        assert!(!is_ping_allowed(&p, &mut nonces, &allowed_keys, None));
+        let (pk, sk) = sign::gen_keypair();
+        let mut stale = PeerDiscovery {
+            cmd: "ping".to_owned(),
+            nonce: randombytes(16).into(),
+            time: get_time() - 2 * PING_TIME_TOLERANCE_MS,
+            ..Default::default()
+        };
+        sign_discovery_with(&mut stale, &sk.0, pk.0.to_vec(), None);
+        let allowed_keys = [pk.0.to_vec()];
+        assert!(!is_ping_allowed(&stale, &mut nonces, &allowed_keys, None));
+
+        let p = new_signed("ping", &randombytes(16), Some(&key));
+        assert!(is_ping_allowed(&p, &mut nonces, &[], Some(&key)));
+        let p = new_signed("ping", &randombytes(16), Some(&auth::gen_key()));
+        assert!(!is_ping_allowed(&p, &mut nonces, &[], Some(&key)));
+    }
+
+    #[test]
+    fn test_wol_macs() {
+        let macs = |v: &[&str]| v.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            normalize_macs(&macs(&[
+                "AA-BB-CC-DD-EE-FF",
+                " aa:bb:cc:dd:ee:ff ",
+                "00:11:22:33:44:55",
+                "",
+                "00:11:22:33:44",
+                "00:11:22:33:44:55:66",
+                "0g:11:22:33:44:55",
+                "+0:11:22:33:44:55",
+                "000:11:22:33:44:5",
+            ])),
+            macs(&["aa:bb:cc:dd:ee:ff", "00:11:22:33:44:55"])
+        );
+        let many: Vec<String> = (0..=255)
+            .map(|i| format!("00:11:22:33:44:{:02x}", i))
+            .collect();
+        assert_eq!(normalize_macs(&many).len(), MAX_WOL_MACS);
+        // nothing is sent for the invalid ones
+        assert_eq!(send_wol_to_macs(&macs(&["", "not a mac"])), 0);
+
+        let peers = vec![
+            config::DiscoveryPeer {
+                id: "123456789".to_owned(),
+                ip_mac: HashMap::from([
+                    ("192.168.1.2".to_owned(), "AA:BB:CC:DD:EE:FF".to_owned()),
+                    ("192.168.2.2".to_owned(), "aa:bb:cc:dd:ee:ff".to_owned()),
+                    ("10.0.0.2".to_owned(), "".to_owned()),
+                ]),
+                ..Default::default()
+            },
+            config::DiscoveryPeer {
+                ip_mac: HashMap::from([("192.168.1.3".to_owned(), "00:11:22:33:44:55".to_owned())]),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            get_peer_macs(&peers, "123456789"),
+            macs(&["aa:bb:cc:dd:ee:ff"])
+        );
+        assert!(get_peer_macs(&peers, "987654321").is_empty());
+        // not the peer without ID
+        assert!(get_peer_macs(&peers, "").is_empty());
+    }
+}
@@ -236,5 +236,6 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("enable-trusted-devices-tip", "Skip 2FA verification on trusted devices"),
         ("one-way-file-transfer-tip", "One-way file transfer is enabled on the controlled side."),
         ("web_id_input_tip", "You can input an ID in the same server, direct IP access is not supported in web client.\nIf you want to access a device on another server, please append the server address (<id>@<server_address>?key=<key_value>), for example,\n9123456234@192.168.16.1:21117?key=5Qbwsde3unUcJBtrx9ZkvUmwFNoExHzpryHuPUdqlWM=.\nIf you want to access a device on a public server, please input \"<id>@public\", the key is not needed for public server."),
+        ("xrandr_virtual_display_not_support_tip", "Virtual displays require an X11 session with RandR 1.5 or newer."),
     ].iter().cloned().collect();
 }
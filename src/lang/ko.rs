@@ -648,5 +648,6 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Authentication Required", "인증 필요함"),
         ("Authenticate", "인증"),
         ("web_id_input_tip", ""),
+        ("xrandr_virtual_display_not_support_tip", ""),
     ].iter().cloned().collect();
 }
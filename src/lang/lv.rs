@@ -648,5 +648,6 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Authentication Required", "Nepieciešama autentifikācija"),
         ("Authenticate", "Autentificēt"),
         ("web_id_input_tip", ""),
+        ("xrandr_virtual_display_not_support_tip", ""),
     ].iter().cloned().collect();
 }
@@ -45,6 +45,7 @@ mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+mod tunnel;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod clipboard;
 
@@ -66,5 +67,5 @@ pub mod clipboard_file;
 
 pub mod privacy_mode;
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 pub mod virtual_display_manager;
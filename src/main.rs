@@ -44,7 +44,18 @@ fn main() {
     use hbb_common::log;
     let args = format!(
         "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        --socks=[SOCKS-OPTIONS] 'Run a local SOCKS5 proxy through the peer, format: remote-id:local-port'
         -c, --connect=[REMOTE_ID] 'test only'
+        -f, --file-transfer=[REMOTE_ID] 'Transfer files, use with --ls, --get, --put or --rm'
+        --ls=[REMOTE_PATH] 'List the remote directory'
+        --get=[REMOTE_PATH] 'Download the remote file or directory to --to'
+        --put=[LOCAL_PATH] 'Upload the local file or directory to --to'
+        --rm=[REMOTE_PATH] 'Remove the remote file or directory'
+        --to=[PATH] 'Destination of --get or --put'
+        --wake=[REMOTE_ID] 'Wake the peer on LAN by the online peer of --via'
+        --via=[RELAY_ID] 'The online peer in the same LAN as the one to wake'
+        --mac=[MAC] 'Mac address of the peer to wake, optional if it has been discovered'
+        --password-file=[PATH] 'Read the password of the peer from the file, - for stdin, or set RUSTDESK_PASSWORD'
         -k, --key=[KEY] ''
        -s, --server=[] 'Start server'",
     );
@@ -56,6 +67,14 @@ fn main() {
         .get_matches();
     use hbb_common::{config::LocalConfig, env_logger::*};
     init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
+    // not taken from the arguments, which are visible to the other users
+    let password = match cli::read_password(matches.value_of("password-file")) {
+        Ok(password) => password,
+        Err(err) => {
+            log::error!("Failed to read the password: {}", err);
+            return;
+        }
+    };
     if let Some(p) = matches.value_of("port-forward") {
         let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
         if options.len() < 3 {
@@ -92,12 +111,80 @@ fn main() {
             key,
             token,
         );
+    } else if let Some(p) = matches.value_of("socks") {
+        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
+        if options.len() != 2 {
+            log::error!("Wrong socks options");
+            return;
+        }
+        let port = match options[1].parse::<i32>() {
+            Ok(v) if v > 0 => v,
+            _ => {
+                log::error!("Wrong local-port");
+                return;
+            }
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_socks(options[0].clone(), port, password, key, token);
     } else if let Some(p) = matches.value_of("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
         cli::connect_test(p, key, token);
+    } else if let Some(p) = matches.value_of("file-transfer") {
+        let to = matches.value_of("to").unwrap_or("").to_owned();
+        if (matches.is_present("get") || matches.is_present("put")) && to.is_empty() {
+            log::error!("--to is required");
+            return;
+        }
+        let command = if let Some(path) = matches.value_of("ls") {
+            cli::FileTransferCommand::List(path.to_owned())
+        } else if let Some(path) = matches.value_of("get") {
+            cli::FileTransferCommand::Download {
+                remote: path.to_owned(),
+                local: to,
+            }
+        } else if let Some(path) = matches.value_of("put") {
+            cli::FileTransferCommand::Upload {
+                local: path.to_owned(),
+                remote: to,
+            }
+        } else if let Some(path) = matches.value_of("rm") {
+            cli::FileTransferCommand::Remove(path.to_owned())
+        } else {
+            log::error!("One of --ls, --get, --put or --rm is required");
+            return;
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli::file_transfer(p.to_owned(), command, password, key, token);
+        common::global_clean();
+        std::process::exit(code);
+    } else if let Some(p) = matches.value_of("wake") {
+        let relay_id = match matches.value_of("via") {
+            Some(v) => v.to_owned(),
+            None => {
+                log::error!("--via is required");
+                return;
+            }
+        };
+        let macs = matches
+            .value_of("mac")
+            .map(|x| vec![x.to_owned()])
+            .unwrap_or_default();
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli::wake_on_lan(relay_id, p.to_owned(), macs, password, key, token);
+        common::global_clean();
+        std::process::exit(code);
     } else if let Some(p) = matches.value_of("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true, false);
@@ -217,6 +217,9 @@ impl PeerConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PluginStatus {
     pub enabled: bool,
+    // granted to wasm plugins, see `wasm.rs`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
 }
 
 const MANAGER_VERSION: &str = "0.1.0";
@@ -277,6 +280,12 @@ impl ManagerConfig {
                     .to_string();
                 Some(enabled)
             }
+            "capabilities" => Some(
+                lock.plugins
+                    .get(id)
+                    .map(|status| status.capabilities.join(","))
+                    .unwrap_or_default(),
+            ),
             _ => None,
         }
     }
@@ -286,7 +295,34 @@ impl ManagerConfig {
         if let Some(status) = lock.plugins.get_mut(id) {
             status.enabled = enabled;
         } else {
-            lock.plugins.insert(id.to_owned(), PluginStatus { enabled });
+            lock.plugins.insert(
+                id.to_owned(),
+                PluginStatus {
+                    enabled,
+                    capabilities: Vec::new(),
+                },
+            );
+        }
+        hbb_common::config::store_path(Self::path(), &*lock)
+    }
+
+    fn set_plugin_option_capabilities(id: &str, capabilities: &str) -> ResultType<()> {
+        let capabilities = capabilities
+            .split(',')
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect();
+        let mut lock = CONFIG_MANAGER.lock().unwrap();
+        if let Some(status) = lock.plugins.get_mut(id) {
+            status.capabilities = capabilities;
+        } else {
+            lock.plugins.insert(
+                id.to_owned(),
+                PluginStatus {
+                    enabled: true,
+                    capabilities,
+                },
+            );
         }
         hbb_common::config::store_path(Self::path(), &*lock)
     }
@@ -302,6 +338,13 @@ impl ManagerConfig {
                     super::unload_plugin(id);
                 }
             }
+            "capabilities" => {
+                allow_err!(Self::set_plugin_option_capabilities(id, value));
+                // The capabilities are applied when loading.
+                if Self::get_plugin_option(id, "enabled").as_deref() == Some("true") {
+                    allow_err!(super::reload_plugin(id));
+                }
+            }
             _ => log::error!("No such option {}", key),
         }
     }
@@ -310,7 +353,12 @@ impl ManagerConfig {
     pub fn add_plugin(id: &str) -> ResultType<()> {
         let mut lock = CONFIG_MANAGER.lock().unwrap();
         lock.plugins
-            .insert(id.to_owned(), PluginStatus { enabled: true });
+            .entry(id.to_owned())
+            .or_insert(PluginStatus {
+                enabled: true,
+                capabilities: Vec::new(),
+            })
+            .enabled = true;
         hbb_common::config::store_path(Self::path(), &*lock)
     }
 
@@ -11,6 +11,7 @@ pub enum InstallStatus {
     FailedCreating,
     FailedDownloading,
     FailedInstalling,
+    FailedVerifying,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -382,6 +382,9 @@ async fn handle_conn(mut stream: crate::ipc::Connection) {
                                     InstallStatus::FailedInstalling => {
                                         push_install_event(&id, "failed-installing");
                                     }
+                                    InstallStatus::FailedVerifying => {
+                                        push_install_event(&id, "failed-verifying");
+                                    }
                                 }
                             }
                             _ => {}
@@ -446,7 +449,10 @@ pub(super) mod install {
     use crate::hbbs_http::create_http_client;
     use crate::{
         ipc::{connect, Data},
-        plugin::ipc::{InstallStatus, Plugin},
+        plugin::{
+            ipc::{InstallStatus, Plugin},
+            sign::{get_signature_path, verify_file, SIGNATURE_SUFFIX},
+        },
     };
     use hbb_common::{allow_err, bail, log, tokio, ResultType};
     use std::{
@@ -571,13 +577,17 @@ pub(super) mod install {
             }
         };
 
+        let sig_filename = get_signature_path(&filename);
+
         let filename_to_remove = filename.clone();
+        let sig_filename_to_remove = sig_filename.clone();
         let _call_on_ret = crate::common::SimpleCallOnReturn {
             b: true,
             f: Box::new(move || {
                 if let Err(e) = std::fs::remove_file(&filename_to_remove) {
                     log::error!("Failed to remove plugin file: {}", e);
                 }
+                std::fs::remove_file(&sig_filename_to_remove).ok();
             }),
         };
 
@@ -585,6 +595,16 @@ pub(super) mod install {
         if !download_file(id, url, &filename) {
             return;
         }
+        if !download_file(id, &format!("{}{}", url, SIGNATURE_SUFFIX), &sig_filename) {
+            return;
+        }
+
+        // verify
+        if let Err(e) = verify_file(&filename) {
+            log::error!("Refused to install plugin '{}': {}", id, e);
+            send_install_status(id, InstallStatus::FailedVerifying);
+            return;
+        }
 
         // install
         send_install_status(id, InstallStatus::Installing);
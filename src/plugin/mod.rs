@@ -18,6 +18,8 @@ pub mod native;
 pub mod native_handlers;
 mod plog;
 mod plugins;
+mod sign;
+mod wasm;
 
 pub use manager::{
     install::{change_uninstall_plugin, install_plugin_with_url},
@@ -13,7 +13,7 @@ use serde_derive::Serialize;
 use std::{
     collections::{HashMap, HashSet},
     ffi::{c_char, c_void},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
 };
 
@@ -309,7 +309,10 @@ fn load_plugin_dir(dir: &PathBuf) {
                     if path.is_file() {
                         let filename = entry.file_name();
                         let filename = filename.to_str().unwrap_or("");
-                        if filename.starts_with("plugin_") && filename.ends_with(DYLIB_SUFFIX) {
+                        if filename.starts_with("plugin_")
+                            && (filename.ends_with(DYLIB_SUFFIX)
+                                || filename.ends_with(super::wasm::WASM_SUFFIX))
+                        {
                             if let Some(path) = path.to_str() {
                                 if let Err(e) = load_plugin_path(path) {
                                     log::error!("Failed to load plugin {}, {}", filename, e);
@@ -333,6 +336,7 @@ fn load_plugin_dir(dir: &PathBuf) {
 pub fn unload_plugin(id: &str) {
     log::info!("Plugin {} unloaded", id);
     PLUGINS.write().unwrap().remove(id);
+    super::wasm::unload(id);
 }
 
 pub(super) fn mark_uninstalled(id: &str, uninstalled: bool) {
@@ -356,19 +360,18 @@ pub fn reload_plugin(id: &str) -> ResultType<()> {
 fn load_plugin_path(path: &str) -> ResultType<()> {
     log::info!("Begin load plugin {}", path);
 
-    let plugin = Plugin::new(path)?;
+    let data = super::sign::verify_file(Path::new(path))?;
+    if path.ends_with(super::wasm::WASM_SUFFIX) {
+        return load_wasm_plugin_path(path, &data);
+    }
+    let plugin = Plugin::new(&private_copy(path, &data)?)?;
     let desc = plugin.desc()?;
 
     // to-do validate plugin
     // to-do check the plugin id (make sure it does not use another plugin's id)
 
     let id = desc.meta().id.clone();
-    let plugin_info = PluginInfo {
-        path: path.to_string(),
-        uninstalled: false,
-        desc: desc.clone(),
-    };
-    PLUGIN_INFO.write().unwrap().insert(id.clone(), plugin_info);
+    add_plugin_info(path, &desc);
 
     let init_info = serde_json::to_string(&InitInfo {
         is_server: super::is_server_running(),
@@ -389,18 +392,77 @@ fn load_plugin_path(path: &str) -> ResultType<()> {
         log::error!("Failed to init plugin '{}', {}", desc.meta().id, e);
     }
 
+    on_plugin_loaded(&desc)?;
+
+    // add plugins
+    PLUGINS.write().unwrap().insert(id.clone(), plugin);
+
+    log::info!("Plugin {} loaded, {}", id, path);
+    Ok(())
+}
+
+// Load the library from a copy of the verified content in the private config dir,
+// so that it can not be replaced after verifying.
+fn private_copy(path: &str, data: &[u8]) -> ResultType<String> {
+    let src = Path::new(path);
+    let name = match (src.parent().and_then(|p| p.file_name()), src.file_name()) {
+        (Some(dir), Some(name)) => format!("{}_{}", dir.to_string_lossy(), name.to_string_lossy()),
+        _ => bail!("invalid plugin path {}", path),
+    };
+    let dir = hbb_common::config::Config::path("plugins");
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    let dst = dir.join(name);
+    // a loaded library keeps the removed file
+    std::fs::remove_file(&dst).ok();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(&dst)?, data)?;
+    match dst.to_str() {
+        Some(dst) => Ok(dst.to_owned()),
+        None => bail!("invalid plugin path {}", dst.display()),
+    }
+}
+
+fn load_wasm_plugin_path(path: &str, data: &[u8]) -> ResultType<()> {
+    let mut plugin = super::wasm::WasmPlugin::new(path, data)?;
+    let desc = plugin.desc()?;
+    let id = desc.meta().id.clone();
+    add_plugin_info(path, &desc);
+    if let Err(e) = plugin.init() {
+        log::error!("Failed to init wasm plugin '{}', {}", id, e);
+    }
+    on_plugin_loaded(&desc)?;
+    super::wasm::add_plugin(&id, plugin);
+    log::info!("Wasm plugin {} loaded, {}", id, path);
+    Ok(())
+}
+
+fn add_plugin_info(path: &str, desc: &Desc) {
+    let plugin_info = PluginInfo {
+        path: path.to_string(),
+        uninstalled: false,
+        desc: desc.clone(),
+    };
+    PLUGIN_INFO
+        .write()
+        .unwrap()
+        .insert(desc.meta().id.clone(), plugin_info);
+}
+
+fn on_plugin_loaded(desc: &Desc) -> ResultType<()> {
     if super::is_server_running() {
         super::config::ManagerConfig::add_plugin(&desc.meta().id)?;
     }
 
     // update ui
     // Ui may be not ready now, so we need to update again once ui is ready.
-    reload_ui(&desc, None);
-
-    // add plugins
-    PLUGINS.write().unwrap().insert(id.clone(), plugin);
-
-    log::info!("Plugin {} loaded, {}", id, path);
+    reload_ui(desc, None);
     Ok(())
 }
 
@@ -446,6 +508,9 @@ pub fn plugin_call_get_return(
     peer: &str,
     event: &[u8],
 ) -> ResultType<PluginReturn> {
+    if let Some((ret, _)) = super::wasm::call(id, method, peer, event) {
+        return Ok(ret);
+    }
     match PLUGINS.read().unwrap().get(id) {
         Some(plugin) => Ok((plugin.call)(
             method.as_ptr() as _,
@@ -487,14 +552,8 @@ fn _handle_listen_event(event: String, peer: String) {
         let mut peer: String = peer.to_owned();
         peer.push('\0');
         for id in plugins {
-            match PLUGINS.read().unwrap().get(&id) {
-                Some(plugin) => {
-                    let mut ret = (plugin.call)(
-                        METHOD_HANDLE_LISTEN_EVENT.as_ptr() as _,
-                        peer.as_ptr() as _,
-                        evt_bytes.as_ptr() as _,
-                        evt_bytes.len(),
-                    );
+            match plugin_call_get_return(&id, METHOD_HANDLE_LISTEN_EVENT, &peer, &evt_bytes) {
+                Ok(mut ret) => {
                     if !ret.is_success() {
                         let (code, msg) = ret.get_code_msg(&id);
                         log::error!(
@@ -506,8 +565,8 @@ fn _handle_listen_event(event: String, peer: String) {
                         );
                     }
                 }
-                None => {
-                    log::error!("Plugin {} not found when handle_listen_event", id);
+                Err(e) => {
+                    log::error!("Failed to handle listen event, {}", e);
                 }
             }
         }
@@ -523,69 +582,73 @@ pub fn handle_listen_event(event: String, peer: String) {
 pub fn handle_client_event(id: &str, peer: &str, event: &[u8]) -> Message {
     let mut peer: String = peer.to_owned();
     peer.push('\0');
-    match PLUGINS.read().unwrap().get(id) {
-        Some(plugin) => {
-            let mut out = std::ptr::null_mut();
-            let mut out_len: usize = 0;
-            let mut ret = (plugin.call_with_out_data)(
-                METHOD_HANDLE_PEER.as_ptr() as _,
-                peer.as_ptr() as _,
-                event.as_ptr() as _,
-                event.len(),
-                &mut out as _,
-                &mut out_len as _,
-            );
-            if ret.is_success() {
-                let msg = make_plugin_request(id, out, out_len);
+    let (mut ret, out) = match super::wasm::call(id, METHOD_HANDLE_PEER, &peer, event) {
+        Some(res) => res,
+        None => match PLUGINS.read().unwrap().get(id) {
+            Some(plugin) => {
+                let mut out = std::ptr::null_mut();
+                let mut out_len: usize = 0;
+                let ret = (plugin.call_with_out_data)(
+                    METHOD_HANDLE_PEER.as_ptr() as _,
+                    peer.as_ptr() as _,
+                    event.as_ptr() as _,
+                    event.len(),
+                    &mut out as _,
+                    &mut out_len as _,
+                );
+                let data = if out.is_null() {
+                    Vec::new()
+                } else {
+                    unsafe { std::slice::from_raw_parts(out as *const u8, out_len) }.to_vec()
+                };
                 free_c_ptr(out as _);
+                (ret, data)
+            }
+            None => return make_plugin_failure(id, "", "Plugin not found"),
+        },
+    };
+    if ret.is_success() {
+        make_plugin_request(id, out)
+    } else {
+        let (code, msg) = ret.get_code_msg(id);
+        if code > ERR_RUSTDESK_HANDLE_BASE && code < ERR_PLUGIN_HANDLE_BASE {
+            log::debug!(
+                "Plugin {} failed to handle client event, code: {}, msg: {}",
+                id,
+                code,
                 msg
-            } else {
-                let (code, msg) = ret.get_code_msg(id);
-                if code > ERR_RUSTDESK_HANDLE_BASE && code < ERR_PLUGIN_HANDLE_BASE {
-                    log::debug!(
-                        "Plugin {} failed to handle client event, code: {}, msg: {}",
-                        id,
-                        code,
-                        msg
-                    );
-                    let name = match PLUGIN_INFO.read().unwrap().get(id) {
-                        Some(plugin) => &plugin.desc.meta().name,
-                        None => "???",
-                    }
-                    .to_owned();
-                    match code {
-                        ERR_CALL_NOT_SUPPORTED_METHOD => {
-                            make_plugin_failure(id, &name, "Plugin method is not supported")
-                        }
-                        ERR_CALL_INVALID_ARGS => {
-                            make_plugin_failure(id, &name, "Plugin arguments is invalid")
-                        }
-                        _ => make_plugin_failure(id, &name, &msg),
-                    }
-                } else {
-                    log::error!(
-                        "Plugin {} failed to handle client event, code: {}, msg: {}",
-                        id,
-                        code,
-                        msg
-                    );
-                    let msg = make_plugin_request(id, out, out_len);
-                    free_c_ptr(out as _);
-                    msg
+            );
+            let name = match PLUGIN_INFO.read().unwrap().get(id) {
+                Some(plugin) => &plugin.desc.meta().name,
+                None => "???",
+            }
+            .to_owned();
+            match code {
+                ERR_CALL_NOT_SUPPORTED_METHOD => {
+                    make_plugin_failure(id, &name, "Plugin method is not supported")
+                }
+                ERR_CALL_INVALID_ARGS => {
+                    make_plugin_failure(id, &name, "Plugin arguments is invalid")
                 }
+                _ => make_plugin_failure(id, &name, &msg),
             }
+        } else {
+            log::error!(
+                "Plugin {} failed to handle client event, code: {}, msg: {}",
+                id,
+                code,
+                msg
+            );
+            make_plugin_request(id, out)
         }
-        None => make_plugin_failure(id, "", "Plugin not found"),
     }
 }
 
-fn make_plugin_request(id: &str, content: *const c_void, len: usize) -> Message {
+fn make_plugin_request(id: &str, content: Vec<u8>) -> Message {
     let mut misc = Misc::new();
     misc.set_plugin_request(PluginRequest {
         id: id.to_owned(),
-        content: unsafe { std::slice::from_raw_parts(content as *const u8, len) }
-            .clone()
-            .into(),
+        content: content.into(),
         ..Default::default()
     });
     let mut msg_out = Message::new();
@@ -0,0 +1,80 @@
+// Detached ed25519 signatures of plugin packages and libraries.
+// `<file>.sig` holds the base64 signature of `<file>`, made by one of the publisher keys
+// in `plugin-trusted-keys` of the root config. Unsigned plugins are neither installed nor loaded.
+use hbb_common::{
+    bail,
+    base64::{engine::general_purpose::STANDARD, Engine as _},
+    config::{keys, RootConfig},
+    sodiumoxide::crypto::sign,
+    ResultType,
+};
+use std::path::{Path, PathBuf};
+
+pub(super) const SIGNATURE_SUFFIX: &str = ".sig";
+
+// base64 public keys separated by comma
+fn get_trusted_keys() -> Vec<sign::PublicKey> {
+    RootConfig::get_option(keys::OPTION_PLUGIN_TRUSTED_KEYS)
+        .split(',')
+        .filter_map(|x| STANDARD.decode(x.trim()).ok())
+        .filter_map(|x| sign::PublicKey::from_slice(&x))
+        .collect()
+}
+
+#[inline]
+pub(super) fn get_signature_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(SIGNATURE_SUFFIX);
+    p.into()
+}
+
+fn verify(data: &[u8], signature: &str, keys: &[sign::PublicKey]) -> ResultType<()> {
+    if keys.is_empty() {
+        bail!("no trusted plugin publisher keys");
+    }
+    let signature = match STANDARD.decode(signature.trim()) {
+        Ok(s) if s.len() == sign::SIGNATUREBYTES => s,
+        _ => bail!("invalid signature"),
+    };
+    let mut signed = signature;
+    signed.extend_from_slice(data);
+    if keys.iter().any(|pk| sign::verify(&signed, pk).is_ok()) {
+        Ok(())
+    } else {
+        bail!("signature is not made by a trusted publisher");
+    }
+}
+
+/// Verify `path` against the signature in `<path>.sig`, returning the verified content.
+/// Use the content rather than the path afterwards, the file may be replaced meanwhile.
+pub(super) fn verify_file(path: &Path) -> ResultType<Vec<u8>> {
+    let sig_path = get_signature_path(path);
+    let signature = match std::fs::read_to_string(&sig_path) {
+        Ok(s) => s,
+        Err(e) => bail!("failed to read signature {}, {}", sig_path.display(), e),
+    };
+    let data = std::fs::read(path)?;
+    match verify(&data, &signature, &get_trusted_keys()) {
+        Ok(()) => Ok(data),
+        Err(e) => bail!("failed to verify {}, {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let (pk, sk) = sign::gen_keypair();
+        let (pk2, _) = sign::gen_keypair();
+        let data = b"plugin";
+        let signed = sign::sign(data, &sk);
+        let signature = STANDARD.encode(&signed[..sign::SIGNATUREBYTES]);
+        assert!(verify(data, &signature, &[pk2, pk]).is_ok());
+        assert!(verify(data, &signature, &[pk2]).is_err());
+        assert!(verify(data, &signature, &[]).is_err());
+        assert!(verify(b"plugin2", &signature, &[pk]).is_err());
+        assert!(verify(data, "invalid", &[pk]).is_err());
+    }
+}
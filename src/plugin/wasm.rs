@@ -0,0 +1,554 @@
+// WebAssembly plugins, run in an interpreter instead of being loaded into the process.
+// A trap or a runaway loop (out of fuel) fails the call instead of crashing the service.
+//
+// The module exports:
+//   memory
+//   alloc(len: i32) -> i32
+//   desc() -> i32, the json description is returned by `set_return`
+//   init(is_server: i32) -> i32
+//   call(method_ptr, method_len, peer_ptr, peer_len, args_ptr, args_len: i32) -> i32
+//     The methods are the same as the native plugins, "handle_ui", "handle_peer" and
+//     "handle_listen_event". The output data or the error message is returned by `set_return`.
+//   clear() -> i32, optional, called on unload after a successful init
+// Return 0 on success, otherwise an error code in `errno`.
+//
+// The host functions in module "rustdesk":
+//   log(level: i32, ptr: i32, len: i32), level 0 error, 1 warn, 2 info, 3 debug, 4 trace
+//   set_return(ptr: i32, len: i32) -> i32
+//   read_result(ptr: i32) -> i32, copy the result of the last host call returning a length
+//   get_id() -> i32, capability "id"
+//   get_conf(peer_ptr, peer_len, key_ptr, key_len: i32) -> i32, capability "config",
+//     the shared config if peer is empty, -1 if not found
+//   send_msg(target_ptr, target_len, peer_ptr, peer_len, content_ptr, content_len: i32) -> i32,
+//     the same as `cb_msg`, the target "peer", "ui" or "config" requires the capability of the name
+// A denied host call returns -1 and is logged.
+use super::{callback_msg, config, desc::Desc, errno::*, PluginReturn};
+use hbb_common::{bail, lazy_static, log, ResultType};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+use wasmi::{
+    Caller, Config, Engine, Extern, Instance, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+pub(super) const WASM_SUFFIX: &str = ".wasm";
+
+pub(super) const CAP_ID: &str = "id";
+pub(super) const CAP_CONFIG: &str = "config";
+pub(super) const CAP_PEER: &str = "peer";
+pub(super) const CAP_UI: &str = "ui";
+
+const HOST_MODULE: &str = "rustdesk";
+// The calls are made on the connection threads with the plugin locked,
+// a runaway loop must fail in tens of milliseconds.
+const FUEL_PER_CALL: u64 = 10_000_000;
+const MAX_MEMORY_SIZE: usize = 64 * 1024 * 1024;
+const MAX_HOST_DATA_LEN: i32 = 16 * 1024 * 1024;
+
+lazy_static::lazy_static! {
+    static ref WASM_PLUGINS: Arc<RwLock<HashMap<String, Arc<Mutex<WasmPlugin>>>>> = Default::default();
+}
+
+struct HostState {
+    id: String,
+    capabilities: Vec<String>,
+    // set by the plugin with `set_return`
+    ret: Vec<u8>,
+    // read by the plugin with `read_result`
+    result: Vec<u8>,
+    limits: StoreLimits,
+}
+
+impl HostState {
+    fn check_capability(&self, cap: &str) -> bool {
+        if self.capabilities.iter().any(|x| x == cap) {
+            true
+        } else {
+            log::warn!("Wasm plugin '{}' is not granted '{}'", self.id, cap);
+            false
+        }
+    }
+
+    // Keep the result for `read_result`, return the length.
+    fn set_result(&mut self, result: Vec<u8>) -> i32 {
+        let len = result.len() as i32;
+        self.result = result;
+        len
+    }
+}
+
+pub(super) struct WasmPlugin {
+    store: Store<HostState>,
+    instance: Instance,
+    initialized: bool,
+}
+
+fn read_memory(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    if ptr < 0 || len < 0 || len > MAX_HOST_DATA_LEN {
+        return None;
+    }
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let mut buf = vec![0; len as usize];
+    memory.read(caller, ptr as usize, &mut buf).ok()?;
+    Some(buf)
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<String> {
+    String::from_utf8(read_memory(caller, ptr, len)?).ok()
+}
+
+fn add_host_functions(linker: &mut Linker<HostState>) -> ResultType<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| {
+            if let Some(msg) = read_string(&caller, ptr, len) {
+                let id = &caller.data().id;
+                match level {
+                    0 => log::error!("[{}] {}", id, msg),
+                    1 => log::warn!("[{}] {}", id, msg),
+                    2 => log::info!("[{}] {}", id, msg),
+                    3 => log::debug!("[{}] {}", id, msg),
+                    _ => log::trace!("[{}] {}", id, msg),
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_return",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i32 {
+            match read_memory(&caller, ptr, len) {
+                Some(data) => {
+                    caller.data_mut().ret = data;
+                    0
+                }
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_result",
+        |mut caller: Caller<'_, HostState>, ptr: i32| -> i32 {
+            let memory = match caller.get_export("memory").and_then(Extern::into_memory) {
+                Some(memory) => memory,
+                None => return -1,
+            };
+            let result = std::mem::take(&mut caller.data_mut().result);
+            if ptr < 0 || memory.write(&mut caller, ptr as usize, &result).is_err() {
+                return -1;
+            }
+            result.len() as i32
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "get_id",
+        |mut caller: Caller<'_, HostState>| -> i32 {
+            if !caller.data().check_capability(CAP_ID) {
+                return -1;
+            }
+            caller
+                .data_mut()
+                .set_result(crate::ui_interface::get_id().into_bytes())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "get_conf",
+        |mut caller: Caller<'_, HostState>,
+         peer_ptr: i32,
+         peer_len: i32,
+         key_ptr: i32,
+         key_len: i32|
+         -> i32 {
+            if !caller.data().check_capability(CAP_CONFIG) {
+                return -1;
+            }
+            let (peer, key) = match (
+                read_string(&caller, peer_ptr, peer_len),
+                read_string(&caller, key_ptr, key_len),
+            ) {
+                (Some(peer), Some(key)) => (peer, key),
+                _ => return -1,
+            };
+            let id = &caller.data().id;
+            let value = if peer.is_empty() {
+                config::SharedConfig::get(id, &key)
+            } else {
+                config::PeerConfig::get(id, &peer, &key)
+            };
+            match value {
+                Some(value) => caller.data_mut().set_result(value.into_bytes()),
+                None => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "send_msg",
+        |caller: Caller<'_, HostState>,
+         target_ptr: i32,
+         target_len: i32,
+         peer_ptr: i32,
+         peer_len: i32,
+         content_ptr: i32,
+         content_len: i32|
+         -> i32 {
+            let (target, peer, content) = match (
+                read_string(&caller, target_ptr, target_len),
+                read_string(&caller, peer_ptr, peer_len),
+                read_memory(&caller, content_ptr, content_len),
+            ) {
+                (Some(target), Some(peer), Some(content)) => (target, peer, content),
+                _ => return -1,
+            };
+            let state = caller.data();
+            if ![CAP_PEER, CAP_UI, CAP_CONFIG].contains(&target.as_str())
+                || !state.check_capability(&target)
+            {
+                return -1;
+            }
+            let (c_peer, c_target, c_id) = match (
+                CString::new(peer),
+                CString::new(target),
+                CString::new(state.id.clone()),
+            ) {
+                (Ok(peer), Ok(target), Ok(id)) => (peer, target, id),
+                _ => return -1,
+            };
+            let mut ret = callback_msg::cb_msg(
+                c_peer.as_ptr(),
+                c_target.as_ptr(),
+                c_id.as_ptr(),
+                content.as_ptr() as _,
+                content.len(),
+            );
+            let (code, msg) = ret.get_code_msg(&state.id);
+            if code != ERR_SUCCESS {
+                log::error!(
+                    "Wasm plugin '{}' failed to send msg, code: {}, msg: {}",
+                    state.id,
+                    code,
+                    msg
+                );
+            }
+            code
+        },
+    )?;
+    Ok(())
+}
+
+impl WasmPlugin {
+    /// The capabilities are granted to the id of the plugin dir, the description must have the same id.
+    // `wasm` is the verified content of `path`.
+    pub(super) fn new(path: &str, wasm: &[u8]) -> ResultType<Self> {
+        let id = plugin_dir(path);
+        let mut plugin = Self::from_bytes(wasm, id, get_capabilities(id))?;
+        check_plugin_id(path, &plugin.desc()?.meta().id)?;
+        Ok(plugin)
+    }
+
+    fn from_bytes(wasm: &[u8], id: &str, capabilities: Vec<String>) -> ResultType<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        let mut store = Store::new(
+            &engine,
+            HostState {
+                id: id.to_owned(),
+                capabilities,
+                ret: Vec::new(),
+                result: Vec::new(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(MAX_MEMORY_SIZE)
+                    .build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        let mut linker = Linker::new(&engine);
+        add_host_functions(&mut linker)?;
+        store.set_fuel(FUEL_PER_CALL).map_err(wasmi::Error::from)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        Ok(Self {
+            store,
+            instance,
+            initialized: false,
+        })
+    }
+
+    // Write the data into the memory allocated by the plugin.
+    fn write(&mut self, data: &[u8]) -> ResultType<(i32, i32)> {
+        if data.is_empty() {
+            return Ok((0, 0));
+        }
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "alloc")?;
+        let ptr = alloc.call(&mut self.store, data.len() as i32)?;
+        let memory = match self.instance.get_memory(&self.store, "memory") {
+            Some(memory) => memory,
+            None => bail!("no memory exported"),
+        };
+        memory
+            .write(&mut self.store, ptr as usize, data)
+            .map_err(wasmi::Error::from)?;
+        Ok((ptr, data.len() as i32))
+    }
+
+    fn take_return(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.store.data_mut().ret)
+    }
+
+    pub(super) fn desc(&mut self) -> ResultType<Desc> {
+        self.store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(wasmi::Error::from)?;
+        let desc = self
+            .instance
+            .get_typed_func::<(), i32>(&self.store, "desc")?;
+        let code = desc.call(&mut self.store, ())?;
+        if code != ERR_SUCCESS {
+            bail!("failed to get desc, code: {}", code);
+        }
+        Ok(serde_json::from_slice(&self.take_return())?)
+    }
+
+    pub(super) fn init(&mut self) -> ResultType<()> {
+        self.store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(wasmi::Error::from)?;
+        let init = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "init")?;
+        let code = init.call(&mut self.store, super::is_server_running() as i32)?;
+        if code != ERR_SUCCESS {
+            let msg = String::from_utf8_lossy(&self.take_return()).into_owned();
+            bail!("code: {}, msg: {}", code, msg);
+        }
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        if let Ok(clear) = self
+            .instance
+            .get_typed_func::<(), i32>(&self.store, "clear")
+        {
+            self.store.set_fuel(FUEL_PER_CALL).ok();
+            if let Err(e) = clear.call(&mut self.store, ()) {
+                log::error!(
+                    "Failed to clear wasm plugin '{}', {}",
+                    self.store.data().id,
+                    e
+                );
+            }
+        }
+    }
+
+    fn call(&mut self, method: &str, peer: &str, args: &[u8]) -> ResultType<(i32, Vec<u8>)> {
+        self.store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(wasmi::Error::from)?;
+        self.store.data_mut().ret.clear();
+        let (method_ptr, method_len) = self.write(method.as_bytes())?;
+        let (peer_ptr, peer_len) = self.write(peer.as_bytes())?;
+        let (args_ptr, args_len) = self.write(args)?;
+        let call = self
+            .instance
+            .get_typed_func::<(i32, i32, i32, i32, i32, i32), i32>(&self.store, "call")?;
+        let code = call.call(
+            &mut self.store,
+            (
+                method_ptr, method_len, peer_ptr, peer_len, args_ptr, args_len,
+            ),
+        )?;
+        Ok((code, self.take_return()))
+    }
+}
+
+impl Drop for WasmPlugin {
+    fn drop(&mut self) {
+        if self.initialized {
+            self.clear();
+        }
+    }
+}
+
+// The plugin is installed in the dir of its id, a plugin must not take the capabilities of another one.
+fn check_plugin_id(path: &str, id: &str) -> ResultType<()> {
+    let dir = plugin_dir(path);
+    if id.is_empty() || id != dir {
+        bail!("plugin id '{}' does not match the plugin dir '{}'", id, dir);
+    }
+    Ok(())
+}
+
+fn plugin_dir(path: &str) -> &str {
+    Path::new(path)
+        .parent()
+        .and_then(|p| p.file_name())
+        .and_then(|f| f.to_str())
+        .unwrap_or_default()
+}
+
+fn get_capabilities(id: &str) -> Vec<String> {
+    config::ManagerConfig::get_plugin_option(id, "capabilities")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+pub(super) fn add_plugin(id: &str, plugin: WasmPlugin) {
+    WASM_PLUGINS
+        .write()
+        .unwrap()
+        .insert(id.to_owned(), Arc::new(Mutex::new(plugin)));
+}
+
+pub(super) fn unload(id: &str) {
+    WASM_PLUGINS.write().unwrap().remove(id);
+}
+
+/// Call the wasm plugin, None if `id` is not a wasm plugin.
+/// `method` and `peer` may be null terminated as the native plugins require.
+pub(super) fn call(
+    id: &str,
+    method: &[u8],
+    peer: &str,
+    args: &[u8],
+) -> Option<(PluginReturn, Vec<u8>)> {
+    let plugin = WASM_PLUGINS.read().unwrap().get(id)?.clone();
+    let method = String::from_utf8_lossy(method);
+    let method = method.trim_end_matches('\0');
+    let peer = peer.trim_end_matches('\0');
+    let res = plugin.lock().unwrap().call(method, peer, args);
+    Some(match res {
+        Ok((ERR_SUCCESS, out)) => (PluginReturn::success(), out),
+        Ok((code, out)) => (PluginReturn::new(code, &String::from_utf8_lossy(&out)), out),
+        Err(e) => (
+            PluginReturn::new(EER_CALL_FAILED, &format!("wasm call failed, {}", e)),
+            Vec::new(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (module
+    //   (import "rustdesk" "set_return" (func $set_return (param i32 i32) (result i32)))
+    //   (import "rustdesk" "get_id" (func $get_id (result i32)))
+    //   (import "rustdesk" "get_conf" (func $get_conf (param i32 i32 i32 i32) (result i32)))
+    //   (memory (export "memory") 1)
+    //   (func (export "set_return") (param i32 i32) (result i32)
+    //     local.get 0 local.get 1 call $set_return)
+    //   (func (export "get_id") (result i32) call $get_id)
+    //   (func (export "get_conf") (param i32 i32 i32 i32) (result i32)
+    //     local.get 0 local.get 1 local.get 2 local.get 3 call $get_conf))
+    const TEST_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+        0x01, 0x13, 0x03, // types
+        0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, //
+        0x60, 0x00, 0x01, 0x7f, //
+        0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, //
+        0x02, 0x3d, 0x03, // imports
+        0x08, b'r', b'u', b's', b't', b'd', b'e', b's', b'k', //
+        0x0a, b's', b'e', b't', b'_', b'r', b'e', b't', b'u', b'r', b'n', 0x00, 0x00, //
+        0x08, b'r', b'u', b's', b't', b'd', b'e', b's', b'k', //
+        0x06, b'g', b'e', b't', b'_', b'i', b'd', 0x00, 0x01, //
+        0x08, b'r', b'u', b's', b't', b'd', b'e', b's', b'k', //
+        0x08, b'g', b'e', b't', b'_', b'c', b'o', b'n', b'f', 0x00, 0x02, //
+        0x03, 0x04, 0x03, 0x00, 0x01, 0x02, // functions
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x07, 0x2b, 0x04, // exports
+        0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, //
+        0x0a, b's', b'e', b't', b'_', b'r', b'e', b't', b'u', b'r', b'n', 0x00, 0x03, //
+        0x06, b'g', b'e', b't', b'_', b'i', b'd', 0x00, 0x04, //
+        0x08, b'g', b'e', b't', b'_', b'c', b'o', b'n', b'f', 0x00, 0x05, //
+        0x0a, 0x1c, 0x03, // code
+        0x08, 0x00, 0x20, 0x00, 0x20, 0x01, 0x10, 0x00, 0x0b, //
+        0x04, 0x00, 0x10, 0x01, 0x0b, //
+        0x0c, 0x00, 0x20, 0x00, 0x20, 0x01, 0x20, 0x02, 0x20, 0x03, 0x10, 0x02, 0x0b,
+    ];
+
+    // (module (func (export "spin") (loop br 0)))
+    const SPIN_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x07, 0x08, 0x01, 0x04, b's', b'p', b'i', b'n', 0x00, 0x00, // exports
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b, // code
+    ];
+
+    fn set_return(plugin: &mut WasmPlugin, ptr: i32, len: i32) -> i32 {
+        let f = plugin
+            .instance
+            .get_typed_func::<(i32, i32), i32>(&plugin.store, "set_return")
+            .unwrap();
+        f.call(&mut plugin.store, (ptr, len)).unwrap()
+    }
+
+    #[test]
+    fn test_check_plugin_id() {
+        let path = Path::new("plugins").join("a").join("plugin_a.wasm");
+        let path = path.to_str().unwrap();
+        assert!(check_plugin_id(path, "a").is_ok());
+        assert!(check_plugin_id(path, "b").is_err());
+        assert!(check_plugin_id(path, "").is_err());
+        assert!(check_plugin_id("plugin_a.wasm", "a").is_err());
+    }
+
+    #[test]
+    fn test_memory_bounds() {
+        let mut plugin = WasmPlugin::from_bytes(TEST_WASM, "a", Vec::new()).unwrap();
+        assert_eq!(set_return(&mut plugin, 0, 4), 0);
+        assert_eq!(plugin.take_return(), vec![0; 4]);
+        assert_eq!(set_return(&mut plugin, -1, 4), -1);
+        assert_eq!(set_return(&mut plugin, 0, -1), -1);
+        assert_eq!(set_return(&mut plugin, 0, MAX_HOST_DATA_LEN + 1), -1);
+        // one page of memory
+        assert_eq!(set_return(&mut plugin, 65535, 2), -1);
+        assert!(plugin.take_return().is_empty());
+    }
+
+    #[test]
+    fn test_capability_denied() {
+        let mut plugin = WasmPlugin::from_bytes(TEST_WASM, "a", vec![CAP_UI.to_owned()]).unwrap();
+        let get_id = plugin
+            .instance
+            .get_typed_func::<(), i32>(&plugin.store, "get_id")
+            .unwrap();
+        assert_eq!(get_id.call(&mut plugin.store, ()).unwrap(), -1);
+        let get_conf = plugin
+            .instance
+            .get_typed_func::<(i32, i32, i32, i32), i32>(&plugin.store, "get_conf")
+            .unwrap();
+        assert_eq!(get_conf.call(&mut plugin.store, (0, 0, 0, 1)).unwrap(), -1);
+        assert!(plugin.store.data().result.is_empty());
+
+        let state = plugin.store.data();
+        assert!(state.check_capability(CAP_UI));
+        assert!(!state.check_capability(CAP_PEER));
+    }
+
+    #[test]
+    fn test_out_of_fuel() {
+        let mut plugin = WasmPlugin::from_bytes(SPIN_WASM, "a", Vec::new()).unwrap();
+        let spin = plugin
+            .instance
+            .get_typed_func::<(), ()>(&plugin.store, "spin")
+            .unwrap();
+        let err = spin.call(&mut plugin.store, ()).unwrap_err();
+        assert_eq!(err.as_trap_code(), Some(wasmi::core::TrapCode::OutOfFuel));
+    }
+}
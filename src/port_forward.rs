@@ -1,20 +1,41 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use crate::client::*;
+use crate::{client::*, tunnel};
 use hbb_common::{
     allow_err, bail,
-    config::READ_TIMEOUT,
+    bytes::Bytes,
+    config::{PortForwardProfile, READ_TIMEOUT},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UdpSocket},
+        sync::mpsc,
+        time,
+    },
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
 
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_REP_SUCCEEDED: u8 = 0;
+const SOCKS5_REP_HOST_UNREACHABLE: u8 = 4;
+const SOCKS5_REP_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_REP_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
         .arg("/delete:localhost")
@@ -67,10 +88,11 @@ pub async fn listen(
             Ok((forward, addr)) = listener.accept() => {
                 log::info!("new connection from {:?}", addr);
                 lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
+                lc.write().unwrap().port_forward_multiplex = false;
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
                         tokio::spawn(async move {
@@ -108,7 +130,7 @@ async fn connect_and_login(
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -118,7 +140,7 @@ async fn connect_and_login(
     } else {
         ConnType::PORT_FORWARD
     };
-    let ((mut stream, direct, _pk), (feedback, rendezvous_server)) =
+    let ((mut stream, direct, _pk, _), (feedback, rendezvous_server)) =
         Client::start(id, key, token, conn_type, interface.clone()).await?;
     interface.update_direct(Some(direct));
     let mut buffer = Vec::new();
@@ -178,7 +200,7 @@ async fn connect_and_login(
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async { forward.as_mut().unwrap().next().await }, if forward.is_some() => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -187,6 +209,10 @@ async fn connect_and_login(
             },
         }
     }
+    if forward.is_none() {
+        // multiplexed, the stream keeps carrying messages
+        return Ok(Some(stream));
+    }
     stream.set_raw();
     if !buffer.is_empty() {
         allow_err!(stream.send_bytes(buffer.into()).await);
@@ -218,3 +244,526 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+enum Channel {
+    Tcp(tunnel::DataSender),
+    Udp(Arc<UdpSocket>, SocketAddr),
+}
+
+// The peer listens on the reverse forwards after login, only tcp is supported.
+// Returns the targets the peer can open channels to.
+fn get_reverse_targets(profile: &PortForwardProfile) -> ResultType<HashSet<(String, i32)>> {
+    let mut targets = HashSet::new();
+    for rule in profile.forwards.iter().filter(|r| r.reverse) {
+        if rule.udp {
+            bail!(
+                "UDP is not supported by reverse forwarding, port {}",
+                rule.local_port
+            );
+        }
+        targets.insert((rule.remote_host.clone(), rule.remote_port));
+    }
+    Ok(targets)
+}
+
+fn is_reverse_open_allowed(open: &TunnelOpen, targets: &HashSet<(String, i32)>) -> bool {
+    open.channel & tunnel::REVERSE_CHANNEL != 0
+        && !open.udp
+        && targets.contains(&(open.host.clone(), open.port))
+}
+
+/// Listen on all the forwards of `profile`, and carry them over one session.
+pub async fn listen_profile(
+    id: String,
+    password: String,
+    profile: PortForwardProfile,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let reverse_targets = get_reverse_targets(&profile)?;
+    // Bind all before login, so that a used port fails the whole profile.
+    let (tx_accept, mut rx_accept) = mpsc::channel::<(usize, TcpStream)>(tunnel::QUEUE_SIZE);
+    let (tx_datagram, mut rx_datagram) =
+        mpsc::channel::<(usize, SocketAddr, Bytes)>(tunnel::QUEUE_SIZE);
+    let mut udp_sockets = HashMap::new();
+    for (i, rule) in profile.forwards.iter().enumerate() {
+        let addr = format!("0.0.0.0:{}", rule.local_port);
+        if rule.reverse {
+            continue;
+        } else if rule.udp {
+            let socket = Arc::new(UdpSocket::bind(&addr).await?);
+            log::info!("listening on udp {:?}", socket.local_addr()?);
+            udp_sockets.insert(i, socket.clone());
+            let tx = tx_datagram.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+                while let Ok((n, addr)) = socket.recv_from(&mut buf).await {
+                    if tx
+                        .send((i, addr, Bytes::copy_from_slice(&buf[..n])))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        } else {
+            let listener = tcp::new_listener(&addr, true).await?;
+            log::info!("listening on port {:?}", listener.local_addr()?);
+            let tx = tx_accept.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, addr)) = listener.accept().await {
+                    log::info!("new connection from {:?}", addr);
+                    if tx.send((i, socket)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+    drop(tx_accept);
+    drop(tx_datagram);
+    lc.write().unwrap().port_forward = ("".to_owned(), 0);
+    lc.write().unwrap().port_forward_multiplex = true;
+    let mut ui_receiver = ui_receiver;
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await
+    {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            interface.on_establish_connection_error(err.to_string());
+            return Ok(());
+        }
+    };
+    log::info!("port forward profile {} started", profile.name);
+    for rule in profile.forwards.iter().filter(|r| r.reverse) {
+        let listen =
+            tunnel::new_listen(rule.local_port, rule.remote_host.clone(), rule.remote_port);
+        stream.send(&tunnel::new_message(listen)).await?;
+    }
+    let mut next_channel: u32 = 0;
+    let mut channels: HashMap<u32, Channel> = HashMap::new();
+    let mut udp_channels: HashMap<(usize, SocketAddr), u32> = HashMap::new();
+    let (tx_tunnel, mut rx_tunnel) = mpsc::channel::<TunnelMessage>(tunnel::QUEUE_SIZE);
+    loop {
+        tokio::select! {
+            Some((i, socket)) = rx_accept.recv() => {
+                let rule = &profile.forwards[i];
+                next_channel += 1;
+                let channel = next_channel;
+                let (tx, rx) = mpsc::channel(tunnel::QUEUE_SIZE);
+                channels.insert(channel, Channel::Tcp(tx));
+                let open = tunnel::new_open(channel, rule.remote_host.clone(), rule.remote_port, false);
+                stream.send(&tunnel::new_message(open)).await?;
+                tokio::spawn(tunnel::run_tcp(channel, socket, rx, tx_tunnel.clone()));
+            }
+            Some((i, addr, data)) = rx_datagram.recv() => {
+                let channel = match udp_channels.get(&(i, addr)) {
+                    Some(channel) => *channel,
+                    None => {
+                        let rule = &profile.forwards[i];
+                        next_channel += 1;
+                        let channel = next_channel;
+                        udp_channels.insert((i, addr), channel);
+                        if let Some(socket) = udp_sockets.get(&i) {
+                            channels.insert(channel, Channel::Udp(socket.clone(), addr));
+                        }
+                        let open = tunnel::new_open(channel, rule.remote_host.clone(), rule.remote_port, true);
+                        stream.send(&tunnel::new_message(open)).await?;
+                        channel
+                    }
+                };
+                stream.send(&tunnel::new_message(tunnel::new_data(channel, data))).await?;
+            }
+            Some(tm) = rx_tunnel.recv() => {
+                if let Some(tunnel_message::Union::Close(c)) = &tm.union {
+                    channels.remove(&c.channel);
+                }
+                stream.send(&tunnel::new_message(tm)).await?;
+            }
+            res = stream.next() => {
+                let bytes = match res {
+                    Some(Ok(bytes)) => bytes,
+                    Some(Err(err)) => bail!("Connection closed: {}", err),
+                    None => bail!("Reset by the peer"),
+                };
+                let msg = Message::parse_from_bytes(&bytes)?;
+                if let Some(message::Union::TunnelMessage(tm)) = msg.union {
+                    match tm.union {
+                        Some(tunnel_message::Union::Open(open)) => {
+                            let channel = open.channel;
+                            if is_reverse_open_allowed(&open, &reverse_targets) {
+                                channels.insert(channel, Channel::Tcp(tunnel::open(open, tx_tunnel.clone())));
+                            } else {
+                                log::error!("Rejected tunnel channel {} to {}:{}", channel, open.host, open.port);
+                                let close = tunnel::new_close(channel, "Not allowed".to_owned());
+                                stream.send(&tunnel::new_message(close)).await?;
+                            }
+                        }
+                        Some(tunnel_message::Union::ListenResponse(r)) => {
+                            if r.error.is_empty() {
+                                log::info!("peer is listening on {} for reverse forward", r.port);
+                            } else {
+                                interface.msgbox(
+                                    "error",
+                                    "Error",
+                                    &format!("Failed to listen on remote port {}: {}", r.port, r.error),
+                                    "",
+                                );
+                            }
+                        }
+                        Some(tunnel_message::Union::Data(d)) => match channels.get(&d.channel) {
+                            Some(Channel::Tcp(tx)) => {
+                                if !tunnel::send_data(tx, d.data).await {
+                                    channels.remove(&d.channel);
+                                    let close = tunnel::new_close(d.channel, "Channel stalled".to_owned());
+                                    stream.send(&tunnel::new_message(close)).await?;
+                                }
+                            }
+                            Some(Channel::Udp(socket, addr)) => {
+                                allow_err!(socket.send_to(&d.data, addr).await);
+                            }
+                            None => {}
+                        },
+                        Some(tunnel_message::Union::Close(c)) => {
+                            if !c.error.is_empty() {
+                                log::error!("Tunnel channel {}: {}", c.channel, c.error);
+                            }
+                            channels.remove(&c.channel);
+                            udp_channels.retain(|_, channel| *channel != c.channel);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    log::info!("port forward profile {} closed", profile.name);
+    Ok(())
+}
+
+/// Run a local SOCKS5 server, each CONNECT request is a channel opened by the peer.
+pub async fn listen_socks(
+    id: String,
+    password: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let listener = tcp::new_listener(format!("127.0.0.1:{}", port), true).await?;
+    log::info!("SOCKS5 listening on {:?}", listener.local_addr()?);
+    lc.write().unwrap().port_forward = ("".to_owned(), 0);
+    lc.write().unwrap().port_forward_multiplex = true;
+    let mut ui_receiver = ui_receiver;
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await
+    {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            interface.on_establish_connection_error(err.to_string());
+            return Ok(());
+        }
+    };
+    let (tx_request, mut rx_request) =
+        mpsc::channel::<(TcpStream, String, i32)>(tunnel::QUEUE_SIZE);
+    let (tx_tunnel, mut rx_tunnel) = mpsc::channel::<TunnelMessage>(tunnel::QUEUE_SIZE);
+    let mut next_channel: u32 = 0;
+    // waiting for the peer to connect the target, with the time the channel was opened
+    let mut pending: HashMap<u32, (TcpStream, Instant)> = HashMap::new();
+    let mut channels: HashMap<u32, tunnel::DataSender> = HashMap::new();
+    let mut timer = crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                if let Ok((mut socket, addr)) = res {
+                    let tx = tx_request.clone();
+                    tokio::spawn(async move {
+                        match timeout(READ_TIMEOUT, socks5_handshake(&mut socket)).await {
+                            Ok(Ok((host, port))) => {
+                                log::info!("SOCKS5 connect from {:?} to {}:{}", addr, host, port);
+                                tx.send((socket, host, port)).await.ok();
+                            }
+                            Ok(Err(err)) => log::warn!("SOCKS5 request from {:?}: {}", addr, err),
+                            Err(_) => log::warn!("SOCKS5 request from {:?}: timeout", addr),
+                        }
+                    });
+                }
+            }
+            Some((socket, host, port)) = rx_request.recv() => {
+                next_channel += 1;
+                let channel = next_channel;
+                pending.insert(channel, (socket, Instant::now()));
+                let open = tunnel::new_open(channel, host, port, false);
+                stream.send(&tunnel::new_message(open)).await?;
+            }
+            Some(tm) = rx_tunnel.recv() => {
+                if let Some(tunnel_message::Union::Close(c)) = &tm.union {
+                    channels.remove(&c.channel);
+                }
+                stream.send(&tunnel::new_message(tm)).await?;
+            }
+            res = stream.next() => {
+                let bytes = match res {
+                    Some(Ok(bytes)) => bytes,
+                    Some(Err(err)) => bail!("Connection closed: {}", err),
+                    None => bail!("Reset by the peer"),
+                };
+                let msg = Message::parse_from_bytes(&bytes)?;
+                if let Some(message::Union::TunnelMessage(tm)) = msg.union {
+                    match tm.union {
+                        Some(tunnel_message::Union::Opened(o)) => {
+                            if let Some((mut socket, _)) = pending.remove(&o.channel) {
+                                let channel = o.channel;
+                                let (tx, rx) = mpsc::channel(tunnel::QUEUE_SIZE);
+                                channels.insert(channel, tx);
+                                let tx_tunnel = tx_tunnel.clone();
+                                tokio::spawn(async move {
+                                    if let Err(err) = socks5_reply(&mut socket, SOCKS5_REP_SUCCEEDED).await {
+                                        tx_tunnel.send(tunnel::new_close(channel, err.to_string())).await.ok();
+                                    } else {
+                                        tunnel::run_tcp(channel, socket, rx, tx_tunnel).await;
+                                    }
+                                });
+                            }
+                        }
+                        Some(tunnel_message::Union::Data(d)) => {
+                            if let Some(tx) = channels.get(&d.channel) {
+                                if !tunnel::send_data(tx, d.data).await {
+                                    channels.remove(&d.channel);
+                                    let close = tunnel::new_close(d.channel, "Channel stalled".to_owned());
+                                    stream.send(&tunnel::new_message(close)).await?;
+                                }
+                            }
+                        }
+                        Some(tunnel_message::Union::Close(c)) => {
+                            if let Some((mut socket, _)) = pending.remove(&c.channel) {
+                                log::error!("SOCKS5 channel {}: {}", c.channel, c.error);
+                                tokio::spawn(async move {
+                                    socks5_reply(&mut socket, SOCKS5_REP_HOST_UNREACHABLE).await.ok();
+                                });
+                            }
+                            channels.remove(&c.channel);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ = timer.tick() => {
+                let expired: Vec<u32> = pending
+                    .iter()
+                    .filter(|(_, (_, time))| time.elapsed().as_millis() >= READ_TIMEOUT as u128)
+                    .map(|(channel, _)| *channel)
+                    .collect();
+                for channel in expired {
+                    if let Some((mut socket, _)) = pending.remove(&channel) {
+                        log::error!("SOCKS5 channel {}: timeout", channel);
+                        tokio::spawn(async move {
+                            socks5_reply(&mut socket, SOCKS5_REP_HOST_UNREACHABLE).await.ok();
+                        });
+                        // the peer drops the channel, a late opened is ignored
+                        let close = tunnel::new_close(channel, "Timeout".to_owned());
+                        stream.send(&tunnel::new_message(close)).await?;
+                    }
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    log::info!("SOCKS5 (:{}) closed", port);
+    Ok(())
+}
+
+// https://www.rfc-editor.org/rfc/rfc1928, only CONNECT without authentication is supported.
+async fn socks5_handshake(socket: &mut TcpStream) -> ResultType<(String, i32)> {
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await?;
+    if header[0] != SOCKS5_VERSION {
+        bail!("Unsupported version {}", header[0]);
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        socket.write_all(&[SOCKS5_VERSION, 0xff]).await?;
+        bail!("No acceptable authentication method");
+    }
+    socket.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[1] != SOCKS5_CMD_CONNECT {
+        socks5_reply(socket, SOCKS5_REP_COMMAND_NOT_SUPPORTED).await?;
+        bail!("Unsupported command {}", request[1]);
+    }
+    let host = match request[3] {
+        1 => {
+            let mut addr = [0u8; 4];
+            socket.read_exact(&mut addr).await?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        3 => {
+            let mut name = vec![0u8; socket.read_u8().await? as usize];
+            socket.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        }
+        4 => {
+            let mut addr = [0u8; 16];
+            socket.read_exact(&mut addr).await?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        t => {
+            socks5_reply(socket, SOCKS5_REP_ADDRESS_NOT_SUPPORTED).await?;
+            bail!("Unsupported address type {}", t);
+        }
+    };
+    let port = socket.read_u16().await?;
+    Ok((host, port as _))
+}
+
+async fn socks5_reply(socket: &mut TcpStream, rep: u8) -> ResultType<()> {
+    // bound address is not meaningful through the tunnel, 0.0.0.0:0
+    socket
+        .write_all(&[SOCKS5_VERSION, rep, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::{config::PortForwardRule, tokio::net::TcpListener};
+
+    // Run the handshake on the request, returns its result and the replies.
+    async fn handshake(request: &[u8]) -> (ResultType<(String, i32)>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        client.write_all(request).await.unwrap();
+        let res = socks5_handshake(&mut server).await;
+        drop(server);
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        (res, replies)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_handshake() {
+        let accepted = [SOCKS5_VERSION, SOCKS5_NO_AUTH];
+
+        let (res, replies) = handshake(&[5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]).await;
+        assert_eq!(res.unwrap(), ("10.0.0.1".to_owned(), 8080));
+        assert_eq!(replies, accepted);
+
+        let mut request = vec![5, 2, 2, 0, 5, 1, 0, 3, 11];
+        request.extend(b"example.com");
+        request.extend([0, 80]);
+        let (res, replies) = handshake(&request).await;
+        assert_eq!(res.unwrap(), ("example.com".to_owned(), 80));
+        assert_eq!(replies, accepted);
+
+        let mut request = vec![5, 1, 0, 5, 1, 0, 4];
+        request.extend(Ipv6Addr::LOCALHOST.octets());
+        request.extend([1, 187]);
+        let (res, replies) = handshake(&request).await;
+        assert_eq!(res.unwrap(), ("::1".to_owned(), 443));
+        assert_eq!(replies, accepted);
+
+        // BIND, replied with command not supported
+        let (res, replies) = handshake(&[5, 1, 0, 5, 2, 0, 1]).await;
+        assert!(res.is_err());
+        assert_eq!(replies, [5, 0, 5, 7, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+        // username/password only
+        let (res, replies) = handshake(&[5, 1, 2]).await;
+        assert!(res.is_err());
+        assert_eq!(replies, [5, 0xff]);
+
+        let (res, replies) = handshake(&[4, 1]).await;
+        assert!(res.is_err());
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn test_reverse_rules() {
+        let reverse = |remote_host: &str, udp| PortForwardRule {
+            local_port: 8080,
+            remote_host: remote_host.to_owned(),
+            remote_port: 80,
+            udp,
+            reverse: true,
+        };
+        let mut profile = PortForwardProfile {
+            forwards: vec![
+                PortForwardRule {
+                    local_port: 8081,
+                    remote_host: "db".to_owned(),
+                    remote_port: 80,
+                    ..Default::default()
+                },
+                reverse("web", false),
+            ],
+            ..Default::default()
+        };
+        let targets = get_reverse_targets(&profile).unwrap();
+        assert_eq!(targets, HashSet::from([("web".to_owned(), 80)]));
+        let allowed = |channel, host: &str, udp| {
+            let open = TunnelOpen {
+                channel,
+                host: host.to_owned(),
+                port: 80,
+                udp,
+                ..Default::default()
+            };
+            is_reverse_open_allowed(&open, &targets)
+        };
+        let channel = tunnel::REVERSE_CHANNEL | 1;
+        assert!(allowed(channel, "web", false));
+        // only the reverse channels to the targets of reverse forwards
+        assert!(!allowed(1, "web", false));
+        assert!(!allowed(channel, "db", false));
+        assert!(!allowed(channel, "web", true));
+        profile.forwards.push(reverse("dns", true));
+        assert!(get_reverse_targets(&profile).is_err());
+    }
+}
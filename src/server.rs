@@ -15,10 +15,11 @@ use hbb_common::{
     allow_err,
     anyhow::Context,
     bail,
-    config::{Config, CONNECT_TIMEOUT, RELAY_PORT},
+    config::{self, Config, CONNECT_TIMEOUT, RELAY_PORT},
     log,
     message_proto::*,
     protobuf::{Enum, Message as _},
+    quic,
     rendezvous_proto::*,
     socket_client,
     sodiumoxide::crypto::{box_, sign},
@@ -30,7 +31,9 @@ use service::{EmptyExtraFieldService, GenericService, Service, Subscriber};
 
 use crate::ipc::Data;
 
+mod acl;
 pub mod audio_service;
+pub mod audit;
 cfg_if::cfg_if! {
 if #[cfg(not(any(target_os = "android", target_os = "ios")))] {
 mod clipboard_service;
@@ -56,7 +59,9 @@ pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
 mod connection;
+pub mod control_api;
 pub mod display_service;
+pub mod login_failure;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
@@ -116,7 +121,12 @@ pub fn new() -> ServerPtr {
     Arc::new(RwLock::new(server))
 }
 
-async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) -> ResultType<()> {
+async fn accept_connection_(
+    server: ServerPtr,
+    socket: Stream,
+    peer_addr: SocketAddr,
+    secure: bool,
+) -> ResultType<()> {
     let local_addr = socket.local_addr();
     drop(socket);
     // even we drop socket, below still may fail if not use reuse_addr,
@@ -124,10 +134,29 @@ async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) ->
     // see “Only one usage of each socket address is normally permitted” on windows sometimes,
     let listener = new_listener(local_addr, true).await?;
     log::info!("Server listening on: {}", &listener.local_addr()?);
-    if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
-        stream.set_nodelay(true).ok();
-        let stream_addr = stream.local_addr()?;
-        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure).await?;
+    let quic = async {
+        if config::is_quic_allowed() {
+            match quic::accept(local_addr, peer_addr, CONNECT_TIMEOUT).await {
+                Ok(res) => return res,
+                // the peer may still come over TCP
+                Err(err) => log::info!("Failed to accept QUIC connection: {}", err),
+            }
+        }
+        std::future::pending().await
+    };
+    tokio::select! {
+        res = timeout(CONNECT_TIMEOUT, listener.accept()) => {
+            if let Ok((stream, addr)) = res? {
+                stream.set_nodelay(true).ok();
+                let stream_addr = stream.local_addr()?;
+                create_connection(server, Stream::from(stream, stream_addr), None, addr, secure)
+                    .await?;
+            }
+        }
+        (stream, sub_streams, addr) = quic => {
+            log::info!("QUIC connection from {}", addr);
+            create_connection(server, stream, Some(sub_streams), addr, secure).await?;
+        }
     }
     Ok(())
 }
@@ -137,6 +166,16 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+) -> ResultType<()> {
+    create_connection(server, stream, None, addr, secure).await
+}
+
+async fn create_connection(
+    server: ServerPtr,
+    stream: Stream,
+    mut sub_streams: Option<quic::SubStreams>,
+    addr: SocketAddr,
+    secure: bool,
 ) -> ResultType<()> {
     let mut stream = stream;
     let id = server.write().unwrap().get_new_id();
@@ -202,7 +241,10 @@ pub async fn create_tcp_connection(
             .ok();
         log::info!("wake up macos");
     }
-    Connection::start(addr, stream, id, Arc::downgrade(&server)).await;
+    if let Some(sub_streams) = sub_streams.as_mut() {
+        sub_streams.secure_with(&stream);
+    }
+    Connection::start(addr, stream, sub_streams, id, Arc::downgrade(&server)).await;
     Ok(())
 }
 
@@ -212,7 +254,7 @@ pub async fn accept_connection(
     peer_addr: SocketAddr,
     secure: bool,
 ) {
-    if let Err(err) = accept_connection_(server, socket, secure).await {
+    if let Err(err) = accept_connection_(server, socket, peer_addr, secure).await {
         log::error!("Failed to accept connection from {}: {}", peer_addr, err);
     }
 }
@@ -488,6 +530,12 @@ pub async fn start_server(is_server: bool, no_server: bool) {
                 std::process::exit(-1);
             }
         });
+        #[cfg(all(unix, not(any(target_os = "android", target_os = "ios"))))]
+        tokio::spawn(async {
+            if let Err(err) = control_api::start().await {
+                log::error!("Failed to start control api: {}", err);
+            }
+        });
         input_service::fix_key_down_timeout_loop();
         #[cfg(target_os = "linux")]
         if input_service::wayland_use_uinput() {
@@ -0,0 +1,168 @@
+// Per-peer access control, the rules are a json array in the option `access-control-rules`.
+// The first rule matching the remote ID, ip and current time decides the permissions,
+// the connection is refused if no rule matches.
+// The rules are evaluated after the peer is authenticated. The remote ID is claimed by the
+// peer and not verified, only `ips` and the time restrict who can connect, `ids` select the
+// permissions of a peer which already knows the password.
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    bail,
+    chrono::{Datelike, Local, NaiveTime, Timelike},
+    config::{keys, Config},
+    log, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{net::IpAddr, str::FromStr};
+
+pub const PERMISSION_KEYBOARD: &str = "keyboard";
+pub const PERMISSION_CLIPBOARD: &str = "clipboard";
+pub const PERMISSION_AUDIO: &str = "audio";
+pub const PERMISSION_FILE: &str = "file";
+pub const PERMISSION_TUNNEL: &str = "tunnel";
+pub const PERMISSION_RESTART: &str = "restart";
+pub const PERMISSION_RECORDING: &str = "recording";
+pub const PERMISSION_BLOCK_INPUT: &str = "block_input";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TimeWindow {
+    // 0 is Monday, all days if empty
+    #[serde(default)]
+    pub days: Vec<u32>,
+    // "HH:MM" in local time, the window crosses midnight if end is before start
+    #[serde(default)]
+    pub start: String,
+    #[serde(default)]
+    pub end: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AccessRule {
+    #[serde(default)]
+    pub name: String,
+    // remote IDs as claimed by the peer, any if empty
+    #[serde(default)]
+    pub ids: Vec<String>,
+    // ip or cidr, any if empty
+    #[serde(default)]
+    pub ips: Vec<String>,
+    #[serde(default)]
+    pub time: Option<TimeWindow>,
+    // granted permissions besides viewing the screen, still limited by the global options
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+impl TimeWindow {
+    fn contains(&self, weekday: u32, time: NaiveTime) -> bool {
+        if !self.days.is_empty() && !self.days.contains(&weekday) {
+            return false;
+        }
+        let start = NaiveTime::parse_from_str(&self.start, "%H:%M").ok();
+        let end = NaiveTime::parse_from_str(&self.end, "%H:%M").ok();
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => start <= time && time < end,
+            (Some(start), Some(end)) => time >= start || time < end,
+            (Some(start), None) => time >= start,
+            (None, Some(end)) => time < end,
+            (None, None) => true,
+        }
+    }
+}
+
+impl AccessRule {
+    fn matches(&self, id: &str, ip: Option<IpAddr>) -> bool {
+        if !self.ids.is_empty() && !self.ids.iter().any(|x| x == id) {
+            return false;
+        }
+        if !self.ips.is_empty() {
+            let ip = match ip {
+                Some(ip) => ip,
+                None => return false,
+            };
+            if !self
+                .ips
+                .iter()
+                .any(|x| IpCidr::from_str(x).map_or(false, |y| y.contains(ip)))
+            {
+                return false;
+            }
+        }
+        self.in_time_window()
+    }
+
+    pub fn in_time_window(&self) -> bool {
+        match self.time.as_ref() {
+            Some(window) => {
+                let now = Local::now();
+                let time = NaiveTime::from_hms_opt(now.hour(), now.minute(), now.second())
+                    .unwrap_or_default();
+                window.contains(now.weekday().num_days_from_monday(), time)
+            }
+            None => true,
+        }
+    }
+
+    pub fn permit(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|x| x == permission)
+    }
+}
+
+/// The rule for the remote ID and ip, None if access control is not enabled.
+/// Err if no rule matches or the rules are invalid, the connection should be refused.
+pub fn get_rule(id: &str, ip: &str) -> ResultType<Option<AccessRule>> {
+    let rules = Config::get_option(keys::OPTION_ACCESS_CONTROL_RULES);
+    if rules.is_empty() {
+        return Ok(None);
+    }
+    let rules: Vec<AccessRule> = match serde_json::from_str(&rules) {
+        Ok(rules) => rules,
+        Err(err) => {
+            log::error!("Invalid access control rules: {}", err);
+            bail!("Invalid access control rules");
+        }
+    };
+    let ip = ip.parse().ok();
+    match rules.into_iter().find(|r| r.matches(id, ip)) {
+        Some(rule) => Ok(Some(rule)),
+        None => bail!("No access control rule for {} from {:?}", id, ip),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_access_rule() {
+        let rule = AccessRule {
+            ids: vec!["123456789".to_owned()],
+            ips: vec!["10.0.0.0/8".to_owned()],
+            permissions: vec![PERMISSION_FILE.to_owned()],
+            ..Default::default()
+        };
+        assert!(rule.matches("123456789", "10.1.2.3".parse().ok()));
+        assert!(!rule.matches("123456789", "192.168.1.1".parse().ok()));
+        assert!(!rule.matches("987654321", "10.1.2.3".parse().ok()));
+        assert!(!rule.matches("123456789", None));
+        assert!(rule.permit(PERMISSION_FILE));
+        assert!(!rule.permit(PERMISSION_KEYBOARD));
+
+        let window = TimeWindow {
+            days: vec![0, 1, 2, 3, 4],
+            start: "22:00".to_owned(),
+            end: "06:00".to_owned(),
+        };
+        let t = |s| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        assert!(window.contains(0, t("23:00")));
+        assert!(window.contains(4, t("05:59")));
+        assert!(!window.contains(4, t("06:00")));
+        assert!(!window.contains(5, t("23:00")));
+        let window = TimeWindow {
+            start: "09:00".to_owned(),
+            end: "18:00".to_owned(),
+            ..Default::default()
+        };
+        assert!(window.contains(6, t("09:00")));
+        assert!(!window.contains(6, t("18:00")));
+    }
+}
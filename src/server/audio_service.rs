@@ -15,15 +15,33 @@
 use super::*;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
+use hbb_common::config::keys;
 use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_os = "android"))]
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+// 10ms in 48000 stereo
+#[cfg(not(target_os = "android"))]
+const MIX_FRAME_LEN: usize = 480 * 2;
+// A stalled source, e.g. the loopback while nothing is playing, is padded with silence
+// once the other one has buffered this many frames.
+#[cfg(not(target_os = "android"))]
+const MIX_MAX_BUFFERED_FRAMES: usize = 3;
+// The loopback captures the voice of the peer played on this side too, so it is left out of the
+// mix while the voice is audible, otherwise the peer hears its own echo.
+const PEER_VOICE_THRESHOLD: f32 = 0.02;
+// longer than the playback latency
+#[cfg(not(target_os = "android"))]
+const PEER_VOICE_HOLD_MS: i64 = 1000;
+static PEER_VOICE_TIME: AtomicI64 = AtomicI64::new(0);
 
 lazy_static::lazy_static! {
     static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
+    static ref AUDIO_PROFILES: Arc::<Mutex::<HashMap<i32, AudioProfile>>> = Default::default();
 }
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
@@ -60,6 +78,9 @@ pub fn set_voice_call_input_device(device: Option<String>, set_if_present: bool)
 
 #[inline]
 fn get_audio_input() -> String {
+    if get_mix_input().is_some() {
+        return Config::get_option("audio-input");
+    }
     VOICE_CALL_INPUT_DEVICE
         .lock()
         .unwrap()
@@ -67,6 +88,175 @@ fn get_audio_input() -> String {
         .unwrap_or(Config::get_option("audio-input"))
 }
 
+// The microphone mixed with the system audio during a voice call, if mixing is enabled.
+fn get_mix_input() -> Option<String> {
+    let device = VOICE_CALL_INPUT_DEVICE.lock().unwrap().clone()?;
+    if Config::get_option(keys::OPTION_VOICE_CALL_AUDIO_MIXING) != "Y" {
+        return None;
+    }
+    // no loopback without the audio input set, i.e. the virtual device on mac
+    #[cfg(target_os = "macos")]
+    if Config::get_option("audio-input").is_empty() {
+        return None;
+    }
+    Some(device)
+}
+
+// The options requiring a restart of the audio service if changed.
+pub fn get_mix_options() -> Vec<String> {
+    [
+        keys::OPTION_VOICE_CALL_AUDIO_MIXING,
+        keys::OPTION_AUDIO_SYSTEM_VOLUME,
+        keys::OPTION_AUDIO_MIC_VOLUME,
+    ]
+    .iter()
+    .map(|k| Config::get_option(k))
+    .collect()
+}
+
+// Volumes of the system audio and the microphone in the mix, 100 (percent) by default.
+fn get_mix_volumes() -> (f32, f32) {
+    let get = |k: &str| {
+        Config::get_option(k)
+            .parse::<u32>()
+            .map(|v| v.min(200) as f32 / 100.)
+            .unwrap_or(1.)
+    };
+    (
+        get(keys::OPTION_AUDIO_SYSTEM_VOLUME),
+        get(keys::OPTION_AUDIO_MIC_VOLUME),
+    )
+}
+
+/// Mix two interleaved frames of the same format, the shorter one is padded with silence.
+pub fn mix_audio(a: &[f32], a_volume: f32, b: &[f32], b_volume: f32) -> Vec<f32> {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let x = a.get(i).map_or(0., |x| x * a_volume) + b.get(i).map_or(0., |x| x * b_volume);
+            x.clamp(-1., 1.)
+        })
+        .collect()
+}
+
+/// Called with the decoded voice of the peer before playing it.
+pub fn on_peer_voice_decoded(data: &[f32]) {
+    if data.iter().any(|x| x.abs() > PEER_VOICE_THRESHOLD) {
+        PEER_VOICE_TIME.store(hbb_common::get_time(), Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn is_peer_voice_audible() -> bool {
+    hbb_common::get_time() - PEER_VOICE_TIME.load(Ordering::SeqCst) < PEER_VOICE_HOLD_MS
+}
+
+/// Mixes the system audio and the microphone captured by separate streams, in the same format.
+#[cfg(not(target_os = "android"))]
+pub struct Mixer {
+    buffers: [VecDeque<f32>; 2],
+    volumes: [f32; 2],
+}
+
+#[cfg(not(target_os = "android"))]
+impl Mixer {
+    pub fn new(volumes: [f32; 2]) -> Self {
+        Self {
+            buffers: Default::default(),
+            volumes,
+        }
+    }
+
+    /// Buffer the data of the source `index`, 0 for the system audio, returns the mixed frames.
+    pub fn push(&mut self, index: usize, data: &[f32]) -> Vec<Vec<f32>> {
+        self.buffers[index].extend(data);
+        let mut frames = vec![];
+        loop {
+            let (a, b) = (self.buffers[0].len(), self.buffers[1].len());
+            if !(a >= MIX_FRAME_LEN && b >= MIX_FRAME_LEN)
+                && a.max(b) < MIX_FRAME_LEN * MIX_MAX_BUFFERED_FRAMES
+            {
+                break;
+            }
+            let a: Vec<f32> = self.buffers[0].drain(0..a.min(MIX_FRAME_LEN)).collect();
+            let b: Vec<f32> = self.buffers[1].drain(0..b.min(MIX_FRAME_LEN)).collect();
+            let volume = if is_peer_voice_audible() {
+                0.
+            } else {
+                self.volumes[0]
+            };
+            frames.push(mix_audio(&a, volume, &b, self.volumes[1]));
+        }
+        frames
+    }
+}
+
+/// Set the profile requested by the connection, None to remove it.
+pub fn set_profile(conn_id: i32, profile: Option<AudioProfile>) {
+    let mut lock = AUDIO_PROFILES.lock().unwrap();
+    let old = get_profile_(&lock);
+    match profile {
+        Some(profile) => lock.insert(conn_id, profile),
+        None => lock.remove(&conn_id),
+    };
+    let new = get_profile_(&lock);
+    drop(lock);
+    if old != new {
+        log::info!("audio profile changed to {:?}", new);
+        restart();
+    }
+}
+
+fn get_profile() -> AudioProfile {
+    get_profile_(&AUDIO_PROFILES.lock().unwrap())
+}
+
+// The audio is shared by all the connections, the profile using the least bandwidth wins.
+fn get_profile_(profiles: &HashMap<i32, AudioProfile>) -> AudioProfile {
+    let rank = |p: &AudioProfile| match p {
+        AudioProfile::LowBandwidth => 0,
+        AudioProfile::Voice => 1,
+        AudioProfile::Music => 2,
+        AudioProfile::AudioProfileNotSet | AudioProfile::AudioProfileDefault => 3,
+    };
+    profiles
+        .values()
+        .min_by_key(|p| rank(p))
+        .cloned()
+        .unwrap_or(AudioProfile::AudioProfileNotSet)
+}
+
+// The opus encoder of the current profile, the input is buffered to the frame size of the profile.
+struct AudioEncoder {
+    encoder: Encoder,
+    // 0 to encode the input as is
+    frame_len: usize,
+    buffer: Vec<f32>,
+}
+
+impl AudioEncoder {
+    fn new(sample_rate: u32, channels: magnum_opus::Channels) -> ResultType<Self> {
+        let profile = get_profile();
+        let (application, bitrate, frame_ms) = match profile {
+            AudioProfile::Voice => (Voip, Some(24_000), 20),
+            AudioProfile::Music => (Audio, Some(128_000), 20),
+            AudioProfile::LowBandwidth => (Voip, Some(12_000), 40),
+            AudioProfile::AudioProfileNotSet | AudioProfile::AudioProfileDefault => {
+                (LowDelay, None, 0)
+            }
+        };
+        let mut encoder = Encoder::new(sample_rate, channels, application)?;
+        if let Some(bitrate) = bitrate {
+            encoder.set_bitrate(magnum_opus::Bitrate::Bits(bitrate))?;
+        }
+        log::info!("audio encoder of profile {:?}", profile);
+        Ok(Self {
+            encoder,
+            frame_len: sample_rate as usize * frame_ms / 1000 * channels as usize,
+            buffer: Vec::new(),
+        })
+    }
+}
+
 pub fn restart() {
     log::info!("restart the audio service, freezing now...");
     if RESTARTING.load(Ordering::SeqCst) {
@@ -82,19 +272,37 @@ mod pa_impl {
     pub async fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
         hbb_common::sleep(0.1).await; // one moment to wait for _pa ipc
         RESTARTING.store(false, Ordering::SeqCst);
+        let _call_on_ret = crate::common::SimpleCallOnReturn {
+            b: true,
+            f: Box::new(super::video_service::clear_record_audio_format),
+        };
         #[cfg(target_os = "linux")]
         let mut stream = crate::ipc::connect(1000, "_pa").await?;
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut encoder = AudioEncoder::new(crate::platform::PA_SAMPLE_RATE, Stereo)?;
+        // The microphone is mixed by the pa ipc server, which records both.
+        #[cfg(target_os = "linux")]
+        let config = match super::get_mix_input() {
+            Some(mix_input) => {
+                let (volume, mix_volume) = super::get_mix_volumes();
+                (
+                    "audio-mix".to_owned(),
+                    serde_json::json!({
+                        "input": super::get_audio_input(),
+                        "mix_input": mix_input,
+                        "volumes": [volume, mix_volume],
+                    })
+                    .to_string(),
+                )
+            }
+            None => ("audio-input".to_owned(), super::get_audio_input()),
+        };
         #[cfg(target_os = "linux")]
         allow_err!(
             stream
-                .send(&crate::ipc::Data::Config((
-                    "audio-input".to_owned(),
-                    Some(super::get_audio_input())
-                )))
+                .send(&crate::ipc::Data::Config((config.0, Some(config.1))))
                 .await
         );
         #[cfg(target_os = "linux")]
@@ -146,19 +354,25 @@ mod cpal_impl {
         BufferSize, Device, Host, InputCallbackInfo, StreamConfig, SupportedStreamConfig,
     };
 
+    use std::collections::VecDeque;
+
+    // The format of the mixed audio.
+    const MIX_SAMPLE_RATE: u32 = 48000;
+
     lazy_static::lazy_static! {
         static ref HOST: Host = cpal::default_host();
-        static ref INPUT_BUFFER: Arc<Mutex<std::collections::VecDeque<f32>>> = Default::default();
+        static ref INPUT_BUFFER: Arc<Mutex<VecDeque<f32>>> = Default::default();
     }
 
     #[derive(Default)]
     pub struct State {
-        stream: Option<(Box<dyn StreamTrait>, Arc<Message>)>,
+        stream: Option<(Vec<Box<dyn StreamTrait>>, Arc<Message>)>,
     }
 
     impl super::service::Reset for State {
         fn reset(&mut self) {
             self.stream.take();
+            super::video_service::clear_record_audio_format();
         }
     }
 
@@ -208,7 +422,7 @@ mod cpal_impl {
         sample_rate: u32,
         device_channel: u16,
         encode_channel: u16,
-        encoder: &mut Encoder,
+        encoder: &mut AudioEncoder,
         sp: &GenericService,
     ) {
         let mut data = data;
@@ -280,8 +494,11 @@ mod cpal_impl {
         Ok((device, format))
     }
 
-    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
+    fn play(sp: &GenericService) -> ResultType<(Vec<Box<dyn StreamTrait>>, Arc<Message>)> {
         use cpal::SampleFormat::*;
+        if let Some(mix_input) = super::get_mix_input() {
+            return play_mixed(sp, &mix_input);
+        }
         let (device, config) = get_device()?;
         let sp = sp.clone();
         // Sample rate must be one of 8000, 12000, 16000, 24000, or 48000.
@@ -313,11 +530,104 @@ mod cpal_impl {
         };
         stream.play()?;
         Ok((
-            Box::new(stream),
+            vec![Box::new(stream)],
             Arc::new(create_format_msg(sample_rate, ch as _)),
         ))
     }
 
+    // Capture the system audio and the microphone, mixed into one stream.
+    fn play_mixed(
+        sp: &GenericService,
+        mix_input: &str,
+    ) -> ResultType<(Vec<Box<dyn StreamTrait>>, Arc<Message>)> {
+        use cpal::SampleFormat::*;
+        let (volume, mix_volume) = super::get_mix_volumes();
+        let mixer = Arc::new(Mutex::new((
+            Mixer::new([volume, mix_volume]),
+            AudioEncoder::new(MIX_SAMPLE_RATE, Stereo)?,
+        )));
+        let mut streams: Vec<Box<dyn StreamTrait>> = Vec::new();
+        for (index, (device, config)) in [get_device()?, get_audio_input(mix_input)?]
+            .into_iter()
+            .enumerate()
+        {
+            let sp = sp.clone();
+            let m = mixer.clone();
+            let stream: Box<dyn StreamTrait> = match config.sample_format() {
+                I8 => Box::new(build_mixed_stream::<i8>(device, &config, sp, m, index)?),
+                I16 => Box::new(build_mixed_stream::<i16>(device, &config, sp, m, index)?),
+                I32 => Box::new(build_mixed_stream::<i32>(device, &config, sp, m, index)?),
+                I64 => Box::new(build_mixed_stream::<i64>(device, &config, sp, m, index)?),
+                U8 => Box::new(build_mixed_stream::<u8>(device, &config, sp, m, index)?),
+                U16 => Box::new(build_mixed_stream::<u16>(device, &config, sp, m, index)?),
+                U32 => Box::new(build_mixed_stream::<u32>(device, &config, sp, m, index)?),
+                U64 => Box::new(build_mixed_stream::<u64>(device, &config, sp, m, index)?),
+                F32 => Box::new(build_mixed_stream::<f32>(device, &config, sp, m, index)?),
+                F64 => Box::new(build_mixed_stream::<f64>(device, &config, sp, m, index)?),
+                f => bail!("unsupported audio format: {:?}", f),
+            };
+            stream.play()?;
+            streams.push(stream);
+        }
+        unsafe {
+            AUDIO_ZERO_COUNT = 0;
+        }
+        Ok((streams, Arc::new(create_format_msg(MIX_SAMPLE_RATE, 2))))
+    }
+
+    fn build_mixed_stream<T>(
+        device: cpal::Device,
+        config: &cpal::SupportedStreamConfig,
+        sp: GenericService,
+        mixer: Arc<Mutex<(Mixer, AudioEncoder)>>,
+        index: usize,
+    ) -> ResultType<cpal::Stream>
+    where
+        T: cpal::SizedSample + dasp::sample::ToSample<f32>,
+    {
+        let err_fn = move |err| {
+            log::trace!("an error occurred on stream: {}", err);
+        };
+        let sample_rate_0 = config.sample_rate().0;
+        let device_channel = config.channels();
+        let stream_config = StreamConfig {
+            channels: device_channel,
+            sample_rate: config.sample_rate(),
+            buffer_size: BufferSize::Default,
+        };
+        let stream = device.build_input_stream(
+            &stream_config,
+            move |data: &[T], _: &InputCallbackInfo| {
+                let mut buffer: Vec<f32> = data.iter().map(|s| T::to_sample(*s)).collect();
+                if sample_rate_0 != MIX_SAMPLE_RATE {
+                    buffer = crate::common::audio_resample(
+                        &buffer,
+                        sample_rate_0,
+                        MIX_SAMPLE_RATE,
+                        device_channel,
+                    );
+                }
+                if device_channel != 2 {
+                    buffer = crate::common::audio_rechannel(
+                        buffer,
+                        MIX_SAMPLE_RATE,
+                        MIX_SAMPLE_RATE,
+                        device_channel,
+                        2,
+                    );
+                }
+                let mut lock = mixer.lock().unwrap();
+                let (mixer, encoder) = &mut *lock;
+                for frame in mixer.push(index, &buffer) {
+                    send_f32(&frame, encoder, &sp);
+                }
+            },
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
     fn build_input_stream<T>(
         device: cpal::Device,
         config: &cpal::SupportedStreamConfig,
@@ -338,7 +648,7 @@ mod cpal_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let device_channel = config.channels();
-        let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let mut encoder = AudioEncoder::new(sample_rate, encode_channel)?;
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
         // Do not set `frame_size = sample_rate as usize / 100;`
@@ -384,8 +694,10 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     let format = AudioFormat {
         sample_rate,
         channels: channels as _,
+        profile: get_profile().into(),
         ..Default::default()
     };
+    super::video_service::set_record_audio_format(format.clone());
     let mut misc = Misc::new();
     misc.set_audio_format(format);
     let mut msg = Message::new();
@@ -399,7 +711,7 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
-fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+fn send_f32(data: &[f32], encoder: &mut AudioEncoder, sp: &GenericService) {
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -427,14 +739,17 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             let n = input_size / BATCH_SIZE;
             for i in 0..n {
                 match encoder
+                    .encoder
                     .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
                     Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
+                        let frame = AudioFrame {
                             data: data.into(),
                             ..Default::default()
-                        });
+                        };
+                        super::video_service::record_audio_frame(&frame);
+                        let mut msg_out = Message::new();
+                        msg_out.set_audio_frame(frame);
                         sp.send(msg_out);
                     }
                     Err(_) => {}
@@ -447,15 +762,86 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     }
 
     #[cfg(not(target_os = "android"))]
+    {
+        if encoder.frame_len == 0 {
+            encode_f32(data, &mut encoder.encoder, sp);
+            return;
+        }
+        encoder.buffer.extend_from_slice(data);
+        while encoder.buffer.len() >= encoder.frame_len {
+            let frame: Vec<f32> = encoder.buffer.drain(..encoder.frame_len).collect();
+            encode_f32(&frame, &mut encoder.encoder, sp);
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn encode_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     match encoder.encode_vec_float(data, data.len() * 6) {
         Ok(data) => {
-            let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
+            let frame = AudioFrame {
                 data: data.into(),
                 ..Default::default()
-            });
+            };
+            super::video_service::record_audio_frame(&frame);
+            let mut msg_out = Message::new();
+            msg_out.set_audio_frame(frame);
             sp.send(msg_out);
         }
         Err(_) => {}
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_profile() {
+        let mut profiles = HashMap::new();
+        assert_eq!(get_profile_(&profiles), AudioProfile::AudioProfileNotSet);
+        profiles.insert(1, AudioProfile::Music);
+        assert_eq!(get_profile_(&profiles), AudioProfile::Music);
+        profiles.insert(2, AudioProfile::Voice);
+        assert_eq!(get_profile_(&profiles), AudioProfile::Voice);
+        profiles.insert(3, AudioProfile::LowBandwidth);
+        assert_eq!(get_profile_(&profiles), AudioProfile::LowBandwidth);
+        profiles.remove(&3);
+        assert_eq!(get_profile_(&profiles), AudioProfile::Voice);
+        profiles.remove(&2);
+        profiles.insert(4, AudioProfile::AudioProfileNotSet);
+        assert_eq!(get_profile_(&profiles), AudioProfile::Music);
+    }
+
+    #[test]
+    fn test_mix_audio() {
+        assert_eq!(mix_audio(&[0.5, -0.5], 1., &[0.25, 0.25], 2.), vec![1., 0.]);
+        // clamped
+        assert_eq!(mix_audio(&[0.8, -0.8], 1., &[0.8, -0.8], 1.), vec![1., -1.]);
+        // the shorter one is padded with silence
+        assert_eq!(
+            mix_audio(&[0.5], 0.5, &[0.125, 0.25], 1.),
+            vec![0.375, 0.25]
+        );
+        assert_eq!(mix_audio(&[], 1., &[], 1.), Vec::<f32>::new());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn test_mixer() {
+        const F: usize = MIX_FRAME_LEN;
+        let mut mixer = Mixer::new([1., 1.]);
+        assert!(mixer.push(0, &[0.25; F]).is_empty());
+        assert_eq!(mixer.push(1, &[0.5; F + 2]), vec![vec![0.75; F]]);
+        // the stalled microphone is padded once the system audio is buffered enough
+        let n = F * MIX_MAX_BUFFERED_FRAMES;
+        assert!(mixer.push(0, &vec![0.25; n - 1]).is_empty());
+        let frames = mixer.push(0, &[0.25]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][..2], [0.75, 0.75]);
+        assert_eq!(frames[0][2..], vec![0.25; F - 2][..]);
+        // the buffered frames are mixed as soon as the microphone is back
+        assert_eq!(mixer.push(1, &vec![0.5; n]), vec![vec![0.75; F]; 2]);
+        assert!(mixer.push(0, &[]).is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+// Local audit sinks, receiving the same events posted to the audit server.
+// The built-in file and syslog sinks are configured in the root config only.
+use hbb_common::{
+    anyhow::anyhow,
+    chrono,
+    config::{keys, Config, RootConfig},
+    lazy_static, log, ResultType,
+};
+use serde_json::{json, Value};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
+};
+
+const DEFAULT_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_MAX_FILES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    Conn,
+    File,
+    Alarm,
+}
+
+impl AuditKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Conn => "conn",
+            AuditKind::File => "file",
+            AuditKind::Alarm => "alarm",
+        }
+    }
+}
+
+pub trait AuditSink: Send {
+    fn name(&self) -> &'static str;
+    fn write(&mut self, kind: AuditKind, record: &Value) -> ResultType<()>;
+}
+
+#[derive(Default)]
+struct Sinks {
+    // options the built-in sinks are created from
+    options: Vec<String>,
+    builtin: Vec<Box<dyn AuditSink>>,
+    extra: Vec<Box<dyn AuditSink>>,
+}
+
+enum Event {
+    Record(AuditKind, Value),
+    AddSink(Box<dyn AuditSink>),
+}
+
+lazy_static::lazy_static! {
+    // The sinks block on io, so they are written in a dedicated thread.
+    static ref SENDER: Mutex<Sender<Event>> = Mutex::new(start_writer());
+}
+
+fn start_writer() -> Sender<Event> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let mut sinks = Sinks::default();
+        for event in rx {
+            match event {
+                Event::Record(kind, record) => sinks.write(kind, &record),
+                Event::AddSink(sink) => sinks.extra.push(sink),
+            }
+        }
+    });
+    tx
+}
+
+/// Add a sink besides the built-in ones.
+pub fn add_sink(sink: Box<dyn AuditSink>) {
+    SENDER.lock().unwrap().send(Event::AddSink(sink)).ok();
+}
+
+/// Write the event to all the sinks.
+pub fn record(kind: AuditKind, v: &Value) {
+    let record = new_record(kind, v, chrono::Local::now());
+    SENDER
+        .lock()
+        .unwrap()
+        .send(Event::Record(kind, record))
+        .ok();
+}
+
+fn new_record(kind: AuditKind, v: &Value, time: chrono::DateTime<chrono::Local>) -> Value {
+    let mut record = json!({
+        "time": time.to_rfc3339(),
+        "kind": kind.as_str(),
+    });
+    if let (Some(r), Some(v)) = (record.as_object_mut(), v.as_object()) {
+        for (k, v) in v {
+            r.insert(k.clone(), v.clone());
+        }
+    }
+    record
+}
+
+impl Sinks {
+    fn write(&mut self, kind: AuditKind, record: &Value) {
+        let options: Vec<String> = [
+            keys::OPTION_AUDIT_LOG_FILE,
+            keys::OPTION_AUDIT_LOG_MAX_SIZE,
+            keys::OPTION_AUDIT_LOG_MAX_FILES,
+            keys::OPTION_AUDIT_SYSLOG,
+        ]
+        .iter()
+        .map(|k| RootConfig::get_option(k))
+        .collect();
+        if self.options != options {
+            self.builtin = new_builtin_sinks(&options);
+            self.options = options;
+        }
+        for sink in self.builtin.iter_mut().chain(self.extra.iter_mut()) {
+            if let Err(err) = sink.write(kind, record) {
+                log::error!("Failed to write audit to {}: {}", sink.name(), err);
+            }
+        }
+    }
+}
+
+fn new_builtin_sinks(options: &[String]) -> Vec<Box<dyn AuditSink>> {
+    let mut sinks: Vec<Box<dyn AuditSink>> = Vec::new();
+    let file = &options[0];
+    if !file.is_empty() {
+        // "Y" for the default path
+        let path = if file == "Y" {
+            Config::log_path().join("audit.log")
+        } else {
+            PathBuf::from(file)
+        };
+        let max_size = options[1].parse().unwrap_or(DEFAULT_MAX_SIZE_MB) * 1024 * 1024;
+        let max_files = options[2].parse().unwrap_or(DEFAULT_MAX_FILES);
+        sinks.push(Box::new(FileSink::new(path, max_size, max_files)));
+    }
+    let syslog = &options[3];
+    if !syslog.is_empty() {
+        match SyslogSink::new(syslog) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => log::error!("Failed to create syslog audit sink: {}", err),
+        }
+    }
+    sinks
+}
+
+/// Append-only JSON lines, `audit.log` is rotated to `audit.log.1` ... `audit.log.<max_files>`.
+struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl FileSink {
+    fn new(path: PathBuf, max_size: u64, max_files: usize) -> Self {
+        Self {
+            path,
+            max_size,
+            max_files,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{}", n));
+        p.into()
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path).ok();
+            return;
+        }
+        std::fs::remove_file(self.rotated_path(self.max_files)).ok();
+        for n in (1..self.max_files).rev() {
+            std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1)).ok();
+        }
+        std::fs::rename(&self.path, self.rotated_path(1)).ok();
+    }
+}
+
+impl AuditSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write(&mut self, _kind: AuditKind, record: &Value) -> ResultType<()> {
+        let mut line = record.to_string();
+        line.push('\n');
+        if self.file.is_some() && self.size + line.len() as u64 > self.max_size {
+            self.rotate();
+        }
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir).ok();
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().ok_or(anyhow!("audit file is None"))?;
+        file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// "local" for the local syslog daemon (unix only), or "host:port" of a remote one over udp.
+enum SyslogSink {
+    #[cfg(unix)]
+    Local,
+    Remote(std::net::UdpSocket),
+}
+
+// facility auth (4), https://www.rfc-editor.org/rfc/rfc5424#section-6.2.1
+const SYSLOG_FACILITY_AUTH: u8 = 4;
+const SYSLOG_SEVERITY_NOTICE: u8 = 5;
+const SYSLOG_SEVERITY_WARNING: u8 = 4;
+
+impl SyslogSink {
+    fn new(target: &str) -> ResultType<Self> {
+        if target == "local" {
+            #[cfg(unix)]
+            return Ok(Self::Local);
+            #[cfg(not(unix))]
+            hbb_common::bail!("Local syslog is not supported");
+        }
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(target)?;
+        Ok(Self::Remote(socket))
+    }
+}
+
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn write(&mut self, kind: AuditKind, record: &Value) -> ResultType<()> {
+        let severity = if kind == AuditKind::Alarm {
+            SYSLOG_SEVERITY_WARNING
+        } else {
+            SYSLOG_SEVERITY_NOTICE
+        };
+        let msg = record.to_string();
+        match self {
+            #[cfg(unix)]
+            Self::Local => {
+                let msg = std::ffi::CString::new(msg)?;
+                unsafe {
+                    hbb_common::libc::syslog(
+                        ((SYSLOG_FACILITY_AUTH as i32) << 3) | severity as i32,
+                        b"%s\0".as_ptr() as _,
+                        msg.as_ptr(),
+                    );
+                }
+            }
+            Self::Remote(socket) => {
+                // RFC 5424 without structured data
+                let pri = SYSLOG_FACILITY_AUTH * 8 + severity;
+                let packet = format!(
+                    "<{}>1 {} {} {} - audit - {}",
+                    pri,
+                    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    crate::common::hostname(),
+                    crate::get_app_name(),
+                    msg
+                );
+                socket.send(packet.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new_record() {
+        let time = chrono::Local::now();
+        let v = json!({"id": "123456789", "kind": "ignored"});
+        let record = new_record(AuditKind::File, &v, time);
+        assert_eq!(record["time"], time.to_rfc3339());
+        assert_eq!(record["id"], "123456789");
+        // the fields of the event come last
+        assert_eq!(record["kind"], "ignored");
+        let record = new_record(AuditKind::Alarm, &json!(1), time);
+        assert_eq!(record, json!({"time": time.to_rfc3339(), "kind": "alarm"}));
+    }
+
+    #[test]
+    fn test_file_sink_rotate() {
+        let dir = std::env::temp_dir().join(format!("audit_test_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join("audit.log");
+        let record = json!({"n": 0});
+        let line_len = record.to_string().len() as u64 + 1;
+        let mut sink = FileSink::new(path.clone(), line_len * 2, 2);
+        for _ in 0..7 {
+            sink.write(AuditKind::Conn, &record).unwrap();
+        }
+        let lines = |p: &PathBuf| {
+            std::fs::read_to_string(p)
+                .map(|s| s.lines().count())
+                .unwrap_or_default()
+        };
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&sink.rotated_path(1)), 2);
+        assert_eq!(lines(&sink.rotated_path(2)), 2);
+        assert!(!sink.rotated_path(3).exists());
+        // reopened, the size is continued
+        let mut sink = FileSink::new(path.clone(), line_len * 2, 0);
+        sink.write(AuditKind::Conn, &record).unwrap();
+        assert_eq!(lines(&path), 2);
+        sink.write(AuditKind::Conn, &record).unwrap();
+        assert_eq!(lines(&path), 1);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
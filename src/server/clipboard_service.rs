@@ -6,6 +6,12 @@ pub use crate::clipboard::{
 #[cfg(windows)]
 use crate::ipc::{self, ClipboardFile, ClipboardNonFile, Data};
 use clipboard_master::{CallbackResult, ClipboardHandler};
+use hbb_common::{
+    compress::{compress, decompress},
+    config::keys,
+    regex::Regex,
+};
+use serde_derive::Deserialize;
 use std::{
     io,
     sync::mpsc::{channel, RecvTimeoutError, Sender},
@@ -14,6 +20,168 @@ use std::{
 #[cfg(windows)]
 use tokio::runtime::Runtime;
 
+lazy_static::lazy_static! {
+    // the option and the policy parsed from it
+    static ref POLICY: Mutex<(String, Option<ClipboardPolicy>)> = Default::default();
+}
+
+const DEFAULT_REDACTION: &str = "[REDACTED]";
+
+// The json in the option `clipboard-policy`, e.g.
+// {"deny_formats": ["image"], "max_size": 1048576, "redact": [{"pattern": "\\b(?:\\d[ -]?){12,15}\\d\\b"}]}
+#[derive(Debug, Default, Deserialize)]
+struct ClipboardPolicyConfig {
+    // text, rtf, html, image or special, all formats are allowed if empty
+    #[serde(default)]
+    allow_formats: Vec<String>,
+    #[serde(default)]
+    deny_formats: Vec<String>,
+    // max size in bytes of the uncompressed content of each format, 0 for no limit
+    #[serde(default)]
+    max_size: usize,
+    // applied to text, rtf and html
+    #[serde(default)]
+    redact: Vec<RedactionConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RedactionConfig {
+    pattern: String,
+    #[serde(default)]
+    replacement: Option<String>,
+}
+
+#[derive(Debug)]
+struct ClipboardPolicy {
+    allow_formats: Vec<String>,
+    deny_formats: Vec<String>,
+    max_size: usize,
+    redact: Vec<(Regex, String)>,
+}
+
+impl ClipboardPolicy {
+    fn parse(s: &str) -> ResultType<Self> {
+        let config: ClipboardPolicyConfig = serde_json::from_str(s)?;
+        let mut redact = Vec::new();
+        for r in config.redact {
+            redact.push((
+                Regex::new(&r.pattern)?,
+                r.replacement.unwrap_or(DEFAULT_REDACTION.to_owned()),
+            ));
+        }
+        Ok(Self {
+            allow_formats: config.allow_formats,
+            deny_formats: config.deny_formats,
+            max_size: config.max_size,
+            redact,
+        })
+    }
+
+    fn format_name(c: &Clipboard) -> &'static str {
+        match c.format.enum_value() {
+            Ok(ClipboardFormat::Text) => "text",
+            Ok(ClipboardFormat::Rtf) => "rtf",
+            Ok(ClipboardFormat::Html) => "html",
+            Ok(ClipboardFormat::ImageRgba)
+            | Ok(ClipboardFormat::ImagePng)
+            | Ok(ClipboardFormat::ImageSvg) => "image",
+            _ => "special",
+        }
+    }
+
+    fn is_format_allowed(&self, name: &str) -> bool {
+        (self.allow_formats.is_empty() || self.allow_formats.iter().any(|x| x == name))
+            && !self.deny_formats.iter().any(|x| x == name)
+    }
+
+    // None if the format is not allowed or the content is too large.
+    fn apply(&self, mut c: Clipboard) -> Option<Clipboard> {
+        let name = Self::format_name(&c);
+        if !self.is_format_allowed(name) {
+            log::debug!("Clipboard format {} is denied by the policy", name);
+            return None;
+        }
+        let redact = !self.redact.is_empty() && ["text", "rtf", "html"].contains(&name);
+        if self.max_size == 0 && !redact {
+            return Some(c);
+        }
+        let data = if c.compress {
+            decompress(&c.content)
+        } else {
+            c.content.to_vec()
+        };
+        if self.max_size > 0 && data.len() > self.max_size {
+            log::debug!(
+                "Clipboard {} of {} bytes exceeds the policy limit {}",
+                name,
+                data.len(),
+                self.max_size
+            );
+            return None;
+        }
+        if redact {
+            let mut text = String::from_utf8_lossy(&data).into_owned();
+            for (re, replacement) in self.redact.iter() {
+                text = re.replace_all(&text, replacement.as_str()).into_owned();
+            }
+            let compressed = compress(text.as_bytes());
+            c.compress = compressed.len() < text.len();
+            c.content = if c.compress {
+                compressed.into()
+            } else {
+                text.into_bytes().into()
+            };
+        }
+        Some(c)
+    }
+
+    fn apply_msg(&self, msg: Message) -> Option<Message> {
+        let mut msg_out = Message::new();
+        match msg.union {
+            Some(message::Union::MultiClipboards(mcb)) => {
+                let clipboards: Vec<Clipboard> = mcb
+                    .clipboards
+                    .into_iter()
+                    .filter_map(|c| self.apply(c))
+                    .collect();
+                // the owner flag alone is not worth sending
+                if clipboards.iter().all(|c| Self::format_name(c) == "special") {
+                    return None;
+                }
+                msg_out.set_multi_clipboards(MultiClipboards {
+                    clipboards,
+                    ..Default::default()
+                });
+            }
+            Some(message::Union::Clipboard(c)) => {
+                msg_out.set_clipboard(self.apply(c)?);
+            }
+            _ => return Some(msg),
+        }
+        Some(msg_out)
+    }
+}
+
+// Apply the option `clipboard-policy` to the clipboard to send, the clipboard is not sent if the policy is invalid.
+fn apply_policy(msg: Message) -> Option<Message> {
+    let option = Config::get_option(keys::OPTION_CLIPBOARD_POLICY);
+    if option.is_empty() {
+        return Some(msg);
+    }
+    let mut policy = POLICY.lock().unwrap();
+    if policy.0 != option {
+        policy.1 = match ClipboardPolicy::parse(&option) {
+            Ok(p) => Some(p),
+            Err(err) => {
+                log::error!("Invalid clipboard policy: {}", err);
+                None
+            }
+        };
+        policy.0 = option;
+    }
+    policy.1.as_ref()?.apply_msg(msg)
+}
+
 struct Handler {
     sp: EmptyExtraFieldService,
     ctx: Option<ClipboardContext>,
@@ -94,6 +262,10 @@ impl ClipboardHandler for Handler {
 
 impl Handler {
     fn get_clipboard_msg(&mut self) -> Option<Message> {
+        apply_policy(self.get_clipboard_msg_()?)
+    }
+
+    fn get_clipboard_msg_(&mut self) -> Option<Message> {
         #[cfg(target_os = "windows")]
         if crate::common::is_server() && crate::platform::is_root() {
             match self.read_clipboard_from_cm_ipc() {
@@ -216,3 +388,38 @@ impl Handler {
         bail!("failed to get clipboard data from cm");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(s: &str) -> Clipboard {
+        Clipboard {
+            content: s.as_bytes().to_vec().into(),
+            format: ClipboardFormat::Text.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_clipboard_policy() {
+        let policy = ClipboardPolicy::parse(
+            r#"{"deny_formats": ["image"], "max_size": 64, "redact": [{"pattern": "\\b(?:\\d[ -]?){12,15}\\d\\b"}]}"#,
+        )
+        .unwrap();
+        let c = policy.apply(text("card 4111 1111 1111 1111 ok")).unwrap();
+        let data = if c.compress {
+            decompress(&c.content)
+        } else {
+            c.content.to_vec()
+        };
+        assert_eq!(String::from_utf8(data).unwrap(), "card [REDACTED] ok");
+        assert!(policy.apply(text(&"a".repeat(65))).is_none());
+        let image = Clipboard {
+            format: ClipboardFormat::ImagePng.into(),
+            ..Default::default()
+        };
+        assert!(policy.apply(image).is_none());
+        assert!(ClipboardPolicy::parse(r#"{"redact": [{"pattern": "("}]}"#).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use super::login_failure::LoginKind;
 use super::{input_service::*, *};
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::clipboard::{update_clipboard, ClipboardSide};
@@ -15,7 +16,8 @@ use crate::platform::WallPaperRemover;
 use crate::portable_service::client as portable_client;
 use crate::{
     client::{
-        new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
+        new_voice_call_request, new_voice_call_response, start_audio_thread_, MediaData,
+        MediaSender,
     },
     display_service, ipc, privacy_mode, video_service, VERSION,
 };
@@ -33,7 +35,7 @@ use hbb_common::{
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
     password_security::{self as password, ApproveMode},
-    sleep, timeout,
+    quic, sleep, timeout,
     tokio::{
         net::TcpStream,
         sync::mpsc,
@@ -56,14 +58,13 @@ use std::{
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use system_shutdown;
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use crate::virtual_display_manager;
 #[cfg(not(any(target_os = "ios")))]
 use std::collections::HashSet;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
-    static ref LOGIN_FAILURES: [Arc::<Mutex<HashMap<String, (i32, i32, i32)>>>; 2] = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<String, Session>>> = Default::default();
     static ref ALIVE_CONNS: Arc::<Mutex<Vec<i32>>> = Default::default();
     pub static ref AUTHED_CONNS: Arc::<Mutex<Vec<(i32, AuthConnType)>>> = Default::default();
@@ -168,6 +169,8 @@ pub struct Connection {
     inner: ConnInner,
     display_idx: usize,
     stream: super::Stream,
+    // the video, input and file transfer streams of QUIC
+    sub_streams: Option<quic::SubStreams>,
     server: super::ServerPtrWeak,
     hash: Hash,
     read_jobs: Vec<fs::TransferJob>,
@@ -175,6 +178,8 @@ pub struct Connection {
     file_timer: crate::RustDeskInterval,
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    // The forwards are opened by the client through `TunnelMessage`.
+    port_forward_multiplex: bool,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -186,6 +191,13 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     block_input: bool,
+    // Permissions are limited by the rule if access control is enabled.
+    access_rule: Option<acl::AccessRule>,
+    // the connection ends after the login without a session,
+    // refused by the access control or a Wake-on-LAN only login
+    no_session: bool,
+    // a Wake-on-LAN only login, answered once authenticated
+    wake_on_lan: Option<WakeOnLan>,
     last_test_delay: Option<Instant>,
     network_delay: u32,
     lock_after_session_end: bool,
@@ -280,11 +292,13 @@ const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
 const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_TUNNEL_CHANNELS: usize = 256;
 
 impl Connection {
     pub async fn start(
         addr: SocketAddr,
         stream: super::Stream,
+        sub_streams: Option<quic::SubStreams>,
         id: i32,
         server: super::ServerPtrWeak,
     ) {
@@ -297,6 +311,7 @@ impl Connection {
         let (tx_from_cm_holder, mut rx_from_cm) = mpsc::unbounded_channel::<ipc::Data>();
         // holding tx_from_cm_holder to avoid cpu burning of rx_from_cm.recv when all sender closed
         let tx_from_cm = tx_from_cm_holder.clone();
+        control_api::add_conn(id, tx_from_cm_holder.clone());
         let (tx_to_cm, rx_to_cm) = mpsc::unbounded_channel::<ipc::Data>();
         let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_video, mut rx_video) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
@@ -321,6 +336,7 @@ impl Connection {
             require_2fa: crate::auth_2fa::get_2fa(None),
             display_idx: *display_service::PRIMARY_DISPLAY_IDX,
             stream,
+            sub_streams,
             server,
             hash,
             read_jobs: Vec::new(),
@@ -328,6 +344,7 @@ impl Connection {
             file_timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_transfer: None,
             port_forward_socket: None,
+            port_forward_multiplex: false,
             port_forward_address: "".to_owned(),
             tx_to_cm,
             authorized: false,
@@ -339,6 +356,9 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
+            access_rule: None,
+            no_session: false,
+            wake_on_lan: None,
             last_test_delay: None,
             network_delay: 0,
             lock_after_session_end: false,
@@ -426,13 +446,12 @@ impl Connection {
             crate::rustdesk_interval(time::interval_at(Instant::now(), TEST_DELAY_TIMEOUT));
         let mut last_recv_time = Instant::now();
 
-        conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
+        conn.stream
+            .set_send_timeout(if conn.file_transfer.is_some() || conn.is_port_forward() {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
-            },
-        );
+            });
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
@@ -447,7 +466,7 @@ impl Connection {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() || conn.no_session {
                                 break;
                             }
                         }
@@ -460,6 +479,7 @@ impl Connection {
                         }
                         #[cfg(target_os = "android")]
                         ipc::Data::InputControl(v) => {
+                            let v = v && conn.acl_permit(acl::PERMISSION_KEYBOARD);
                             conn.keyboard = v;
                             conn.send_permission(Permission::Keyboard, v).await;
                         }
@@ -483,6 +503,7 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            let enabled = enabled && conn.acl_permit(&name);
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -579,7 +600,7 @@ impl Connection {
                         _ => {}
                     }
                 },
-                res = conn.stream.next() => {
+                res = quic::next(&mut conn.stream, conn.sub_streams.as_mut()) => {
                     if let Some(res) = res {
                         match res {
                             Err(err) => {
@@ -590,10 +611,10 @@ impl Connection {
                                 last_recv_time = Instant::now();
                                 *conn.last_recv_time.lock().unwrap() = Instant::now();
                                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
-                                    if !conn.on_message(msg_in).await {
+                                    if !conn.on_message(msg_in).await || conn.no_session {
                                         break;
                                     }
-                                    if conn.port_forward_socket.is_some() && conn.authorized {
+                                    if conn.is_port_forward() && conn.authorized {
                                         log::info!("Port forward, last_test_delay is none: {}", conn.last_test_delay.is_none());
                                         // Avoid TestDelay reply injection into rdp data stream
                                         if conn.last_test_delay.is_none() {
@@ -611,7 +632,7 @@ impl Connection {
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        match fs::handle_read_jobs(&mut conn.read_jobs, quic::select(&mut conn.stream, conn.sub_streams.as_mut(), quic::STREAM_FILE)).await {
                             Ok(log) => {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
@@ -637,7 +658,7 @@ impl Connection {
                     if !conn.video_ack_required {
                         video_service::notify_video_frame_fetched(id, Some(instant.into()));
                     }
-                    if let Err(err) = conn.stream.send(&value as &Message).await {
+                    if let Err(err) = quic::send(&mut conn.stream, conn.sub_streams.as_mut(), &value).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
@@ -697,8 +718,7 @@ impl Connection {
                         _ => {}
                     }
 
-                    let msg: &Message = &msg;
-                    if let Err(err) = conn.stream.send(msg).await {
+                    if let Err(err) = quic::send(&mut conn.stream, conn.sub_streams.as_mut(), &msg).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
                     }
@@ -706,6 +726,11 @@ impl Connection {
                 _ = second_timer.tick() => {
                     #[cfg(windows)]
                     conn.portable_check();
+                    if conn.access_time_window_ended() {
+                        conn.send_close_reason_no_retry("Access time window ended").await;
+                        conn.on_close("access time window ended", true).await;
+                        break;
+                    }
                     if let Some((instant, minute)) = conn.auto_disconnect_timer.as_ref() {
                         if instant.elapsed().as_secs() > minute * 60 {
                             conn.send_close_reason_no_retry("Connection failed due to inactivity").await;
@@ -723,7 +748,7 @@ impl Connection {
                         break;
                     }
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
-                    if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
+                    if conn.last_test_delay.is_none() && !(conn.is_port_forward() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
@@ -867,15 +892,24 @@ impl Connection {
         log::info!("Input thread exited");
     }
 
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some() || self.port_forward_multiplex
+    }
+
     async fn try_port_forward_loop(
         &mut self,
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
+        if self.port_forward_multiplex {
+            return self.port_forward_multiplex_loop(rx_from_cm).await;
+        }
         let mut last_recv_time = Instant::now();
         if let Some(mut forward) = self.port_forward_socket.take() {
             log::info!("Running port forwarding loop");
             self.stream.set_raw();
             let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+            let mut second_timer = crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
             loop {
                 tokio::select! {
                     Some(data) = rx_from_cm.recv() => {
@@ -911,6 +945,11 @@ impl Connection {
                             bail!("Timeout");
                         }
                     }
+                    _ = second_timer.tick() => {
+                        if self.access_time_window_ended() {
+                            bail!("Access time window ended");
+                        }
+                    }
                     Ok(conns) = hbbs_rx.recv() => {
                         if conns.contains(&self.inner.id) {
                             // todo: check reconnect
@@ -923,6 +962,191 @@ impl Connection {
         Ok(())
     }
 
+    async fn port_forward_multiplex_loop(
+        &mut self,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!("Running multiplexed port forwarding loop");
+        let mut last_recv_time = Instant::now();
+        let mut channels: HashMap<u32, crate::tunnel::DataSender> = HashMap::new();
+        let (tx_tunnel, mut rx_tunnel) = mpsc::channel::<TunnelMessage>(crate::tunnel::QUEUE_SIZE);
+        // reverse forwards, accepted connections with their targets
+        let (tx_accept, mut rx_accept) =
+            mpsc::channel::<(TcpStream, String, i32)>(crate::tunnel::QUEUE_SIZE);
+        let mut listening = HashSet::new();
+        let mut next_channel: u32 = 0;
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let mut second_timer = crate::rustdesk_interval(time::interval(Duration::from_secs(1)));
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                Some(tm) = rx_tunnel.recv() => {
+                    if let Some(tunnel_message::Union::Close(c)) = &tm.union {
+                        channels.remove(&c.channel);
+                    }
+                    timeout(SEND_TIMEOUT_OTHER, self.stream.send(&crate::tunnel::new_message(tm))).await??;
+                }
+                Some((socket, host, port)) = rx_accept.recv() => {
+                    if channels.len() >= MAX_TUNNEL_CHANNELS {
+                        log::warn!("Too many channels, reverse forward connection to {}:{} dropped", host, port);
+                        continue;
+                    }
+                    next_channel += 1;
+                    let channel = crate::tunnel::REVERSE_CHANNEL | next_channel;
+                    let (tx, rx) = mpsc::channel(crate::tunnel::QUEUE_SIZE);
+                    channels.insert(channel, tx);
+                    let open = crate::tunnel::new_open(channel, host, port, false);
+                    timeout(SEND_TIMEOUT_OTHER, self.stream.send(&crate::tunnel::new_message(open))).await??;
+                    tokio::spawn(crate::tunnel::run_tcp(channel, socket, rx, tx_tunnel.clone()));
+                }
+                res = self.stream.next() => {
+                    let bytes = match res {
+                        Some(res) => res?,
+                        None => bail!("Stream reset by the peer"),
+                    };
+                    last_recv_time = Instant::now();
+                    let msg = Message::parse_from_bytes(&bytes)?;
+                    match msg.union {
+                        Some(message::Union::TunnelMessage(tm)) => match tm.union {
+                            Some(tunnel_message::Union::Open(open)) => {
+                                if let Some(error) = crate::tunnel::check_open(open.channel, &channels, MAX_TUNNEL_CHANNELS) {
+                                    log::warn!("Rejected tunnel channel {}: {}", open.channel, error);
+                                    let close = crate::tunnel::new_close(open.channel, error.to_owned());
+                                    timeout(SEND_TIMEOUT_OTHER, self.stream.send(&crate::tunnel::new_message(close))).await??;
+                                } else {
+                                    let channel = open.channel;
+                                    channels.insert(channel, crate::tunnel::open(open, tx_tunnel.clone()));
+                                }
+                            }
+                            Some(tunnel_message::Union::Data(d)) => {
+                                if let Some(tx) = channels.get(&d.channel) {
+                                    if !crate::tunnel::send_data(tx, d.data).await {
+                                        channels.remove(&d.channel);
+                                        let close = crate::tunnel::new_close(d.channel, "Channel stalled".to_owned());
+                                        timeout(SEND_TIMEOUT_OTHER, self.stream.send(&crate::tunnel::new_message(close))).await??;
+                                    }
+                                }
+                            }
+                            Some(tunnel_message::Union::Close(c)) => {
+                                // dropping the sender closes the channel
+                                channels.remove(&c.channel);
+                            }
+                            Some(tunnel_message::Union::Listen(l)) => {
+                                let error = if listening.contains(&l.port) {
+                                    "".to_owned()
+                                } else {
+                                    match Self::reverse_forward_listen(l.port, l.host, l.target_port, tx_accept.clone()).await {
+                                        Ok(()) => {
+                                            listening.insert(l.port);
+                                            "".to_owned()
+                                        }
+                                        Err(err) => err.to_string(),
+                                    }
+                                };
+                                let res = crate::tunnel::new_listen_response(l.port, error);
+                                timeout(SEND_TIMEOUT_OTHER, self.stream.send(&crate::tunnel::new_message(res))).await??;
+                            }
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                },
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                _ = second_timer.tick() => {
+                    if self.access_time_window_ended() {
+                        bail!("Access time window ended");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
+    // Send the magic packets to the LAN of this side on behalf of the authenticated peer.
+    fn wake_on_lan(&self, wol: WakeOnLan) -> Message {
+        let mut res = WakeOnLanResponse::new();
+        let mut macs = wol.macs;
+        macs.extend(crate::lan::get_lan_peer_macs(&wol.id));
+        let macs = crate::lan::normalize_macs(&macs);
+        if macs.is_empty() {
+            res.error = format!("No mac address of {}", wol.id);
+        } else {
+            log::info!("Wake-on-LAN {:?} {:?} for {}", wol.id, macs, self.lr.my_id);
+            res.sent = crate::lan::send_wol_to_macs(&macs) as _;
+            if res.sent == 0 {
+                res.error = "Failed to send the magic packets".to_owned();
+            }
+        }
+        let mut misc = Misc::new();
+        misc.set_wake_on_lan_response(res);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    // Listen on the loopback of the controlled side for a reverse forward,
+    // the listener stops when the multiplexed loop exits.
+    async fn reverse_forward_listen(
+        port: i32,
+        host: String,
+        target_port: i32,
+        tx: mpsc::Sender<(TcpStream, String, i32)>,
+    ) -> ResultType<()> {
+        if !Connection::permission("enable-tunnel") {
+            bail!("No permission of IP tunneling");
+        }
+        if port <= 0 || port > u16::MAX as i32 || target_port <= 0 {
+            bail!("Invalid port");
+        }
+        let listener = hbb_common::tcp::new_listener(format!("127.0.0.1:{}", port), true).await?;
+        log::info!(
+            "Reverse forward listening on {}, to {}:{}",
+            port,
+            host,
+            target_port
+        );
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    res = listener.accept() => match res {
+                        Ok((socket, addr)) => {
+                            log::info!("Reverse forward {}, new connection from {:?}", port, addr);
+                            if tx.send((socket, host.clone(), target_port)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            log::error!("Reverse forward {} failed to accept: {}", port, err);
+                            break;
+                        }
+                    },
+                    _ = tx.closed() => break,
+                }
+            }
+            log::info!("Reverse forward listener {} closed", port);
+        });
+        Ok(())
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -974,6 +1198,94 @@ impl Connection {
         true
     }
 
+    // Called once the peer is authenticated, the remote ID is claimed by the peer,
+    // so an ID rule only selects the permissions of an authenticated peer.
+    async fn check_access_rule(&mut self) -> bool {
+        match acl::get_rule(&self.lr.my_id, &self.ip) {
+            Ok(Some(rule)) => {
+                log::info!(
+                    "Access control rule {} for {} (claimed) from {}",
+                    rule.name,
+                    self.lr.my_id,
+                    self.ip
+                );
+                if self.file_transfer.is_some() && !rule.permit(acl::PERMISSION_FILE) {
+                    self.send_login_error("No permission of file transfer")
+                        .await;
+                    return false;
+                }
+                if self.is_port_forward() && !rule.permit(acl::PERMISSION_TUNNEL) {
+                    self.send_login_error("No permission of IP tunneling").await;
+                    return false;
+                }
+                let mut revoked = vec![];
+                for (name, permission, enabled) in [
+                    (
+                        acl::PERMISSION_KEYBOARD,
+                        Permission::Keyboard,
+                        &mut self.keyboard,
+                    ),
+                    (
+                        acl::PERMISSION_CLIPBOARD,
+                        Permission::Clipboard,
+                        &mut self.clipboard,
+                    ),
+                    (acl::PERMISSION_AUDIO, Permission::Audio, &mut self.audio),
+                    (acl::PERMISSION_FILE, Permission::File, &mut self.file),
+                    (
+                        acl::PERMISSION_RESTART,
+                        Permission::Restart,
+                        &mut self.restart,
+                    ),
+                    (
+                        acl::PERMISSION_RECORDING,
+                        Permission::Recording,
+                        &mut self.recording,
+                    ),
+                    (
+                        acl::PERMISSION_BLOCK_INPUT,
+                        Permission::BlockInput,
+                        &mut self.block_input,
+                    ),
+                ] {
+                    if *enabled && !rule.permit(name) {
+                        *enabled = false;
+                        revoked.push(permission);
+                    }
+                }
+                self.access_rule = Some(rule);
+                for permission in revoked {
+                    self.send_permission(permission, false).await;
+                }
+                true
+            }
+            Ok(None) => true,
+            Err(err) => {
+                log::warn!("{}", err);
+                self.send_login_error("Access denied by the peer").await;
+                Self::post_alarm_audit(
+                    AlarmAuditType::AccessControl,
+                    json!({ "ip": self.ip, "id": self.lr.my_id }),
+                );
+                false
+            }
+        }
+    }
+
+    #[inline]
+    fn access_time_window_ended(&self) -> bool {
+        self.access_rule
+            .as_ref()
+            .map_or(false, |r| !r.in_time_window())
+    }
+
+    #[inline]
+    fn acl_permit(&self, permission: &str) -> bool {
+        self.access_rule
+            .as_ref()
+            .map_or(true, |r| r.permit(permission))
+    }
+
     async fn on_open(&mut self, addr: SocketAddr) -> bool {
         log::debug!("#{} Connection opened from {}.", self.inner.id, addr);
         if !self.check_whitelist(&addr).await {
@@ -1012,15 +1324,22 @@ impl Connection {
     }
 
     fn post_conn_audit(&self, v: Value) {
-        if self.server_audit_conn.is_empty() {
-            return;
-        }
-        let url = self.server_audit_conn.clone();
         let mut v = v;
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["conn_id"] = json!(self.inner.id);
         v["session_id"] = json!(self.lr.session_id);
+        let mut local = v.clone();
+        if local.get("ip").is_none() {
+            local["ip"] = json!(self.ip.clone());
+        }
+        local["peer_id"] = json!(self.lr.my_id.clone());
+        local["peer_name"] = json!(self.lr.my_name.clone());
+        audit::record(audit::AuditKind::Conn, &local);
+        if self.server_audit_conn.is_empty() {
+            return;
+        }
+        let url = self.server_audit_conn.clone();
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
         });
@@ -1033,10 +1352,6 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
-        if self.server_audit_file.is_empty() {
-            return;
-        }
-        let url = self.server_audit_file.clone();
         let file_num = files.len();
         let mut files = files;
         files.sort_by(|a, b| b.1.cmp(&a.1));
@@ -1056,12 +1371,23 @@ impl Connection {
             "is_file":is_file,
             "info":json!(info).to_string(),
         });
+        audit::record(audit::AuditKind::File, &v);
+        if self.server_audit_file.is_empty() {
+            return;
+        }
+        let url = self.server_audit_file.clone();
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
         });
     }
 
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
+        let mut v = Value::default();
+        v["id"] = json!(Config::get_id());
+        v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
+        v["typ"] = json!(typ as i8);
+        v["info"] = serde_json::Value::String(info.to_string());
+        audit::record(audit::AuditKind::Alarm, &v);
         let url = crate::get_audit_server(
             Config::get_option("api-server"),
             Config::get_option("custom-rendezvous-server"),
@@ -1070,11 +1396,6 @@ impl Connection {
         if url.is_empty() {
             return;
         }
-        let mut v = Value::default();
-        v["id"] = json!(Config::get_id());
-        v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
-        v["typ"] = json!(typ as i8);
-        v["info"] = serde_json::Value::String(info.to_string());
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
         });
@@ -1120,10 +1441,26 @@ impl Connection {
             self.send_login_error(crate::client::REQUIRE_2FA).await;
             return;
         }
+        if !self.check_access_rule().await {
+            self.no_session = true;
+            sleep(1.).await;
+            return;
+        }
+        if let Some(wol) = self.wake_on_lan.take() {
+            let msg_out = self.wake_on_lan(wol);
+            self.send(msg_out).await;
+            self.no_session = true;
+            sleep(1.).await;
+            return;
+        }
         self.authorized = true;
+        if let Some(sub_streams) = self.sub_streams.as_mut() {
+            sub_streams.set_ready();
+        }
+        control_api::on_authorized(self.inner.id());
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             (2, AuthConnType::PortForward)
         } else {
             (0, AuthConnType::Remote)
@@ -1173,6 +1510,7 @@ impl Connection {
                     platform_additions.insert("headless".into(), json!(true));
                 }
             }
+            platform_additions.extend(virtual_display_manager::get_platform_additions());
         }
         #[cfg(target_os = "windows")]
         {
@@ -1205,7 +1543,7 @@ impl Connection {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1213,7 +1551,7 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "linux")]
-        if !self.file_transfer.is_some() && !self.port_forward_socket.is_some() {
+        if !self.file_transfer.is_some() && !self.is_port_forward() {
             let mut msg = "".to_string();
             if crate::platform::linux::is_login_screen_wayland() {
                 msg = crate::client::LOGIN_SCREEN_WAYLAND.to_owned()
@@ -1345,7 +1683,7 @@ impl Connection {
     }
 
     fn try_sub_services(&mut self) {
-        let is_remote = self.file_transfer.is_none() && self.port_forward_socket.is_none();
+        let is_remote = self.file_transfer.is_none() && !self.is_port_forward();
         if is_remote && !self.services_subed {
             self.services_subed = true;
             if let Some(s) = self.server.upgrade() {
@@ -1444,6 +1782,24 @@ impl Connection {
     }
 
     fn try_start_cm(&mut self, peer_id: String, name: String, authorized: bool) {
+        if self.no_session || self.wake_on_lan.is_some() {
+            return;
+        }
+        control_api::on_login(control_api::ConnInfo {
+            id: self.inner.id(),
+            peer_id: peer_id.clone(),
+            name: name.clone(),
+            ip: self.ip.clone(),
+            r#type: if self.file_transfer.is_some() {
+                "file-transfer"
+            } else if !self.port_forward_address.is_empty() {
+                "port-forward"
+            } else {
+                "remote"
+            }
+            .to_owned(),
+            authorized,
+        });
         self.send_to_cm(ipc::Data::Login {
             id: self.inner.id(),
             is_file_transfer: self.file_transfer.is_some(),
@@ -1690,6 +2046,14 @@ impl Connection {
             if self.authorized {
                 return true;
             }
+            if Config::get_option(keys::OPTION_REQUIRE_ENCRYPTION) == "Y"
+                && !self.stream.is_secured()
+            {
+                self.send_login_error(crate::client::LOGIN_MSG_ENCRYPTION_REQUIRED)
+                    .await;
+                sleep(1.).await;
+                return false;
+            }
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
                     if !Connection::permission(keys::OPTION_ENABLE_FILE_TRANSFER) {
@@ -1706,34 +2070,53 @@ impl Connection {
                         sleep(1.).await;
                         return false;
                     }
-                    let mut is_rdp = false;
-                    if pf.host == "RDP" && pf.port == 0 {
-                        pf.host = "localhost".to_owned();
-                        pf.port = 3389;
-                        is_rdp = true;
-                    }
-                    if pf.host.is_empty() {
-                        pf.host = "localhost".to_owned();
-                    }
-                    let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    if pf.multiplex {
+                        self.port_forward_multiplex = true;
+                        self.port_forward_address = "multiplex".to_owned();
+                    } else {
+                        let mut is_rdp = false;
+                        if pf.host == "RDP" && pf.port == 0 {
+                            pf.host = "localhost".to_owned();
+                            pf.port = 3389;
+                            is_rdp = true;
+                        }
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
                         }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                        let mut addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        match timeout(3000, TcpStream::connect(&addr)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
-                            return false;
                         }
                     }
                 }
+                Some(login_request::Union::WakeOnLan(wol)) => {
+                    // only if allowed explicitly, the access mode does not apply
+                    if !config::option2bool(
+                        keys::OPTION_ALLOW_WOL_RELAY,
+                        &Config::get_option(keys::OPTION_ALLOW_WOL_RELAY),
+                    ) {
+                        self.send_login_error("No permission of Wake-on-LAN relay")
+                            .await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    self.wake_on_lan = Some(wol);
+                }
                 _ => {
                     if !self.check_privacy_mode_on().await {
                         return false;
@@ -1741,15 +2124,21 @@ impl Connection {
                 }
             }
 
+            // neither the connection manager nor the desktop is needed without a session
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
-            self.try_start_cm_ipc();
+            if self.wake_on_lan.is_none() {
+                self.try_start_cm_ipc();
+            }
 
             #[cfg(not(target_os = "linux"))]
             let err_msg = "".to_owned();
             #[cfg(target_os = "linux")]
-            let err_msg = self
-                .linux_headless_handle
-                .try_start_desktop(lr.os_login.as_ref());
+            let err_msg = if self.wake_on_lan.is_none() {
+                self.linux_headless_handle
+                    .try_start_desktop(lr.os_login.as_ref())
+            } else {
+                "".to_owned()
+            };
 
             // If err is LOGIN_MSG_DESKTOP_SESSION_NOT_READY, just keep this msg and go on checking password.
             if !err_msg.is_empty() && err_msg != crate::client::LOGIN_MSG_DESKTOP_SESSION_NOT_READY
@@ -1797,12 +2186,11 @@ impl Connection {
                     .await;
                 }
             } else {
-                let (failure, res) = self.check_failure(0).await;
-                if !res {
+                if !self.check_failure(LoginKind::Password).await {
                     return true;
                 }
                 if !self.validate_password() {
-                    self.update_failure(failure, false, 0);
+                    self.update_failure(LoginKind::Password, false);
                     if err_msg.is_empty() {
                         self.send_login_error(crate::client::LOGIN_MSG_PASSWORD_WRONG)
                             .await;
@@ -1814,7 +2202,7 @@ impl Connection {
                         .await;
                     }
                 } else {
-                    self.update_failure(failure, true, 0);
+                    self.update_failure(LoginKind::Password, true);
                     if err_msg.is_empty() {
                         #[cfg(target_os = "linux")]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
@@ -1826,14 +2214,13 @@ impl Connection {
                 }
             }
         } else if let Some(message::Union::Auth2fa(tfa)) = msg.union {
-            let (failure, res) = self.check_failure(1).await;
-            if !res {
+            if !self.check_failure(LoginKind::Tfa).await {
                 return true;
             }
             if let Some(totp) = self.require_2fa.as_ref() {
                 if let Ok(res) = totp.check_current(&tfa.code) {
                     if res {
-                        self.update_failure(failure, true, 1);
+                        self.update_failure(LoginKind::Tfa, true);
                         self.require_2fa.take();
                         self.send_logon_response().await;
                         self.try_start_cm(
@@ -1874,7 +2261,7 @@ impl Connection {
                             });
                         }
                     } else {
-                        self.update_failure(failure, false, 1);
+                        self.update_failure(LoginKind::Tfa, false);
                         self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
                             .await;
                     }
@@ -1923,7 +2310,7 @@ impl Connection {
                 }
             }
         } else if self.authorized {
-            if self.port_forward_socket.is_some() {
+            if self.is_port_forward() {
                 return true;
             }
             match msg.union {
@@ -2330,6 +2717,7 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            copy_blks: block.copy_blks,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -2344,6 +2732,8 @@ impl Connection {
                         file_size: d.file_size,
                         last_modified: d.last_modified,
                         is_upload: true,
+                        resumable: d.resumable,
+                        delta_sync: d.delta_sync,
                     }),
                     Some(file_response::Union::Error(e)) => {
                         self.send_fs(ipc::FS::WriteError {
@@ -2364,7 +2754,7 @@ impl Connection {
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         self.capture_displays(&add, &sub, &set).await;
                     }
-                    #[cfg(windows)]
+                    #[cfg(any(windows, target_os = "linux"))]
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
                         self.toggle_virtual_display(t).await;
                     }
@@ -2437,7 +2827,9 @@ impl Connection {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            self.audio_sender = Some(start_audio_thread_(Some(
+                                super::audio_service::on_peer_voice_decoded,
+                            )));
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -2546,61 +2938,39 @@ impl Connection {
         true
     }
 
-    fn update_failure(&self, (mut failure, time): ((i32, i32, i32), i32), remove: bool, i: usize) {
-        if remove {
-            if failure.0 != 0 {
-                LOGIN_FAILURES[i].lock().unwrap().remove(&self.ip);
-            }
-            return;
-        }
-        if failure.0 == time {
-            failure.1 += 1;
-            failure.2 += 1;
+    fn update_failure(&self, kind: LoginKind, success: bool) {
+        if success {
+            login_failure::succeed(kind, &self.ip);
         } else {
-            failure.0 = time;
-            failure.1 = 1;
-            failure.2 += 1;
+            login_failure::fail(kind, &self.ip);
         }
-        LOGIN_FAILURES[i]
-            .lock()
-            .unwrap()
-            .insert(self.ip.clone(), failure);
     }
 
-    async fn check_failure(&mut self, i: usize) -> (((i32, i32, i32), i32), bool) {
-        let failure = LOGIN_FAILURES[i]
-            .lock()
-            .unwrap()
-            .get(&self.ip)
-            .map(|x| x.clone())
-            .unwrap_or((0, 0, 0));
-        let time = (get_time() / 60_000) as i32;
-        let res = if failure.2 > 30 {
-            self.send_login_error("Too many wrong attempts").await;
-            Self::post_alarm_audit(
-                AlarmAuditType::ExceedThirtyAttempts,
-                json!({
-                            "ip": self.ip,
-                            "id": self.lr.my_id.clone(),
-                            "name": self.lr.my_name.clone(),
-                }),
-            );
-            false
-        } else if time == failure.0 && failure.1 > 6 {
-            self.send_login_error("Please try 1 minute later").await;
-            Self::post_alarm_audit(
-                AlarmAuditType::SixAttemptsWithinOneMinute,
-                json!({
-                            "ip": self.ip,
-                            "id": self.lr.my_id.clone(),
-                            "name": self.lr.my_name.clone(),
-                }),
-            );
-            false
-        } else {
-            true
+    async fn check_failure(&mut self, kind: LoginKind) -> bool {
+        let alarm = match login_failure::check(kind, &self.ip) {
+            login_failure::Check::Allowed => return true,
+            login_failure::Check::Locked => {
+                self.send_login_error("Too many wrong attempts").await;
+                AlarmAuditType::ExceedThirtyAttempts
+            }
+            login_failure::Check::Banned(secs) => {
+                if secs <= 60 {
+                    self.send_login_error("Please try 1 minute later").await;
+                } else {
+                    self.send_login_error("Too many wrong attempts").await;
+                }
+                AlarmAuditType::SixAttemptsWithinOneMinute
+            }
         };
-        ((failure, time), res)
+        Self::post_alarm_audit(
+            alarm,
+            json!({
+                        "ip": self.ip,
+                        "id": self.lr.my_id.clone(),
+                        "name": self.lr.my_name.clone(),
+            }),
+        );
+        false
     }
 
     fn refresh_video_display(&self, display: Option<usize>) {
@@ -2717,7 +3087,7 @@ impl Connection {
         }
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     async fn toggle_virtual_display(&mut self, t: ToggleVirtualDisplay) {
         let make_msg = |text: String| {
             let mut msg_out = Message::new();
@@ -2734,8 +3104,11 @@ impl Connection {
 
         if t.on {
             if !virtual_display_manager::is_virtual_display_supported() {
-                self.send(make_msg("idd_not_support_under_win10_2004_tip".to_string()))
-                    .await;
+                #[cfg(windows)]
+                let tip = "idd_not_support_under_win10_2004_tip";
+                #[cfg(target_os = "linux")]
+                let tip = "xrandr_virtual_display_not_support_tip";
+                self.send(make_msg(tip.to_string())).await;
             } else {
                 if let Err(e) = virtual_display_manager::plug_in_monitor(t.display as _, Vec::new())
                 {
@@ -2784,6 +3157,16 @@ impl Connection {
                     {
                         return;
                     }
+                    #[cfg(target_os = "linux")]
+                    if let Some(_ok) =
+                        virtual_display_manager::xrandr::change_resolution_if_is_virtual_display(
+                            &name,
+                            r.width as _,
+                            r.height as _,
+                        )
+                    {
+                        return;
+                    }
                     let mut record_changed = true;
                     #[cfg(windows)]
                     if virtual_display_manager::amyuni_idd::is_my_display(&name) {
@@ -2918,6 +3301,15 @@ impl Connection {
                 }
             }
         }
+        if let Ok(p) = o.audio_profile.enum_value() {
+            match p {
+                AudioProfile::AudioProfileNotSet => {}
+                AudioProfile::AudioProfileDefault => {
+                    super::audio_service::set_profile(self.inner.id(), None)
+                }
+                p => super::audio_service::set_profile(self.inner.id(), Some(p)),
+            }
+        }
         #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
         if let Ok(q) = o.enable_file_transfer.enum_value() {
             if q != BoolOption::NotSet {
@@ -3172,7 +3564,7 @@ impl Connection {
 
     #[inline]
     async fn send(&mut self, msg: Message) {
-        allow_err!(self.stream.send(&msg).await);
+        allow_err!(quic::send(&mut self.stream, self.sub_streams.as_mut(), &msg).await);
     }
 
     pub fn alive_conns() -> Vec<i32> {
@@ -3183,7 +3575,7 @@ impl Connection {
     fn portable_check(&mut self) {
         if self.portable.is_installed
             || self.file_transfer.is_some()
-            || self.port_forward_socket.is_some()
+            || self.is_port_forward()
             || !self.keyboard
         {
             return;
@@ -3450,8 +3842,9 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            copy_blks}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, copy_blks})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -3480,6 +3873,7 @@ pub enum AlarmAuditType {
     IpWhitelist = 0,
     ExceedThirtyAttempts = 1,
     SixAttemptsWithinOneMinute = 2,
+    AccessControl = 3,
 }
 
 pub enum FileAuditType {
@@ -3804,6 +4198,8 @@ mod raii {
                 .lock()
                 .unwrap()
                 .on_connection_close(self.0);
+            crate::audio_service::set_profile(self.0, None);
+            control_api::remove_conn(self.0);
         }
     }
 
@@ -3864,7 +4260,7 @@ mod raii {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 display_service::reset_resolutions();
-                #[cfg(windows)]
+                #[cfg(any(windows, target_os = "linux"))]
                 let _ = virtual_display_manager::reset_all();
                 #[cfg(target_os = "linux")]
                 scrap::wayland::pipewire::try_close_session();
@@ -0,0 +1,376 @@
+// Local control API for automation, JSON-RPC 2.0 over the unix socket `Config::ipc_path("_api")`.
+//
+// Disabled unless `allow-control-api` is "Y" in the root config. Only root, the user running the
+// service and the uids in `control-api-allowed-uids` (separated by comma) of the root config may
+// connect.
+//
+// Each request and response is one line of json, e.g.
+//   -> {"jsonrpc": "2.0", "id": 1, "method": "list_connections"}
+//   <- {"jsonrpc": "2.0", "id": 1, "result": [{"id": 1001, "peer_id": "123456789", ...}]}
+//
+// Methods:
+//   version                        {"version": 1, "app_version": "..."}
+//   get_id                         the ID of this device
+//   get_option {key}               the value, "" if not set
+//   get_options                    all the options
+//   set_option {key, value}        an empty value removes the option, the options of the root config
+//                                  can not be set
+//   list_connections               the connections, pending ones have "authorized": false
+//   approve_connection {id}        accept a pending connection, as clicking accept in the cm
+//   deny_connection {id}           refuse a pending connection
+//   close_connection {id}          disconnect a connection
+//   subscribe                      receive the connection events as notifications,
+//                                  {"jsonrpc": "2.0", "method": "event", "params": {"event": "login", ...}}
+//                                  the events are "login" with the connection, "authorized" and "close"
+//                                  with its id
+//
+// The version is increased on incompatible changes only.
+use crate::ipc::Data;
+use hbb_common::{
+    config::{keys, Config},
+    lazy_static, log,
+    tokio::sync::broadcast,
+};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Mutex};
+
+pub const CONTROL_API_VERSION: i32 = 1;
+#[cfg(all(unix, not(any(target_os = "android", target_os = "ios"))))]
+const CONTROL_API_POSTFIX: &str = "_api";
+
+// https://www.jsonrpc.org/specification#error_object
+const ERR_PARSE: i32 = -32700;
+const ERR_METHOD_NOT_FOUND: i32 = -32601;
+const ERR_INVALID_PARAMS: i32 = -32602;
+const ERR_CONNECTION_NOT_FOUND: i32 = 1;
+const ERR_NOT_PENDING: i32 = 2;
+const ERR_PERMISSION_DENIED: i32 = 3;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnInfo {
+    pub id: i32,
+    pub peer_id: String,
+    pub name: String,
+    pub ip: String,
+    // "remote", "file-transfer" or "port-forward"
+    pub r#type: String,
+    pub authorized: bool,
+}
+
+struct Conn {
+    info: Option<ConnInfo>,
+    tx: hbb_common::tokio::sync::mpsc::UnboundedSender<Data>,
+}
+
+lazy_static::lazy_static! {
+    static ref CONNS: Mutex<HashMap<i32, Conn>> = Default::default();
+    static ref EVENTS: broadcast::Sender<Value> = broadcast::channel(64).0;
+}
+
+/// Register a connection, `tx` is where the commands of the connection manager go.
+pub fn add_conn(id: i32, tx: hbb_common::tokio::sync::mpsc::UnboundedSender<Data>) {
+    CONNS.lock().unwrap().insert(id, Conn { info: None, tx });
+}
+
+/// The connection is logged in, waiting for approval if not authorized.
+pub fn on_login(info: ConnInfo) {
+    let mut event = json!(info);
+    event["event"] = json!("login");
+    if let Some(conn) = CONNS.lock().unwrap().get_mut(&info.id) {
+        conn.info = Some(info);
+    }
+    EVENTS.send(event).ok();
+}
+
+/// The connection is authorized, by the password or the approval.
+pub fn on_authorized(id: i32) {
+    if let Some(Conn {
+        info: Some(info), ..
+    }) = CONNS.lock().unwrap().get_mut(&id)
+    {
+        if !info.authorized {
+            info.authorized = true;
+            EVENTS.send(json!({"event": "authorized", "id": id})).ok();
+        }
+    }
+}
+
+pub fn remove_conn(id: i32) {
+    if let Some(conn) = CONNS.lock().unwrap().remove(&id) {
+        if conn.info.is_some() {
+            EVENTS.send(json!({"event": "close", "id": id})).ok();
+        }
+    }
+}
+
+// Approve and deny apply to the logged in connections which are not authorized yet.
+fn send_to_conn(id: i32, data: Data, pending_only: bool) -> Result<(), (i32, String)> {
+    let conns = CONNS.lock().unwrap();
+    let not_found = || {
+        (
+            ERR_CONNECTION_NOT_FOUND,
+            format!("connection {} not found", id),
+        )
+    };
+    let conn = conns.get(&id).ok_or_else(not_found)?;
+    if pending_only && !matches!(&conn.info, Some(info) if !info.authorized) {
+        return Err((ERR_NOT_PENDING, format!("connection {} is not pending", id)));
+    }
+    conn.tx.send(data).map_err(|_| not_found())
+}
+
+// Ok(result) or Err((code, message)), and whether to subscribe the events.
+fn handle_request(method: &str, params: &Value) -> (Result<Value, (i32, String)>, bool) {
+    let conn_cmd = |data: Data, pending_only: bool| {
+        let id = match params.get("id").and_then(|x| x.as_i64()) {
+            Some(id) => id as i32,
+            None => return Err((ERR_INVALID_PARAMS, "id is required".to_owned())),
+        };
+        send_to_conn(id, data, pending_only).map(|_| json!(true))
+    };
+    let res = match method {
+        "version" => Ok(json!({
+            "version": CONTROL_API_VERSION,
+            "app_version": crate::VERSION,
+        })),
+        "get_id" => Ok(json!(Config::get_id())),
+        "get_option" => match params.get("key").and_then(|x| x.as_str()) {
+            Some(key) => Ok(json!(Config::get_option(key))),
+            None => Err((ERR_INVALID_PARAMS, "key is required".to_owned())),
+        },
+        "get_options" => Ok(json!(Config::get_options())),
+        "set_option" => match (
+            params.get("key").and_then(|x| x.as_str()),
+            params.get("value").and_then(|x| x.as_str()),
+        ) {
+            (Some(key), Some(_)) if keys::KEYS_ROOT_SETTINGS.contains(&key) => Err((
+                ERR_PERMISSION_DENIED,
+                format!("option {} is in the root config", key),
+            )),
+            (Some(key), Some(value)) => {
+                let _chk = crate::ipc::CheckIfRestart::new();
+                Config::set_option(key.to_owned(), value.to_owned());
+                Ok(json!(true))
+            }
+            _ => Err((ERR_INVALID_PARAMS, "key and value are required".to_owned())),
+        },
+        "list_connections" => Ok(json!(CONNS
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|c| c.info.clone())
+            .collect::<Vec<_>>())),
+        "approve_connection" => conn_cmd(Data::Authorize, true),
+        "deny_connection" => conn_cmd(Data::Close, true),
+        "close_connection" => conn_cmd(Data::Close, false),
+        "subscribe" => return (Ok(json!(true)), true),
+        _ => Err((ERR_METHOD_NOT_FOUND, format!("method {} not found", method))),
+    };
+    (res, false)
+}
+
+// The response of the request line, and whether to subscribe the events.
+fn handle_line(line: &str) -> (Value, bool) {
+    let req: Value = match serde_json::from_str(line) {
+        Ok(req) => req,
+        Err(e) => {
+            return (
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": ERR_PARSE, "message": e.to_string()},
+                }),
+                false,
+            )
+        }
+    };
+    let id = req.get("id").cloned().unwrap_or(Value::Null);
+    let method = req
+        .get("method")
+        .and_then(|x| x.as_str())
+        .unwrap_or_default();
+    let params = req.get("params").cloned().unwrap_or(Value::Null);
+    let (res, subscribe) = handle_request(method, &params);
+    let resp = match res {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    };
+    (resp, subscribe)
+}
+
+#[cfg(all(unix, not(any(target_os = "android", target_os = "ios"))))]
+mod listener {
+    use super::*;
+    use hbb_common::{
+        config::RootConfig,
+        futures::{SinkExt, StreamExt},
+        tokio::{
+            self,
+            net::{UnixListener, UnixStream},
+        },
+        tokio_util::codec::{Framed, LinesCodec},
+        ResultType,
+    };
+
+    fn is_uid_allowed(uid: u32) -> bool {
+        if uid == 0 || uid == unsafe { hbb_common::libc::geteuid() } {
+            return true;
+        }
+        RootConfig::get_option(keys::OPTION_CONTROL_API_ALLOWED_UIDS)
+            .split(',')
+            .any(|x| x.trim().parse::<u32>().ok() == Some(uid))
+    }
+
+    async fn handle_stream(stream: UnixStream) {
+        let uid = match stream.peer_cred() {
+            Ok(cred) => cred.uid(),
+            Err(e) => {
+                log::error!("Failed to get control api peer credentials: {}", e);
+                return;
+            }
+        };
+        if !is_uid_allowed(uid) {
+            log::warn!("Control api connection from uid {} refused", uid);
+            return;
+        }
+        let mut stream = Framed::new(stream, LinesCodec::new());
+        let mut events: Option<broadcast::Receiver<Value>> = None;
+        loop {
+            tokio::select! {
+                res = stream.next() => {
+                    let line = match res {
+                        Some(Ok(line)) => line,
+                        Some(Err(e)) => {
+                            log::debug!("Control api connection closed: {}", e);
+                            break;
+                        }
+                        None => break,
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let (resp, subscribe) = handle_line(&line);
+                    if subscribe && events.is_none() {
+                        events = Some(EVENTS.subscribe());
+                    }
+                    if stream.send(resp.to_string()).await.is_err() {
+                        break;
+                    }
+                }
+                event = async {
+                    match events.as_mut() {
+                        Some(rx) => rx.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match event {
+                        Ok(event) => {
+                            let msg = json!({"jsonrpc": "2.0", "method": "event", "params": event});
+                            if stream.send(msg.to_string()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            log::warn!("Control api subscriber lagged, {} events dropped", n);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn start() -> ResultType<()> {
+        let path = Config::ipc_path(CONTROL_API_POSTFIX);
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path)?;
+        {
+            // everyone may connect, the peer is checked by uid
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o0666)).ok();
+        }
+        log::info!("Started control api at path: {}", path);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            if !hbb_common::config::option2bool(
+                keys::OPTION_ALLOW_CONTROL_API,
+                &RootConfig::get_option(keys::OPTION_ALLOW_CONTROL_API),
+            ) {
+                log::warn!("Control api is not allowed");
+                continue;
+            }
+            tokio::spawn(handle_stream(stream));
+        }
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "android", target_os = "ios"))))]
+pub use listener::start;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handle_line() {
+        let (resp, subscribe) = handle_line(r#"{"jsonrpc": "2.0", "id": 1, "method": "version"}"#);
+        assert_eq!(resp["id"], 1);
+        assert_eq!(resp["result"]["version"], CONTROL_API_VERSION);
+        assert!(!subscribe);
+        let (resp, _) = handle_line(r#"{"jsonrpc": "2.0", "id": 2, "method": "unknown"}"#);
+        assert_eq!(resp["error"]["code"], ERR_METHOD_NOT_FOUND);
+        let (resp, _) = handle_line(r#"{"jsonrpc": "2.0", "id": 3, "method": "close_connection"}"#);
+        assert_eq!(resp["error"]["code"], ERR_INVALID_PARAMS);
+        let (resp, _) = handle_line("{");
+        assert_eq!(resp["error"]["code"], ERR_PARSE);
+        let (_, subscribe) = handle_line(r#"{"jsonrpc": "2.0", "id": 4, "method": "subscribe"}"#);
+        assert!(subscribe);
+    }
+
+    #[test]
+    fn test_set_root_option() {
+        for key in keys::KEYS_ROOT_SETTINGS {
+            let line = json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "set_option",
+                "params": {"key": key, "value": "Y"},
+            });
+            let (resp, _) = handle_line(&line.to_string());
+            assert_eq!(resp["error"]["code"], ERR_PERMISSION_DENIED);
+        }
+    }
+
+    #[test]
+    fn test_approve_connection() {
+        let id = 10001;
+        let approve = r#"{"jsonrpc": "2.0", "id": 1, "method": "approve_connection", "params": {"id": 10001}}"#;
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        add_conn(id, tx);
+        let (resp, _) = handle_line(approve);
+        assert_eq!(resp["error"]["code"], ERR_NOT_PENDING);
+        on_login(ConnInfo {
+            id,
+            peer_id: "123456789".to_owned(),
+            name: "test".to_owned(),
+            ip: "127.0.0.1".to_owned(),
+            r#type: "remote".to_owned(),
+            authorized: false,
+        });
+        let (resp, _) = handle_line(approve);
+        assert_eq!(resp["result"], true);
+        assert!(matches!(rx.try_recv(), Ok(Data::Authorize)));
+        on_authorized(id);
+        assert!(CONNS.lock().unwrap()[&id].info.as_ref().unwrap().authorized);
+        let (resp, _) = handle_line(approve);
+        assert_eq!(resp["error"]["code"], ERR_NOT_PENDING);
+        remove_conn(id);
+        let (resp, _) = handle_line(approve);
+        assert_eq!(resp["error"]["code"], ERR_CONNECTION_NOT_FOUND);
+    }
+}
@@ -187,6 +187,11 @@ fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
         let m = crate::virtual_display_manager::get_platform_additions();
         pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
     }
+    #[cfg(target_os = "linux")]
+    {
+        let m = crate::virtual_display_manager::get_platform_additions();
+        pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
+    }
 
     // current_display should not be used in server.
     // It is set to 0 for compatibility with old clients.
@@ -256,7 +261,10 @@ pub(super) fn get_original_resolution(
     #[cfg(windows)]
     let is_rustdesk_virtual_display =
         crate::virtual_display_manager::rustdesk_idd::is_virtual_display(&display_name);
-    #[cfg(not(windows))]
+    #[cfg(target_os = "linux")]
+    let is_rustdesk_virtual_display =
+        crate::virtual_display_manager::xrandr::is_virtual_display(&display_name);
+    #[cfg(not(any(windows, target_os = "linux")))]
     let is_rustdesk_virtual_display = false;
     Some(if is_rustdesk_virtual_display {
         Resolution {
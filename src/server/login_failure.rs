@@ -0,0 +1,419 @@
+// Lockout of ips with too many wrong passwords or 2FA codes.
+// The policy is configured by options, the records are persisted so that restarting
+// the service does not reset the bans.
+// The locks never expire, they are cleared with `--clear-login-bans [ip]` while the service
+// is running, or by removing `<app>_login_failures.toml` in the config dir while it is stopped.
+use hbb_common::{
+    config::{self, keys, option2bool, Config},
+    get_time, lazy_static, log,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+// The failures allowed, the next one is banned or locked, the same as the fixed policy before.
+const DEFAULT_MAX_ATTEMPTS: u32 = 6;
+const DEFAULT_ATTEMPT_WINDOW_SECS: i64 = 60;
+const DEFAULT_BAN_DURATION_SECS: i64 = 60;
+const DEFAULT_MAX_TOTAL_ATTEMPTS: u32 = 30;
+const MAX_BAN_DURATION_MS: i64 = 24 * 3600 * 1000;
+// idle records without a ban are forgotten after this
+const RECORD_EXPIRE_MS: i64 = 24 * 3600 * 1000;
+// records kept of each kind, the idle ones are dropped first, then the oldest bans
+const MAX_RECORDS: usize = 10_000;
+// the changes within this are stored together
+const STORE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginKind {
+    Password,
+    Tfa,
+}
+
+impl LoginKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginKind::Password => "password",
+            LoginKind::Tfa => "2fa",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Check {
+    Allowed,
+    // remaining seconds
+    Banned(i64),
+    // until cleared manually
+    Locked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginBan {
+    pub ip: String,
+    pub kind: String,
+    // ms since epoch, 0 if locked until cleared
+    pub until: i64,
+    pub total: u32,
+}
+
+struct Policy {
+    max_attempts: u32,
+    window: i64,
+    ban_duration: i64,
+    backoff: bool,
+    max_total: u32,
+}
+
+impl Policy {
+    fn get() -> Self {
+        let get = |k: &str| Config::get_option(k);
+        Self {
+            max_attempts: get(keys::OPTION_LOGIN_MAX_ATTEMPTS)
+                .parse()
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            window: get(keys::OPTION_LOGIN_ATTEMPT_WINDOW)
+                .parse()
+                .unwrap_or(DEFAULT_ATTEMPT_WINDOW_SECS)
+                .saturating_mul(1000),
+            ban_duration: get(keys::OPTION_LOGIN_BAN_DURATION)
+                .parse()
+                .unwrap_or(DEFAULT_BAN_DURATION_SECS)
+                .saturating_mul(1000),
+            backoff: option2bool(
+                keys::OPTION_LOGIN_BAN_BACKOFF,
+                &get(keys::OPTION_LOGIN_BAN_BACKOFF),
+            ),
+            max_total: get(keys::OPTION_LOGIN_MAX_TOTAL_ATTEMPTS)
+                .parse()
+                .unwrap_or(DEFAULT_MAX_TOTAL_ATTEMPTS),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct LoginFailure {
+    #[serde(default)]
+    window_start: i64,
+    #[serde(default)]
+    window_count: u32,
+    #[serde(default)]
+    total: u32,
+    // number of bans so far, doubling the next ban duration if backoff is enabled
+    #[serde(default)]
+    bans: u32,
+    #[serde(default)]
+    banned_until: i64,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    last_time: i64,
+}
+
+impl LoginFailure {
+    fn check(&self, now: i64) -> Check {
+        if self.locked {
+            Check::Locked
+        } else if self.banned_until > now {
+            Check::Banned((self.banned_until - now + 999) / 1000)
+        } else {
+            Check::Allowed
+        }
+    }
+
+    fn fail(&mut self, policy: &Policy, now: i64) {
+        if now - self.window_start >= policy.window {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+        self.window_count += 1;
+        self.total += 1;
+        self.last_time = now;
+        if policy.max_total > 0 && self.total > policy.max_total {
+            self.locked = true;
+        } else if policy.max_attempts > 0 && self.window_count > policy.max_attempts {
+            let shift = if policy.backoff { self.bans.min(16) } else { 0 };
+            self.banned_until = now
+                + policy
+                    .ban_duration
+                    .saturating_mul(1 << shift)
+                    .min(MAX_BAN_DURATION_MS);
+            self.bans += 1;
+            self.window_start = now;
+            self.window_count = 0;
+        }
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        !self.locked && self.banned_until <= now && now - self.last_time > RECORD_EXPIRE_MS
+    }
+}
+
+fn prune(failures: &mut HashMap<String, LoginFailure>, now: i64) {
+    failures.retain(|_, f| !f.is_expired(now));
+    while failures.len() > MAX_RECORDS {
+        let oldest = failures
+            .iter()
+            .min_by_key(|(_, f)| (f.check(now) != Check::Allowed, f.last_time))
+            .map(|(ip, _)| ip.clone());
+        match oldest {
+            Some(ip) => failures.remove(&ip),
+            None => break,
+        };
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct LoginFailures {
+    #[serde(default)]
+    password: HashMap<String, LoginFailure>,
+    #[serde(default)]
+    tfa: HashMap<String, LoginFailure>,
+}
+
+impl LoginFailures {
+    fn file() -> PathBuf {
+        Config::path(format!("{}_login_failures.toml", crate::get_app_name()))
+    }
+
+    fn load() -> Self {
+        let mut failures: Self = config::load_path(Self::file());
+        let now = get_time();
+        prune(&mut failures.password, now);
+        prune(&mut failures.tfa, now);
+        failures
+    }
+
+    fn store(&self) {
+        if let Err(err) = config::store_path(Self::file(), self) {
+            log::error!("Failed to store login failures: {}", err);
+        }
+    }
+
+    fn get_mut(&mut self, kind: LoginKind) -> &mut HashMap<String, LoginFailure> {
+        match kind {
+            LoginKind::Password => &mut self.password,
+            LoginKind::Tfa => &mut self.tfa,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref FAILURES: Mutex<LoginFailures> = Mutex::new(LoginFailures::load());
+    // Storing blocks on io, so it is done in a dedicated thread.
+    static ref STORE_SENDER: Mutex<Sender<()>> = Mutex::new(start_writer());
+}
+
+fn start_writer() -> Sender<()> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        while rx.recv().is_ok() {
+            std::thread::sleep(STORE_DELAY);
+            while rx.try_recv().is_ok() {}
+            let failures = FAILURES.lock().unwrap().clone();
+            failures.store();
+        }
+    });
+    tx
+}
+
+fn request_store() {
+    STORE_SENDER.lock().unwrap().send(()).ok();
+}
+
+pub fn check(kind: LoginKind, ip: &str) -> Check {
+    FAILURES
+        .lock()
+        .unwrap()
+        .get_mut(kind)
+        .get(ip)
+        .map(|f| f.check(get_time()))
+        .unwrap_or(Check::Allowed)
+}
+
+pub fn fail(kind: LoginKind, ip: &str) {
+    let policy = Policy::get();
+    let now = get_time();
+    let mut failures = FAILURES.lock().unwrap();
+    let f = failures.get_mut(kind).entry(ip.to_owned()).or_default();
+    f.fail(&policy, now);
+    match f.check(now) {
+        Check::Locked => log::warn!(
+            "Login of {} locked for {}, clear it with --clear-login-bans",
+            kind.as_str(),
+            ip
+        ),
+        Check::Banned(secs) => {
+            log::warn!("Login of {} banned for {} {}s", kind.as_str(), ip, secs)
+        }
+        Check::Allowed => {}
+    }
+    prune(failures.get_mut(kind), now);
+    request_store();
+}
+
+pub fn succeed(kind: LoginKind, ip: &str) {
+    let mut failures = FAILURES.lock().unwrap();
+    if failures.get_mut(kind).remove(ip).is_some() {
+        request_store();
+    }
+}
+
+/// The ips currently banned or locked.
+pub fn list_bans() -> Vec<LoginBan> {
+    let now = get_time();
+    let mut failures = FAILURES.lock().unwrap();
+    let mut bans = Vec::new();
+    for kind in [LoginKind::Password, LoginKind::Tfa] {
+        for (ip, f) in failures.get_mut(kind).iter() {
+            let until = match f.check(now) {
+                Check::Allowed => continue,
+                Check::Banned(_) => f.banned_until,
+                Check::Locked => 0,
+            };
+            bans.push(LoginBan {
+                ip: ip.clone(),
+                kind: kind.as_str().to_owned(),
+                until,
+                total: f.total,
+            });
+        }
+    }
+    bans
+}
+
+/// Clear the records of `ip`, or all if None.
+pub fn clear_bans(ip: Option<&str>) {
+    let mut failures = FAILURES.lock().unwrap();
+    match ip {
+        Some(ip) => {
+            failures.password.remove(ip);
+            failures.tfa.remove(ip);
+        }
+        None => {
+            failures.password.clear();
+            failures.tfa.clear();
+        }
+    }
+    request_store();
+    log::info!("Login bans cleared: {}", ip.unwrap_or("all"));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_login_failure() {
+        let policy = Policy {
+            max_attempts: 3,
+            window: 60_000,
+            ban_duration: 10_000,
+            backoff: true,
+            max_total: 10,
+        };
+        let mut f = LoginFailure::default();
+        let mut now = 1_000_000;
+        for _ in 0..3 {
+            f.fail(&policy, now);
+        }
+        assert_eq!(f.check(now), Check::Allowed);
+        f.fail(&policy, now);
+        assert_eq!(f.check(now), Check::Banned(10));
+        now += 10_000;
+        assert_eq!(f.check(now), Check::Allowed);
+        for _ in 0..4 {
+            f.fail(&policy, now);
+        }
+        // doubled
+        assert_eq!(f.check(now), Check::Banned(20));
+        // the window restarts after a ban
+        now += 20_000;
+        f.fail(&policy, now);
+        now += 60_000;
+        f.fail(&policy, now);
+        assert_eq!(f.check(now), Check::Allowed);
+        f.fail(&policy, now);
+        assert_eq!(f.check(now), Check::Locked);
+    }
+
+    #[test]
+    fn test_default_policy() {
+        // banned on the 7th failure within a minute, locked on the 31st one
+        let policy = Policy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            window: DEFAULT_ATTEMPT_WINDOW_SECS * 1000,
+            ban_duration: DEFAULT_BAN_DURATION_SECS * 1000,
+            backoff: false,
+            max_total: DEFAULT_MAX_TOTAL_ATTEMPTS,
+        };
+        let mut f = LoginFailure::default();
+        let mut now = 1_000_000;
+        for _ in 0..6 {
+            f.fail(&policy, now);
+        }
+        assert_eq!(f.check(now), Check::Allowed);
+        f.fail(&policy, now);
+        assert_eq!(f.check(now), Check::Banned(60));
+        for _ in 0..3 {
+            now += 60_000;
+            for _ in 0..6 {
+                f.fail(&policy, now);
+            }
+        }
+        assert_eq!(f.total, 25);
+        for _ in 0..5 {
+            now += 60_000;
+            f.fail(&policy, now);
+        }
+        assert_eq!(f.check(now), Check::Allowed);
+        f.fail(&policy, now);
+        assert_eq!(f.check(now), Check::Locked);
+    }
+
+    #[test]
+    fn test_ban_duration_overflow() {
+        let policy = Policy {
+            max_attempts: 1,
+            window: 60_000,
+            ban_duration: i64::MAX,
+            backoff: true,
+            max_total: 0,
+        };
+        let mut f = LoginFailure::default();
+        for _ in 0..4 {
+            f.fail(&policy, 0);
+        }
+        assert_eq!(f.banned_until, MAX_BAN_DURATION_MS);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut failures = HashMap::new();
+        let now = 10 * RECORD_EXPIRE_MS;
+        let new = |last_time: i64, locked: bool| LoginFailure {
+            last_time,
+            locked,
+            ..Default::default()
+        };
+        failures.insert("expired".to_owned(), new(0, false));
+        failures.insert("locked".to_owned(), new(0, true));
+        for i in 0..MAX_RECORDS {
+            failures.insert(i.to_string(), new(now - i as i64, false));
+        }
+        prune(&mut failures, now);
+        assert_eq!(failures.len(), MAX_RECORDS);
+        assert!(!failures.contains_key("expired"));
+        assert!(failures.contains_key("locked"));
+        // the oldest idle one is dropped
+        assert!(!failures.contains_key(&(MAX_RECORDS - 1).to_string()));
+        assert!(failures.contains_key("0"));
+    }
+}
@@ -242,6 +242,24 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         conn_ids
     }
 
+    // Send to the subscribers accepted by `filter` only, e.g. the ones of a quality tier.
+    pub fn send_video_frame_filter<F: Fn(i32) -> bool>(
+        &self,
+        msg: Message,
+        filter: F,
+    ) -> HashSet<i32> {
+        let mut conn_ids = HashSet::new();
+        let msg = Arc::new(msg);
+        let mut lock = self.0.write().unwrap();
+        for s in lock.subscribes.values_mut() {
+            if filter(s.id()) {
+                s.send(msg.clone());
+                conn_ids.insert(s.id());
+            }
+        }
+        conn_ids
+    }
+
     pub fn send_without(&self, msg: Message, sub: i32) {
         let mut lock = self.0.write().unwrap();
         let msg = Arc::new(msg);
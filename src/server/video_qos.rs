@@ -4,6 +4,8 @@ use std::time::Duration;
 pub const FPS: u32 = 30;
 pub const MIN_FPS: u32 = 1;
 pub const MAX_FPS: u32 = 120;
+// More tiers cost more encoders, the lowest ones are merged if exceeded.
+pub const MAX_QUALITY_TIERS: usize = 3;
 trait Percent {
     fn as_percent(&self) -> u32;
 }
@@ -36,6 +38,21 @@ struct UserData {
     delay: Option<Delay>,
     response_delayed: bool,
     record: bool,
+    adjusted_quality: Option<Quality>, // quality after adjusted by the user's own delay
+}
+
+// The users sharing the same encoder, when per-viewer encoding is enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityTier {
+    pub quality: Quality,
+    pub fps: u32,
+    pub users: Vec<i32>,
+}
+
+impl QualityTier {
+    pub fn spf(&self) -> Duration {
+        Duration::from_secs_f32(1. / (self.fps as f32))
+    }
 }
 
 pub struct VideoQoS {
@@ -44,6 +61,8 @@ pub struct VideoQoS {
     users: HashMap<i32, UserData>,
     bitrate_store: u32,
     support_abr: HashMap<usize, bool>,
+    support_tiers: HashMap<usize, bool>,
+    tiers: Vec<QualityTier>,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -74,6 +93,96 @@ impl DelayState {
     }
 }
 
+fn adjust_quality(current: Quality, latest: Quality, delay: DelayState) -> Quality {
+    if delay != DelayState::Normal {
+        match current {
+            Quality::Best => {
+                if delay == DelayState::Broken {
+                    Quality::Low
+                } else {
+                    Quality::Balanced
+                }
+            }
+            Quality::Balanced => Quality::Low,
+            Quality::Low => Quality::Low,
+            Quality::Custom(b) => match delay {
+                DelayState::LowDelay => {
+                    Quality::Custom(if b >= 150 { 100 } else { std::cmp::min(50, b) })
+                }
+                DelayState::HighDelay => {
+                    Quality::Custom(if b >= 100 { 50 } else { std::cmp::min(25, b) })
+                }
+                DelayState::Broken => {
+                    Quality::Custom(if b >= 50 { 25 } else { std::cmp::min(10, b) })
+                }
+                DelayState::Normal => latest,
+            },
+        }
+    } else {
+        match current {
+            Quality::Low => {
+                if latest == Quality::Best {
+                    Quality::Balanced
+                } else {
+                    latest
+                }
+            }
+            Quality::Custom(current_b) => match latest {
+                Quality::Custom(latest_b) if current_b < latest_b / 2 => {
+                    Quality::Custom(latest_b / 2)
+                }
+                _ => latest,
+            },
+            _ => latest,
+        }
+    }
+}
+
+// Same as the bitrate ratio of the encoders
+fn quality_rank(quality: Quality) -> u32 {
+    match quality {
+        Quality::Best => 150,
+        Quality::Balanced => 100,
+        Quality::Low => 50,
+        Quality::Custom(b) => b,
+    }
+}
+
+// (id, fps, quality) of the users, no tiers if they can share one encoder.
+fn build_tiers(users: Vec<(i32, u32, Quality)>) -> Vec<QualityTier> {
+    let mut tiers: Vec<QualityTier> = vec![];
+    for (id, fps, quality) in users {
+        match tiers.iter_mut().find(|t| t.quality == quality) {
+            Some(t) => {
+                t.fps = std::cmp::min(t.fps, fps);
+                t.users.push(id);
+            }
+            None => tiers.push(QualityTier {
+                quality,
+                fps,
+                users: vec![id],
+            }),
+        }
+    }
+    if tiers.len() < 2 {
+        return vec![];
+    }
+    tiers.sort_by(|a, b| quality_rank(b.quality).cmp(&quality_rank(a.quality)));
+    while tiers.len() > MAX_QUALITY_TIERS {
+        if let Some(lowest) = tiers.pop() {
+            if let Some(last) = tiers.last_mut() {
+                last.quality = lowest.quality;
+                last.fps = std::cmp::min(last.fps, lowest.fps);
+                last.users.extend(lowest.users);
+            }
+        }
+    }
+    for t in tiers.iter_mut() {
+        t.users.sort();
+    }
+    tiers
+}
+
 impl Default for VideoQoS {
     fn default() -> Self {
         VideoQoS {
@@ -82,6 +191,8 @@ impl Default for VideoQoS {
             users: Default::default(),
             bitrate_store: 0,
             support_abr: Default::default(),
+            support_tiers: Default::default(),
+            tiers: Default::default(),
         }
     }
 }
@@ -116,6 +227,12 @@ impl VideoQoS {
         self.quality
     }
 
+    // Highest quality first, the first tier uses the shared encoder.
+    // Empty if all users share one encoder.
+    pub fn tiers(&self) -> &[QualityTier] {
+        &self.tiers
+    }
+
     pub fn record(&self) -> bool {
         self.users.iter().any(|u| u.1.record)
     }
@@ -124,6 +241,19 @@ impl VideoQoS {
         self.support_abr.insert(display_idx, support);
     }
 
+    // Whether the encoder of the display can be duplicated for the quality tiers.
+    pub fn set_support_tiers(&mut self, display_idx: usize, support: bool) {
+        if self.support_tiers.insert(display_idx, support) != Some(support) {
+            self.refresh(None);
+        }
+    }
+
+    fn tiers_enabled(&self) -> bool {
+        Config::get_option(config::keys::OPTION_ALLOW_PER_VIEWER_ENCODING) == "Y"
+            && !self.support_tiers.is_empty()
+            && self.support_tiers.iter().all(|e| *e.1)
+    }
+
     pub fn in_vbr_state(&self) -> bool {
         Config::get_option("enable-abr") != "N" && self.support_abr.iter().all(|e| *e.1)
     }
@@ -183,7 +313,8 @@ impl VideoQoS {
 
         // network delay
         let abr_enabled = self.in_vbr_state();
-        if abr_enabled && typ != Some(RefreshType::SetImageQuality) {
+        let adjust = abr_enabled && typ != Some(RefreshType::SetImageQuality);
+        if adjust {
             // max delay
             let delay = self
                 .users
@@ -194,56 +325,38 @@ impl VideoQoS {
                     (a.unwrap_or_default().state as u32).cmp(&(b.unwrap_or_default().state as u32))
                 });
             let delay = delay.unwrap_or_default().unwrap_or_default().state;
-            if delay != DelayState::Normal {
-                match self.quality {
-                    Quality::Best => {
-                        quality = if delay == DelayState::Broken {
-                            Quality::Low
-                        } else {
-                            Quality::Balanced
-                        };
-                    }
-                    Quality::Balanced => {
-                        quality = Quality::Low;
-                    }
-                    Quality::Low => {
-                        quality = Quality::Low;
-                    }
-                    Quality::Custom(b) => match delay {
-                        DelayState::LowDelay => {
-                            quality =
-                                Quality::Custom(if b >= 150 { 100 } else { std::cmp::min(50, b) });
-                        }
-                        DelayState::HighDelay => {
-                            quality =
-                                Quality::Custom(if b >= 100 { 50 } else { std::cmp::min(25, b) });
-                        }
-                        DelayState::Broken => {
-                            quality =
-                                Quality::Custom(if b >= 50 { 25 } else { std::cmp::min(10, b) });
-                        }
-                        DelayState::Normal => {}
-                    },
-                }
-            } else {
-                match self.quality {
-                    Quality::Low => {
-                        if latest_quality == Quality::Best {
-                            quality = Quality::Balanced;
-                        }
-                    }
-                    Quality::Custom(current_b) => {
-                        if let Quality::Custom(latest_b) = latest_quality {
-                            if current_b < latest_b / 2 {
-                                quality = Quality::Custom(latest_b / 2);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
+            quality = adjust_quality(self.quality, latest_quality, delay);
         }
         self.quality = quality;
+
+        // per-viewer, each user is adjusted by its own delay only
+        let mut users = vec![];
+        for (id, u) in self.users.iter_mut() {
+            let own_quality = u.quality.map(|q| q.1).unwrap_or(latest_quality);
+            let quality = if adjust {
+                let delay = u.delay.unwrap_or_default().state;
+                adjust_quality(
+                    u.adjusted_quality.unwrap_or(own_quality),
+                    own_quality,
+                    delay,
+                )
+            } else {
+                own_quality
+            };
+            u.adjusted_quality = Some(quality);
+            users.push((*id, user_fps(u).clamp(MIN_FPS, MAX_FPS), quality));
+        }
+        // otherwise all users share one encoder with the min fps and the max delay above
+        self.tiers = if self.tiers_enabled() {
+            build_tiers(users)
+        } else {
+            vec![]
+        };
+        if let Some(first) = self.tiers.first() {
+            // capture as fast as the fastest tier, every tier skips the frames it does not need
+            self.fps = self.tiers.iter().map(|t| t.fps).max().unwrap_or(FPS);
+            self.quality = first.quality;
+        }
     }
 
     pub fn user_custom_fps(&mut self, id: i32, fps: u32) {
@@ -389,3 +502,31 @@ impl VideoQoS {
         self.refresh(None);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_tiers() {
+        let best = (1, 60, Quality::Best);
+        assert!(build_tiers(vec![best]).is_empty());
+        assert!(build_tiers(vec![best, (2, 30, Quality::Best)]).is_empty());
+        let tiers = build_tiers(vec![(2, 15, Quality::Low), best, (3, 30, Quality::Low)]);
+        assert_eq!(tiers.len(), 2);
+        assert_eq!(tiers[0].quality, Quality::Best);
+        assert_eq!(tiers[0].users, vec![1]);
+        assert_eq!(tiers[1].fps, 15);
+        assert_eq!(tiers[1].users, vec![2, 3]);
+        let tiers = build_tiers(vec![
+            best,
+            (2, 30, Quality::Balanced),
+            (3, 30, Quality::Custom(80)),
+            (4, 10, Quality::Low),
+        ]);
+        assert_eq!(tiers.len(), MAX_QUALITY_TIERS);
+        assert_eq!(tiers[2].quality, Quality::Low);
+        assert_eq!(tiers[2].fps, 10);
+        assert_eq!(tiers[2].users, vec![3, 4]);
+    }
+}
@@ -21,7 +21,7 @@
 use super::{
     display_service::{check_display_changed, get_display_info},
     service::ServiceTmpl,
-    video_qos::VideoQoS,
+    video_qos::{QualityTier, VideoQoS},
     *,
 };
 #[cfg(target_os = "linux")]
@@ -54,12 +54,12 @@ use scrap::{
     codec::{Encoder, EncoderCfg, Quality},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
-    CodecFormat, Display, EncodeInput, TraitCapturer,
+    CodecFormat, Display, EncodeInput, Frame, TraitCapturer, YuvBuffer,
 };
 #[cfg(windows)]
 use std::sync::Once;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     io::ErrorKind::WouldBlock,
     ops::{Deref, DerefMut},
     time::{self, Duration, Instant},
@@ -76,6 +76,8 @@ lazy_static::lazy_static! {
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
+    static ref RECORDERS: Arc<Mutex<HashMap<usize, Arc<Mutex<Option<Recorder>>>>>> = Default::default();
+    static ref RECORD_AUDIO_FORMAT: Arc<Mutex<Option<AudioFormat>>> = Default::default();
 }
 
 #[inline]
@@ -83,6 +85,38 @@ pub fn notify_video_frame_fetched(conn_id: i32, frame_tm: Option<Instant>) {
     FRAME_FETCHED_NOTIFIER.0.send((conn_id, frame_tm)).ok();
 }
 
+// Audio is captured by the audio service, pass it to the recorders of all displays.
+pub fn set_record_audio_format(format: AudioFormat) {
+    *RECORD_AUDIO_FORMAT.lock().unwrap() = Some(format.clone());
+    for recorder in RECORDERS.lock().unwrap().values() {
+        recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|r| r.set_audio_format(&format));
+    }
+}
+
+// Audio is not captured any more.
+pub fn clear_record_audio_format() {
+    *RECORD_AUDIO_FORMAT.lock().unwrap() = None;
+}
+
+#[inline]
+fn record_audio() -> bool {
+    RECORD_AUDIO_FORMAT.lock().unwrap().is_some()
+}
+
+pub fn record_audio_frame(frame: &AudioFrame) {
+    for recorder in RECORDERS.lock().unwrap().values() {
+        recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|r| r.write_audio(frame));
+    }
+}
+
 struct VideoFrameController {
     cur: Instant,
     send_conn_ids: HashSet<i32>,
@@ -133,6 +167,73 @@ impl VideoFrameController {
     }
 }
 
+// The encoder of a lower quality tier, fed with the same yuv as the shared encoder.
+struct TierEncoder {
+    quality: Quality,
+    spf: Duration,
+    users: HashSet<i32>,
+    encoder: Encoder,
+    last: Option<Instant>,
+    encode_fail_counter: usize,
+    first_frame: bool,
+}
+
+impl TierEncoder {
+    // Half a capture interval of tolerance, or a tier with the capture fps would skip frames.
+    fn due(&self, now: Instant, capture_spf: Duration) -> bool {
+        self.last
+            .map_or(true, |last| now + capture_spf / 2 >= last + self.spf)
+    }
+}
+
+// The hardware muxer has no audio track. While audio is recorded with H264/H265, the recorder gets
+// the frames of this VP9 encoder instead, the peers still get the negotiated codec.
+struct RecordEncoder {
+    encoder: Encoder,
+    yuv: YuvBuffer,
+    mid_data: Vec<u8>,
+}
+
+impl RecordEncoder {
+    fn new(c: &CapturerInfo, quality: Quality) -> ResultType<Self> {
+        let encoder = Encoder::new(
+            EncoderCfg::VPX(VpxEncoderConfig {
+                width: c.width as _,
+                height: c.height as _,
+                quality,
+                codec: VpxVideoCodecId::VP9,
+                keyframe_interval: Some(240),
+            }),
+            false,
+        )?;
+        Ok(Self {
+            encoder,
+            yuv: YuvBuffer::default(),
+            mid_data: Vec::new(),
+        })
+    }
+
+    fn record(
+        &mut self,
+        display: usize,
+        frame: &Frame,
+        ms: i64,
+        recorder: &Arc<Mutex<Option<Recorder>>>,
+    ) -> ResultType<()> {
+        let input = frame.to(self.encoder.yuvfmt(), &mut self.yuv, &mut self.mid_data)?;
+        let mut vf = self.encoder.encode_to_message(input, ms)?;
+        vf.display = display as _;
+        let mut msg = Message::new();
+        msg.set_video_frame(vf);
+        recorder
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|r| r.write_message(&msg));
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct VideoService {
     sp: GenericService,
@@ -421,37 +522,49 @@ fn run(vs: VideoService) -> ResultType<()> {
         &Config::get_option("allow-auto-record-incoming"),
     );
     let client_record = video_qos.record();
+    let last_record_audio = record_audio();
     drop(video_qos);
-    let (mut encoder, encoder_cfg, codec_format, use_i444, recorder) = match setup_encoder(
-        &c,
-        display_idx,
-        quality,
-        client_record,
-        record_incoming,
-        last_portable_service_running,
-    ) {
-        Ok(result) => result,
-        Err(err) => {
-            log::error!("Failed to create encoder: {err:?}, fallback to VP9");
-            Encoder::set_fallback(&EncoderCfg::VPX(VpxEncoderConfig {
-                width: c.width as _,
-                height: c.height as _,
-                quality,
-                codec: VpxVideoCodecId::VP9,
-                keyframe_interval: None,
-            }));
-            setup_encoder(
-                &c,
-                display_idx,
-                quality,
-                client_record,
-                record_incoming,
-                last_portable_service_running,
-            )?
-        }
-    };
+    let (mut encoder, encoder_cfg, codec_format, use_i444, recorder, mut record_encoder) =
+        match setup_encoder(
+            &c,
+            display_idx,
+            quality,
+            client_record,
+            record_incoming,
+            last_portable_service_running,
+        ) {
+            Ok(result) => result,
+            Err(err) => {
+                log::error!("Failed to create encoder: {err:?}, fallback to VP9");
+                Encoder::set_fallback(&EncoderCfg::VPX(VpxEncoderConfig {
+                    width: c.width as _,
+                    height: c.height as _,
+                    quality,
+                    codec: VpxVideoCodecId::VP9,
+                    keyframe_interval: None,
+                }));
+                setup_encoder(
+                    &c,
+                    display_idx,
+                    quality,
+                    client_record,
+                    record_incoming,
+                    last_portable_service_running,
+                )?
+            }
+        };
     #[cfg(feature = "vram")]
     c.set_output_texture(encoder.input_texture());
+    // the frames of the shared encoder are not recorded if the recorder has its own encoder
+    let shared_recorder = if record_encoder.is_some() {
+        Default::default()
+    } else {
+        recorder.clone()
+    };
+    RECORDERS
+        .lock()
+        .unwrap()
+        .insert(display_idx, recorder.clone());
     #[cfg(target_os = "android")]
     if let Err(e) = check_change_scale(encoder.is_hardware()) {
         try_broadcast_display_changed(&sp, display_idx, &c, true).ok();
@@ -462,6 +575,10 @@ fn run(vs: VideoService) -> ResultType<()> {
         .lock()
         .unwrap()
         .set_support_abr(display_idx, encoder.support_abr());
+    VIDEO_QOS.lock().unwrap().set_support_tiers(
+        display_idx,
+        !encoder.is_hardware() && tier_encoder_config(&encoder_cfg, quality).is_some(),
+    );
     log::info!("initial quality: {quality:?}");
 
     if sp.is_option_true(OPTION_REFRESH) {
@@ -481,12 +598,17 @@ fn run(vs: VideoService) -> ResultType<()> {
 
     #[cfg(target_os = "linux")]
     let mut would_block_count = 0u32;
-    let mut yuv = Vec::new();
+    let mut yuv = YuvBuffer::default();
     let mut mid_data = Vec::new();
     let mut repeat_encode_counter = 0;
     let repeat_encode_max = 10;
     let mut encode_fail_counter = 0;
     let mut first_frame = true;
+    let mut tiers: Vec<QualityTier> = vec![];
+    let mut tier_encoders: Vec<TierEncoder> = vec![];
+    let mut primary_spf = Duration::ZERO;
+    let mut last_primary: Option<Instant> = None;
+    let mut primary_skipped = false;
 
     while sp.ok() {
         #[cfg(windows)]
@@ -494,6 +616,19 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         let mut video_qos = VIDEO_QOS.lock().unwrap();
         spf = video_qos.spf();
+        if tiers != video_qos.tiers() {
+            let moved = tier_moved_to(&tiers, video_qos.tiers());
+            tiers = video_qos.tiers().to_vec();
+            sync_tier_encoders(&encoder_cfg, use_i444, &tiers, &mut tier_encoders);
+            primary_spf = tiers.first().map(|t| t.spf()).unwrap_or(Duration::ZERO);
+            // a user moved to another encoder needs a key frame
+            for quality in moved {
+                match quality.and_then(|q| tier_encoders.iter_mut().find(|t| t.quality == q)) {
+                    Some(t) => t.encoder.request_keyframe(),
+                    None => encoder.request_keyframe(),
+                }
+            }
+        }
         if quality != video_qos.quality() {
             log::debug!("quality: {:?} -> {:?}", quality, video_qos.quality());
             quality = video_qos.quality();
@@ -511,6 +646,13 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to record changed");
             bail!("SWITCH");
         }
+        if record_incoming
+            && matches!(codec_format, CodecFormat::H264 | CodecFormat::H265)
+            && last_record_audio != record_audio()
+        {
+            log::info!("switch due to record audio changed");
+            bail!("SWITCH");
+        }
         drop(video_qos);
 
         if sp.is_option_true(OPTION_REFRESH) {
@@ -566,17 +708,40 @@ fn run(vs: VideoService) -> ResultType<()> {
             Ok(frame) => {
                 repeat_encode_counter = 0;
                 if frame.valid() {
+                    let due = last_primary.map_or(true, |last| now + spf / 2 >= last + primary_spf);
+                    if due {
+                        // relative to the last captured frame, which may be skipped
+                        let damage = if primary_skipped {
+                            None
+                        } else {
+                            frame.damage()
+                        };
+                        allow_err!(encoder.set_damage(damage));
+                    }
+                    if let Some(r) = record_encoder.as_mut() {
+                        if let Err(e) = r.record(display_idx, &frame, ms, &recorder) {
+                            log::error!("Failed to encode the frame to record: {e:?}");
+                        }
+                    }
                     let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
-                    let send_conn_ids = handle_one_frame(
-                        display_idx,
-                        &sp,
-                        frame,
-                        ms,
-                        &mut encoder,
-                        recorder.clone(),
-                        &mut encode_fail_counter,
-                        &mut first_frame,
-                    )?;
+                    let mut send_conn_ids = HashSet::new();
+                    primary_skipped = !due;
+                    if due {
+                        last_primary = Some(now);
+                        send_conn_ids = handle_one_frame(
+                            display_idx,
+                            &sp,
+                            frame,
+                            ms,
+                            &mut encoder,
+                            shared_recorder.clone(),
+                            &mut encode_fail_counter,
+                            &mut first_frame,
+                            |id| !tier_encoders.iter().any(|t| t.users.contains(&id)),
+                        )?;
+                    }
+                    // not wait for the lower tiers, they are throttled by their own fps
+                    handle_tier_frames(display_idx, &sp, &yuv, ms, now, spf, &mut tier_encoders)?;
                     frame_controller.set_send(now, send_conn_ids);
                 }
                 #[cfg(windows)]
@@ -629,9 +794,19 @@ fn run(vs: VideoService) -> ResultType<()> {
                             EncodeInput::YUV(&yuv),
                             ms,
                             &mut encoder,
-                            recorder.clone(),
+                            shared_recorder.clone(),
                             &mut encode_fail_counter,
                             &mut first_frame,
+                            |id| !tier_encoders.iter().any(|t| t.users.contains(&id)),
+                        )?;
+                        handle_tier_frames(
+                            display_idx,
+                            &sp,
+                            &yuv,
+                            ms,
+                            now,
+                            spf,
+                            &mut tier_encoders,
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                     }
@@ -665,7 +840,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             check_privacy_mode_changed(&sp, display_idx, &c)?;
             frame_controller.try_wait_next(&mut fetched_conn_ids, 300);
             // break if all connections have received current frame
-            if fetched_conn_ids.len() >= frame_controller.send_conn_ids.len() {
+            if frame_controller.send_conn_ids.is_subset(&fetched_conn_ids) {
                 break;
             }
         }
@@ -696,6 +871,8 @@ impl Drop for Raii {
         #[cfg(feature = "vram")]
         Encoder::update(scrap::codec::EncodingUpdate::Check);
         VIDEO_QOS.lock().unwrap().set_support_abr(self.0, true);
+        VIDEO_QOS.lock().unwrap().set_support_tiers(self.0, true);
+        RECORDERS.lock().unwrap().remove(&self.0);
     }
 }
 
@@ -712,20 +889,44 @@ fn setup_encoder(
     CodecFormat,
     bool,
     Arc<Mutex<Option<Recorder>>>,
+    Option<RecordEncoder>,
 )> {
+    let audio = record_incoming && record_audio();
     let encoder_cfg = get_encoder_config(
         &c,
         display_idx,
         quality,
         client_record || record_incoming,
+        audio,
         last_portable_service_running,
     );
     Encoder::set_fallback(&encoder_cfg);
     let codec_format = Encoder::negotiated_codec();
-    let recorder = get_recorder(c.width, c.height, &codec_format, record_incoming);
+    let record_encoder = if audio && matches!(codec_format, CodecFormat::H264 | CodecFormat::H265) {
+        log::info!(
+            "record audio, record with VP9 instead of {:?}",
+            codec_format
+        );
+        Some(RecordEncoder::new(c, quality)?)
+    } else {
+        None
+    };
+    let record_format = if record_encoder.is_some() {
+        CodecFormat::VP9
+    } else {
+        codec_format.clone()
+    };
+    let recorder = get_recorder(c.width, c.height, &record_format, record_incoming);
     let use_i444 = Encoder::use_i444(&encoder_cfg);
     let encoder = Encoder::new(encoder_cfg.clone(), use_i444)?;
-    Ok((encoder, encoder_cfg, codec_format, use_i444, recorder))
+    Ok((
+        encoder,
+        encoder_cfg,
+        codec_format,
+        use_i444,
+        recorder,
+        record_encoder,
+    ))
 }
 
 fn get_encoder_config(
@@ -733,6 +934,7 @@ fn get_encoder_config(
     _display_idx: usize,
     quality: Quality,
     record: bool,
+    _record_audio: bool,
     _portable_service: bool,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "vram"))]
@@ -747,16 +949,19 @@ fn get_encoder_config(
     let negotiated_codec = Encoder::negotiated_codec();
     match negotiated_codec {
         CodecFormat::H264 | CodecFormat::H265 => {
+            // the recorder encodes the captured pixels while recording audio, not textures
             #[cfg(feature = "vram")]
-            if let Some(feature) = VRamEncoder::try_get(&c.device(), negotiated_codec) {
-                return EncoderCfg::VRAM(VRamEncoderConfig {
-                    device: c.device(),
-                    width: c.width,
-                    height: c.height,
-                    quality,
-                    feature,
-                    keyframe_interval,
-                });
+            if !_record_audio {
+                if let Some(feature) = VRamEncoder::try_get(&c.device(), negotiated_codec) {
+                    return EncoderCfg::VRAM(VRamEncoderConfig {
+                        device: c.device(),
+                        width: c.width,
+                        height: c.height,
+                        quality,
+                        feature,
+                        keyframe_interval,
+                    });
+                }
             }
             #[cfg(feature = "hwcodec")]
             if let Some(hw) = HwRamEncoder::try_get(negotiated_codec) {
@@ -832,6 +1037,7 @@ fn get_recorder(
             width,
             height,
             format: codec_format.clone(),
+            audio_format: RECORD_AUDIO_FORMAT.lock().unwrap().clone(),
             tx,
         })
         .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
@@ -910,6 +1116,7 @@ fn handle_one_frame(
     recorder: Arc<Mutex<Option<Recorder>>>,
     encode_fail_counter: &mut usize,
     first_frame: &mut bool,
+    filter: impl Fn(i32) -> bool,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -934,7 +1141,7 @@ fn handle_one_frame(
                 .unwrap()
                 .as_mut()
                 .map(|r| r.write_message(&msg));
-            send_conn_ids = sp.send_video_frame(msg);
+            send_conn_ids = sp.send_video_frame_filter(msg, filter);
         }
         Err(e) => {
             *encode_fail_counter += 1;
@@ -969,6 +1176,118 @@ fn handle_one_frame(
     Ok(send_conn_ids)
 }
 
+// Only the software encoders are duplicated, hardware encoders have limited sessions
+// and texture input can not be shared.
+fn tier_encoder_config(cfg: &EncoderCfg, quality: Quality) -> Option<EncoderCfg> {
+    match cfg {
+        EncoderCfg::VPX(c) => Some(EncoderCfg::VPX(VpxEncoderConfig { quality, ..*c })),
+        EncoderCfg::AOM(c) => Some(EncoderCfg::AOM(AomEncoderConfig { quality, ..*c })),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+// The encoders which the remaining users move to with the new tiers.
+fn tier_moved_to(old: &[QualityTier], new: &[QualityTier]) -> Vec<Option<Quality>> {
+    // None is the shared encoder
+    let tier_of = |tiers: &[QualityTier], id: i32| {
+        tiers
+            .iter()
+            .skip(1)
+            .find(|t| t.users.contains(&id))
+            .map(|t| t.quality)
+    };
+    // all users are back to the shared encoder if no tiers
+    let users = if new.is_empty() { old } else { new };
+    let mut moved = vec![];
+    for id in users.iter().flat_map(|t| t.users.iter()) {
+        let to = tier_of(new, *id);
+        if tier_of(old, *id) != to && !moved.contains(&to) {
+            moved.push(to);
+        }
+    }
+    moved
+}
+
+// Keep one encoder for each tier except the first one, which uses the shared encoder.
+// The users of a tier whose encoder fails to create fall back to the shared encoder.
+fn sync_tier_encoders(
+    cfg: &EncoderCfg,
+    use_i444: bool,
+    tiers: &[QualityTier],
+    tier_encoders: &mut Vec<TierEncoder>,
+) {
+    let mut old = std::mem::take(tier_encoders);
+    for tier in tiers.iter().skip(1) {
+        let users = tier.users.iter().cloned().collect();
+        if let Some(pos) = old.iter().position(|t| t.quality == tier.quality) {
+            let mut t = old.remove(pos);
+            t.spf = tier.spf();
+            t.users = users;
+            tier_encoders.push(t);
+            continue;
+        }
+        let cfg = match tier_encoder_config(cfg, tier.quality) {
+            Some(cfg) => cfg,
+            None => {
+                log::info!("no encoder for quality tiers with {:?}", cfg);
+                tier_encoders.clear();
+                return;
+            }
+        };
+        // same config except the quality, so the yuv of the shared encoder fits
+        match Encoder::new(cfg, use_i444) {
+            Ok(encoder) => {
+                log::info!("new encoder for quality tier {:?}", tier);
+                tier_encoders.push(TierEncoder {
+                    quality: tier.quality,
+                    spf: tier.spf(),
+                    users,
+                    encoder,
+                    last: None,
+                    encode_fail_counter: 0,
+                    first_frame: true,
+                });
+            }
+            Err(e) => log::error!("Failed to create encoder of quality tier {:?}: {e:?}", tier),
+        }
+    }
+}
+
+// Encode the yuv with the encoders of the tiers which need this frame.
+fn handle_tier_frames(
+    display: usize,
+    sp: &GenericService,
+    yuv: &[u8],
+    ms: i64,
+    now: Instant,
+    capture_spf: Duration,
+    tier_encoders: &mut [TierEncoder],
+) -> ResultType<()> {
+    if yuv.is_empty() {
+        return Ok(());
+    }
+    for t in tier_encoders.iter_mut() {
+        if !t.due(now, capture_spf) || !t.users.iter().any(|id| sp.is_subed(*id)) {
+            continue;
+        }
+        t.last = Some(now);
+        let users = &t.users;
+        handle_one_frame(
+            display,
+            sp,
+            EncodeInput::YUV(yuv),
+            ms,
+            &mut t.encoder,
+            Default::default(),
+            &mut t.encode_fail_counter,
+            &mut t.first_frame,
+            |id| users.contains(&id),
+        )?;
+    }
+    Ok(())
+}
+
 #[inline]
 pub fn refresh() {
     #[cfg(target_os = "android")]
@@ -0,0 +1,367 @@
+// Channels of a multiplexed port forwarding session.
+// Each forwarded tcp connection or udp flow is a channel, carried by `TunnelMessage`.
+use hbb_common::{
+    bytes::Bytes,
+    futures::{SinkExt, StreamExt},
+    log,
+    message_proto::*,
+    timeout,
+    tokio::{
+        self,
+        net::{lookup_host, TcpStream, UdpSocket},
+        sync::mpsc,
+        time::{interval, Duration, Instant},
+    },
+    tokio_util::codec::{BytesCodec, FramedRead, FramedWrite},
+    ResultType,
+};
+use std::{collections::HashMap, sync::Mutex};
+
+const CONNECT_TIMEOUT: u64 = 3_000;
+// A channel not taking the data of the peer within this is closed.
+const SEND_DATA_TIMEOUT: u64 = 10_000;
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+// The queues are bounded, a slow side stalls the other one instead of buffering without limit.
+pub const QUEUE_SIZE: usize = 64;
+// Channels of reverse forwards are allocated by the controlled side.
+pub const REVERSE_CHANNEL: u32 = 0x8000_0000;
+
+pub type TunnelSender = mpsc::Sender<TunnelMessage>;
+pub type DataSender = mpsc::Sender<Bytes>;
+
+pub fn new_message(tm: TunnelMessage) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_tunnel_message(tm);
+    msg_out
+}
+
+pub fn new_open(channel: u32, host: String, port: i32, udp: bool) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_open(TunnelOpen {
+        channel,
+        host,
+        port,
+        udp,
+        ..Default::default()
+    });
+    tm
+}
+
+pub fn new_opened(channel: u32) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_opened(TunnelOpened {
+        channel,
+        ..Default::default()
+    });
+    tm
+}
+
+pub fn new_data(channel: u32, data: Bytes) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_data(TunnelData {
+        channel,
+        data,
+        ..Default::default()
+    });
+    tm
+}
+
+pub fn new_close(channel: u32, error: String) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_close(TunnelClose {
+        channel,
+        error,
+        ..Default::default()
+    });
+    tm
+}
+
+pub fn new_listen(port: i32, host: String, target_port: i32) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_listen(TunnelListen {
+        port,
+        host,
+        target_port,
+        ..Default::default()
+    });
+    tm
+}
+
+pub fn new_listen_response(port: i32, error: String) -> TunnelMessage {
+    let mut tm = TunnelMessage::new();
+    tm.set_listen_response(TunnelListenResponse {
+        port,
+        error,
+        ..Default::default()
+    });
+    tm
+}
+
+/// Send the data of the peer to the channel, waiting if the queue is full.
+/// Returns false if the channel is closed or stalled, it should be closed then.
+pub async fn send_data(tx: &DataSender, data: Bytes) -> bool {
+    matches!(timeout(SEND_DATA_TIMEOUT, tx.send(data)).await, Ok(Ok(())))
+}
+
+/// The error to reject a channel opened by the controlling side with, None if it can be opened.
+pub fn check_open<T>(
+    channel: u32,
+    channels: &HashMap<u32, T>,
+    max_channels: usize,
+) -> Option<&'static str> {
+    if channel & REVERSE_CHANNEL != 0 {
+        Some("Channel reserved for reverse forwarding")
+    } else if channels.contains_key(&channel) {
+        Some("Channel already open")
+    } else if channels.len() >= max_channels {
+        Some("Too many channels")
+    } else {
+        None
+    }
+}
+
+/// Connect to the target of `open` and pump the channel in background.
+/// Returns the sender of the data to the target.
+pub fn open(open: TunnelOpen, tx: TunnelSender) -> DataSender {
+    let (tx_data, rx_data) = mpsc::channel(QUEUE_SIZE);
+    tokio::spawn(async move {
+        let channel = open.channel;
+        let host = if open.host.is_empty() {
+            "localhost"
+        } else {
+            &open.host
+        };
+        let addr = if host.contains(':') {
+            // ipv6
+            format!("[{}]:{}", host, open.port)
+        } else {
+            format!("{}:{}", host, open.port)
+        };
+        log::info!(
+            "Tunnel channel {} opened to {} {}",
+            channel,
+            if open.udp { "udp" } else { "tcp" },
+            addr
+        );
+        let res = if open.udp {
+            match connect_udp(&addr).await {
+                Ok(socket) => {
+                    tx.send(new_opened(channel)).await.ok();
+                    run_udp(channel, socket, rx_data, tx.clone()).await;
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            }
+        } else {
+            match timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+                Ok(Ok(socket)) => {
+                    tx.send(new_opened(channel)).await.ok();
+                    run_tcp(channel, socket, rx_data, tx.clone()).await;
+                    Ok(())
+                }
+                Ok(Err(err)) => Err(err.into()),
+                Err(err) => Err(err.into()),
+            }
+        };
+        if let Err(err) = res {
+            log::error!("Failed to access {}: {}", addr, err);
+            tx.send(new_close(
+                channel,
+                format!("Failed to access {}: {}", addr, err),
+            ))
+            .await
+            .ok();
+        }
+    });
+    tx_data
+}
+
+async fn connect_udp(addr: &str) -> ResultType<UdpSocket> {
+    let target = match lookup_host(addr).await?.next() {
+        Some(target) => target,
+        None => hbb_common::bail!("Failed to resolve {}", addr),
+    };
+    let socket = if target.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0").await?
+    } else {
+        UdpSocket::bind("[::]:0").await?
+    };
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// Pump a tcp connection until either side closes it.
+pub async fn run_tcp(
+    channel: u32,
+    socket: TcpStream,
+    mut rx: mpsc::Receiver<Bytes>,
+    tx: TunnelSender,
+) {
+    // Both directions are pumped concurrently, so that the data of the peer is taken even if
+    // the queue to the peer is full.
+    let (reader, writer) = socket.into_split();
+    let reading = async {
+        let mut reader = FramedRead::new(reader, BytesCodec::new());
+        loop {
+            match reader.next().await {
+                Some(Ok(bytes)) => {
+                    if tx.send(new_data(channel, bytes.freeze())).await.is_err() {
+                        break "".to_owned();
+                    }
+                }
+                Some(Err(err)) => break err.to_string(),
+                None => break "".to_owned(),
+            }
+        }
+    };
+    let writing = async {
+        let mut writer = FramedWrite::new(writer, BytesCodec::new());
+        // None if closed by the peer
+        while let Some(data) = rx.recv().await {
+            if let Err(err) = writer.send(data).await {
+                return err.to_string();
+            }
+        }
+        "".to_owned()
+    };
+    let error = tokio::select! {
+        error = reading => error,
+        error = writing => error,
+    };
+    log::info!("Tunnel channel {} closed {}", channel, error);
+    tx.send(new_close(channel, error)).await.ok();
+}
+
+// Pump a connected udp socket, the channel is closed if idle for `UDP_IDLE_TIMEOUT`.
+async fn run_udp(channel: u32, socket: UdpSocket, mut rx: mpsc::Receiver<Bytes>, tx: TunnelSender) {
+    let last_active = Mutex::new(Instant::now());
+    let reading = async {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            match socket.recv(&mut buf).await {
+                Ok(n) => {
+                    *last_active.lock().unwrap() = Instant::now();
+                    let data = Bytes::copy_from_slice(&buf[..n]);
+                    if tx.send(new_data(channel, data)).await.is_err() {
+                        break "".to_owned();
+                    }
+                }
+                Err(err) => break err.to_string(),
+            }
+        }
+    };
+    let writing = async {
+        while let Some(data) = rx.recv().await {
+            *last_active.lock().unwrap() = Instant::now();
+            if let Err(err) = socket.send(&data).await {
+                return err.to_string();
+            }
+        }
+        "".to_owned()
+    };
+    let idle = async {
+        let mut timer = interval(Duration::from_secs(1));
+        loop {
+            timer.tick().await;
+            if last_active.lock().unwrap().elapsed() >= UDP_IDLE_TIMEOUT {
+                break "".to_owned();
+            }
+        }
+    };
+    let error = tokio::select! {
+        error = reading => error,
+        error = writing => error,
+        error = idle => error,
+    };
+    log::info!("Tunnel channel {} closed {}", channel, error);
+    tx.send(new_close(channel, error)).await.ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    async fn recv(rx: &mut mpsc::Receiver<TunnelMessage>) -> Option<tunnel_message::Union> {
+        timeout(CONNECT_TIMEOUT, rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .union
+    }
+
+    #[tokio::test]
+    async fn test_tcp_channel() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port() as i32;
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            loop {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => socket.write_all(&buf[..n]).await.unwrap(),
+                }
+            }
+        });
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let open = match new_open(1, "127.0.0.1".to_owned(), port, false).union {
+            Some(tunnel_message::Union::Open(open)) => open,
+            _ => unreachable!(),
+        };
+        let tx_data = super::open(open, tx);
+        assert!(
+            matches!(recv(&mut rx).await, Some(tunnel_message::Union::Opened(o)) if o.channel == 1)
+        );
+        assert!(send_data(&tx_data, Bytes::from_static(b"hello")).await);
+        match recv(&mut rx).await {
+            Some(tunnel_message::Union::Data(d)) => {
+                assert_eq!(d.channel, 1);
+                assert_eq!(&d.data[..], b"hello");
+            }
+            _ => panic!("expect data"),
+        }
+        // closed by the peer
+        drop(tx_data);
+        assert!(
+            matches!(recv(&mut rx).await, Some(tunnel_message::Union::Close(c)) if c.channel == 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_channel_failed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port() as i32;
+        drop(listener);
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let open = match new_open(2, "127.0.0.1".to_owned(), port, false).union {
+            Some(tunnel_message::Union::Open(open)) => open,
+            _ => unreachable!(),
+        };
+        let tx_data = super::open(open, tx);
+        match recv(&mut rx).await {
+            Some(tunnel_message::Union::Close(c)) => {
+                assert_eq!(c.channel, 2);
+                assert!(c.error.starts_with("Failed to access"));
+            }
+            _ => panic!("expect close"),
+        }
+        assert!(!send_data(&tx_data, Bytes::from_static(b"hello")).await);
+    }
+
+    #[test]
+    fn test_check_open() {
+        let channels = HashMap::from([(1, ()), (2, ())]);
+        assert_eq!(check_open(3, &channels, 3), None);
+        assert_eq!(
+            check_open(REVERSE_CHANNEL | 3, &channels, 3),
+            Some("Channel reserved for reverse forwarding")
+        );
+        assert_eq!(check_open(1, &channels, 3), Some("Channel already open"));
+        assert_eq!(check_open(3, &channels, 2), Some("Too many channels"));
+    }
+}
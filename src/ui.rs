@@ -260,6 +260,10 @@ impl UI {
         forget_password(id)
     }
 
+    fn forget_pinned_key(&self, id: String) {
+        forget_pinned_key(id)
+    }
+
     fn get_peer_option(&self, id: String, name: String) -> String {
         get_peer_option(id, name)
     }
@@ -702,6 +706,7 @@ impl sciter::EventHandler for UI {
         fn get_peer_option(String, String);
         fn peer_has_password(String);
         fn forget_password(String);
+        fn forget_pinned_key(String);
         fn set_peer_option(String, String, String);
         fn get_license();
         fn test_if_valid_server(String, bool);
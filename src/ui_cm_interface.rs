@@ -422,9 +422,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     self.cm.new_message(self.conn_id, text);
                                 }
                                 Data::FS(mut fs) => {
-                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
+                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed, copy_blks } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
-                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
+                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed, copy_blks};
                                             handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
                                         }
                                     } else {
@@ -820,6 +820,7 @@ async fn handle_fs(
             file_num,
             data,
             compressed,
+            copy_blks,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -828,6 +829,7 @@ async fn handle_fs(
                         file_num,
                         data,
                         compressed,
+                        copy_blks,
                         ..Default::default()
                     })
                     .await
@@ -842,6 +844,8 @@ async fn handle_fs(
             file_size,
             last_modified,
             is_upload,
+            resumable,
+            delta_sync,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 let mut req = FileTransferSendConfirmRequest {
@@ -855,8 +859,11 @@ async fn handle_fs(
                     file_num,
                     last_modified,
                     file_size,
+                    resumable,
+                    delta_sync,
                     ..Default::default()
                 };
+                job.set_delta_sync(&digest);
                 if let Some(file) = job.files().get(file_num as usize) {
                     let path = get_string(&job.join(&file.name));
                     match is_write_need_confirmation(&path, &digest) {
@@ -870,6 +877,7 @@ async fn handle_fs(
                                 DigestCheckResult::NeedConfirm(mut digest) => {
                                     // upload to server, but server has the same file, request
                                     digest.is_upload = is_upload;
+                                    digest.signatures = job.new_signatures(file_num).await.into();
                                     let mut msg_out = Message::new();
                                     let mut fr = FileResponse::new();
                                     fr.set_digest(digest);
@@ -877,6 +885,7 @@ async fn handle_fs(
                                     send_raw(msg_out, &tx);
                                 }
                                 DigestCheckResult::NoSuchFile => {
+                                    req.set_offset_blk(job.resume_offset_blk(&digest).await);
                                     let msg_out = new_send_confirm(req);
                                     send_raw(msg_out, &tx);
                                 }
@@ -251,6 +251,13 @@ pub fn forget_password(id: String) {
     c.store(&id);
 }
 
+#[inline]
+pub fn forget_pinned_key(id: String) {
+    let mut c = PeerConfig::load(&id);
+    c.pinned_pk.clear();
+    c.store(&id);
+}
+
 #[inline]
 pub fn get_peer_option(id: String, name: String) -> String {
     let c = PeerConfig::load(&id);
@@ -20,7 +20,7 @@ use uuid::Uuid;
 use hbb_common::fs;
 use hbb_common::{
     allow_err,
-    config::{Config, LocalConfig, PeerConfig},
+    config::{Config, LocalConfig, PeerConfig, PortForwardProfile},
     get_version_number, log,
     message_proto::*,
     rendezvous_proto::ConnType,
@@ -361,6 +361,74 @@ impl<T: InvokeUiSession> Session<T> {
             && !self.lc.read().unwrap().disable_clipboard.v
     }
 
+    // Images are only synced by multi clipboards, which the peer must support.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn is_image_clipboard_required(&self) -> bool {
+        let lc = self.lc.read().unwrap();
+        *self.server_clipboard_enabled.read().unwrap()
+            && *self.server_keyboard_enabled.read().unwrap()
+            && !lc.disable_clipboard.v
+            && lc.peer_info.as_ref().map_or(false, |pi| {
+                crate::clipboard::is_support_multi_clipboard(&pi.version, &pi.platform)
+            })
+    }
+
+    /// The clipboard history of the session as a json array, the latest first.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn get_clipboard_history(&self) -> String {
+        self.lc.read().unwrap().clipboard_history.to_json()
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn clear_clipboard_history(&self) {
+        self.lc.write().unwrap().clipboard_history.clear();
+    }
+
+    /// Put the history entry `id` on the clipboard of the peer if `to_peer`, otherwise on the local one.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn push_clipboard_history(&self, id: u64, to_peer: bool) {
+        let (clipboards, is_image) = match self.lc.read().unwrap().clipboard_history.get(id) {
+            Some(entry) => (entry.clipboards.clone(), entry.is_image()),
+            None => {
+                log::warn!("Clipboard history entry {} not found", id);
+                return;
+            }
+        };
+        if to_peer {
+            let required = if is_image {
+                self.is_image_clipboard_required()
+            } else {
+                self.is_text_clipboard_required()
+            };
+            if !required {
+                return;
+            }
+            let multi_clipboards = MultiClipboards {
+                clipboards,
+                ..Default::default()
+            };
+            let (version, platform) = match self.lc.read().unwrap().peer_info.as_ref() {
+                Some(pi) => (pi.version.clone(), pi.platform.clone()),
+                None => return,
+            };
+            let msg = match crate::clipboard::get_msg_if_not_support_multi_clip(
+                &version,
+                &platform,
+                &multi_clipboards,
+            ) {
+                Some(msg) => msg,
+                None => {
+                    let mut msg = Message::new();
+                    msg.set_multi_clipboards(multi_clipboards);
+                    msg
+                }
+            };
+            self.send(Data::Message(msg));
+        } else {
+            crate::clipboard::update_clipboard(clipboards, crate::clipboard::ClipboardSide::Client);
+        }
+    }
+
     #[cfg(feature = "flutter")]
     pub fn refresh_video(&self, display: i32) {
         if crate::common::is_support_multi_ui_session_num(self.lc.read().unwrap().version) {
@@ -416,6 +484,15 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn get_audio_profile(&self) -> String {
+        self.lc.read().unwrap().get_option("audio-profile")
+    }
+
+    pub fn save_audio_profile(&self, value: String) {
+        let msg = self.lc.write().unwrap().save_audio_profile(value);
+        self.send(Data::Message(msg));
+    }
+
     pub fn save_image_quality(&self, value: String) {
         let msg = self.lc.write().unwrap().save_image_quality(value.clone());
         if let Some(msg) = msg {
@@ -565,6 +642,43 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::AddPortForward(pf));
     }
 
+    pub fn get_port_forward_profiles(&self) -> String {
+        serde_json::to_string(&self.load_config().port_forward_profiles).unwrap_or_default()
+    }
+
+    /// Add the profile, or replace the one with the same name.
+    pub fn save_port_forward_profile(&self, profile: PortForwardProfile) {
+        if profile.name.is_empty() {
+            return;
+        }
+        let mut config = self.load_config();
+        if let Some(p) = config
+            .port_forward_profiles
+            .iter_mut()
+            .find(|p| p.name == profile.name)
+        {
+            *p = profile;
+        } else {
+            config.port_forward_profiles.push(profile);
+        }
+        self.save_config(config);
+    }
+
+    pub fn remove_port_forward_profile(&self, name: String) {
+        let mut config = self.load_config();
+        config.port_forward_profiles.retain(|p| p.name != name);
+        self.save_config(config);
+        self.send(Data::StopPortForwardProfile(name));
+    }
+
+    pub fn start_port_forward_profile(&self, name: String) {
+        self.send(Data::StartPortForwardProfile(name));
+    }
+
+    pub fn stop_port_forward_profile(&self, name: String) {
+        self.send(Data::StopPortForwardProfile(name));
+    }
+
     pub fn get_option(&self, k: String) -> String {
         if k.eq("remote_dir") {
             return self.lc.read().unwrap().get_remote_dir();
@@ -1784,6 +1898,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
         } else if handler.args.len() == 0 {
             let pfs = handler.lc.read().unwrap().port_forwards.clone();
             let mut queues = HashMap::<i32, mpsc::UnboundedSender<Data>>::new();
+            let mut profile_queues = HashMap::<String, mpsc::UnboundedSender<Data>>::new();
             for d in pfs {
                 sender.send(Data::AddPortForward(d)).ok();
             }
@@ -1816,6 +1931,24 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
                             s.send(Data::Close).ok();
                         }
                     }
+                    Some(Data::StartPortForwardProfile(name)) => {
+                        if profile_queues.contains_key(&name) {
+                            continue;
+                        }
+                        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+                        profile_queues.insert(name.clone(), sender);
+                        let handler = handler.clone();
+                        let key = key.clone();
+                        let token = token.clone();
+                        tokio::spawn(async move {
+                            start_port_forward_profile(handler, name, receiver, &key, &token).await;
+                        });
+                    }
+                    Some(Data::StopPortForwardProfile(name)) => {
+                        if let Some(s) = profile_queues.remove(&name) {
+                            s.send(Data::Close).ok();
+                        }
+                    }
                     Some(Data::Close) => {
                         break;
                     }
@@ -1823,17 +1956,23 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
                         for (_, s) in queues.iter() {
                             s.send(d.clone()).ok();
                         }
+                        for (_, s) in profile_queues.iter() {
+                            s.send(d.clone()).ok();
+                        }
                     }
                     _ => {}
                 }
             }
+        } else if handler.args.len() == 1 {
+            let name = handler.args[0].clone();
+            start_port_forward_profile(handler, name, receiver, &key, &token).await;
         } else {
             let port = handler.args[0].parse::<i32>().unwrap_or(0);
             if handler.args.len() != 3
                 || handler.args[2].parse::<i32>().unwrap_or(0) <= 0
                 || port <= 0
             {
-                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port");
+                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port<br>rustdesk --port-forward remote-id profile-name");
             }
             let remote_host = handler.args[1].clone();
             let remote_port = handler.args[2].parse::<i32>().unwrap_or(0);
@@ -1917,6 +2056,43 @@ async fn start_one_port_forward<T: InvokeUiSession>(
     log::info!("port forward (:{}) exit", port);
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+async fn start_port_forward_profile<T: InvokeUiSession>(
+    handler: Session<T>,
+    name: String,
+    receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) {
+    let profile = handler
+        .load_config()
+        .port_forward_profiles
+        .into_iter()
+        .find(|p| p.name == name);
+    let profile = match profile {
+        Some(profile) if !profile.forwards.is_empty() => profile,
+        _ => {
+            handler.on_error(&format!("No port forward profile named {}", name));
+            return;
+        }
+    };
+    if let Err(err) = crate::port_forward::listen_profile(
+        handler.get_id(),
+        handler.password.clone(),
+        profile,
+        handler.clone(),
+        receiver,
+        key,
+        token,
+        handler.lc.clone(),
+    )
+    .await
+    {
+        handler.on_error(&format!("Port forward profile {}: {}", name, err));
+    }
+    log::info!("port forward profile {} exit", name);
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn send_note(url: String, id: String, sid: u64, note: String) {
     let body = serde_json::json!({ "id": id, "session_id": sid, "note": note });
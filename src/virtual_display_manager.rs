@@ -1,19 +1,36 @@
-use hbb_common::{bail, platform::windows::is_windows_version_or_greater, ResultType};
+#[cfg(windows)]
+use hbb_common::bail;
+#[cfg(windows)]
+use hbb_common::platform::windows::is_windows_version_or_greater;
+use hbb_common::ResultType;
+#[cfg(windows)]
+pub use virtual_display::MonitorMode;
+#[cfg(target_os = "linux")]
+pub use xrandr::MonitorMode;
 
 // This string is defined here.
 //  https://github.com/rustdesk-org/RustDeskIddDriver/blob/b370aad3f50028b039aad211df60c8051c4a64d6/RustDeskIddDriver/RustDeskIddDriver.inf#LL73C1-L73C40
+#[cfg(windows)]
 pub const RUSTDESK_IDD_DEVICE_STRING: &'static str = "RustDeskIddDriver Device\0";
+#[cfg(windows)]
 pub const AMYUNI_IDD_DEVICE_STRING: &'static str = "USB Mobile Monitor Virtual Display\0";
 
+#[cfg(windows)]
 const IDD_IMPL: &str = IDD_IMPL_AMYUNI;
+#[cfg(windows)]
 const IDD_IMPL_RUSTDESK: &str = "rustdesk_idd";
+#[cfg(windows)]
 const IDD_IMPL_AMYUNI: &str = "amyuni_idd";
+#[cfg(target_os = "linux")]
+const IDD_IMPL_XRANDR: &str = "xrandr";
 const IDD_PLUG_OUT_ALL_INDEX: i32 = -1;
 
+#[cfg(windows)]
 pub fn is_amyuni_idd() -> bool {
     IDD_IMPL == IDD_IMPL_AMYUNI
 }
 
+#[cfg(windows)]
 pub fn get_cur_device_string() -> &'static str {
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => RUSTDESK_IDD_DEVICE_STRING,
@@ -27,12 +44,13 @@ pub fn is_virtual_display_supported() -> bool {
     {
         is_windows_version_or_greater(10, 0, 19041, 0, 0)
     }
-    #[cfg(not(target_os = "windows"))]
+    #[cfg(target_os = "linux")]
     {
-        false
+        xrandr::is_supported()
     }
 }
 
+#[cfg(windows)]
 pub fn plug_in_headless() -> ResultType<()> {
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_in_headless(),
@@ -43,33 +61,53 @@ pub fn plug_in_headless() -> ResultType<()> {
 
 pub fn get_platform_additions() -> serde_json::Map<String, serde_json::Value> {
     let mut map = serde_json::Map::new();
-    if !crate::platform::windows::is_self_service_running() {
-        return map;
+    #[cfg(target_os = "linux")]
+    {
+        if !xrandr::is_supported() {
+            return map;
+        }
+        map.insert("idd_impl".into(), serde_json::json!(IDD_IMPL_XRANDR));
+        let virtual_displays = xrandr::get_virtual_displays();
+        if !virtual_displays.is_empty() {
+            map.insert(
+                "rustdesk_virtual_displays".into(),
+                serde_json::json!(virtual_displays),
+            );
+        }
     }
-    map.insert("idd_impl".into(), serde_json::json!(IDD_IMPL));
-    match IDD_IMPL {
-        IDD_IMPL_RUSTDESK => {
-            let virtual_displays = rustdesk_idd::get_virtual_displays();
-            if !virtual_displays.is_empty() {
-                map.insert(
-                    "rustdesk_virtual_displays".into(),
-                    serde_json::json!(virtual_displays),
-                );
-            }
+    #[cfg(windows)]
+    {
+        if !crate::platform::windows::is_self_service_running() {
+            return map;
         }
-        IDD_IMPL_AMYUNI => {
-            let c = amyuni_idd::get_monitor_count();
-            if c > 0 {
-                map.insert("amyuni_virtual_displays".into(), serde_json::json!(c));
+        map.insert("idd_impl".into(), serde_json::json!(IDD_IMPL));
+        match IDD_IMPL {
+            IDD_IMPL_RUSTDESK => {
+                let virtual_displays = rustdesk_idd::get_virtual_displays();
+                if !virtual_displays.is_empty() {
+                    map.insert(
+                        "rustdesk_virtual_displays".into(),
+                        serde_json::json!(virtual_displays),
+                    );
+                }
+            }
+            IDD_IMPL_AMYUNI => {
+                let c = amyuni_idd::get_monitor_count();
+                if c > 0 {
+                    map.insert("amyuni_virtual_displays".into(), serde_json::json!(c));
+                }
             }
+            _ => {}
         }
-        _ => {}
     }
     map
 }
 
 #[inline]
-pub fn plug_in_monitor(idx: u32, modes: Vec<virtual_display::MonitorMode>) -> ResultType<()> {
+pub fn plug_in_monitor(idx: u32, modes: Vec<MonitorMode>) -> ResultType<()> {
+    #[cfg(target_os = "linux")]
+    return xrandr::plug_in_index_modes(idx, modes);
+    #[cfg(windows)]
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_in_index_modes(idx, modes),
         IDD_IMPL_AMYUNI => amyuni_idd::plug_in_monitor(),
@@ -78,6 +116,16 @@ pub fn plug_in_monitor(idx: u32, modes: Vec<virtual_display::MonitorMode>) -> Re
 }
 
 pub fn plug_out_monitor(index: i32, force_all: bool, force_one: bool) -> ResultType<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let _ = (force_all, force_one);
+        return if index == IDD_PLUG_OUT_ALL_INDEX {
+            xrandr::reset_all()
+        } else {
+            xrandr::plug_out_peer_request(&[index as _])
+        };
+    }
+    #[cfg(windows)]
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => {
             let indices = if index == IDD_PLUG_OUT_ALL_INDEX {
@@ -92,7 +140,10 @@ pub fn plug_out_monitor(index: i32, force_all: bool, force_one: bool) -> ResultT
     }
 }
 
-pub fn plug_in_peer_request(modes: Vec<Vec<virtual_display::MonitorMode>>) -> ResultType<Vec<u32>> {
+pub fn plug_in_peer_request(modes: Vec<Vec<MonitorMode>>) -> ResultType<Vec<u32>> {
+    #[cfg(target_os = "linux")]
+    return xrandr::plug_in_peer_request(modes);
+    #[cfg(windows)]
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_in_peer_request(modes),
         IDD_IMPL_AMYUNI => {
@@ -108,6 +159,12 @@ pub fn plug_out_monitor_indices(
     force_all: bool,
     force_one: bool,
 ) -> ResultType<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let _ = (force_all, force_one);
+        return xrandr::plug_out_peer_request(indices);
+    }
+    #[cfg(windows)]
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::plug_out_peer_request(indices),
         IDD_IMPL_AMYUNI => {
@@ -121,6 +178,9 @@ pub fn plug_out_monitor_indices(
 }
 
 pub fn reset_all() -> ResultType<()> {
+    #[cfg(target_os = "linux")]
+    return xrandr::reset_all();
+    #[cfg(windows)]
     match IDD_IMPL {
         IDD_IMPL_RUSTDESK => rustdesk_idd::reset_all(),
         IDD_IMPL_AMYUNI => amyuni_idd::reset_all(),
@@ -128,6 +188,7 @@ pub fn reset_all() -> ResultType<()> {
     }
 }
 
+#[cfg(windows)]
 pub mod rustdesk_idd {
     use super::windows;
     use hbb_common::{allow_err, bail, lazy_static, log, ResultType};
@@ -381,6 +442,7 @@ pub mod rustdesk_idd {
     }
 }
 
+#[cfg(windows)]
 pub mod amyuni_idd {
     use super::windows;
     use crate::platform::{reg_display_settings, win_device};
@@ -718,6 +780,418 @@ pub mod amyuni_idd {
     }
 }
 
+// Virtual displays for X11 sessions, e.g. the headless Xorg session with the dummy driver.
+// The framebuffer is enlarged and RandR 1.5 monitors are added to the right of it,
+// so they're enumerated as displays by the capturer and by the window manager.
+#[cfg(target_os = "linux")]
+pub mod xrandr {
+    use hbb_common::{allow_err, bail, lazy_static, log, regex::Regex, ResultType};
+    use std::{
+        collections::BTreeMap,
+        process::Command,
+        sync::{Arc, Mutex},
+    };
+
+    // Keep the same index range as `rustdesk_idd`, the peer side shows them in the same way.
+    const VIRTUAL_DISPLAY_START_FOR_PEER: u32 = 1;
+    const VIRTUAL_DISPLAY_MAX_COUNT: u32 = 5;
+    const DEFAULT_WIDTH: u32 = 1920;
+    const DEFAULT_HEIGHT: u32 = 1080;
+    // The width and height requested by the peers are limited to this.
+    const MAX_SIZE: u32 = 8192;
+    // Used to report the physical size of the monitors.
+    const DPI: u32 = 96;
+
+    lazy_static::lazy_static! {
+        static ref VIRTUAL_DISPLAY_MANAGER: Arc<Mutex<VirtualDisplayManager>> =
+            Arc::new(Mutex::new(VirtualDisplayManager::default()));
+        static ref IS_SUPPORTED: bool = check_supported();
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct MonitorMode {
+        pub width: u32,
+        pub height: u32,
+        pub sync: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Monitor {
+        name: String,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    }
+
+    #[derive(Default)]
+    struct VirtualDisplayManager {
+        // The framebuffer size before plugging in the first virtual display.
+        base_size: Option<(u32, u32)>,
+        index_size: BTreeMap<u32, (u32, u32)>,
+    }
+
+    impl VirtualDisplayManager {
+        // Apply the current virtual displays, `removed` are the indices plugged out.
+        fn apply(&mut self, removed: &[u32]) -> ResultType<()> {
+            let base_size = match self.base_size {
+                Some(size) => size,
+                None => {
+                    let size = get_screen_size()?;
+                    self.base_size = Some(size);
+                    size
+                }
+            };
+            let (monitors, fb_size) = layout(base_size, &self.index_size)?;
+            let cur_size = get_screen_size()?;
+            // Grow the framebuffer before setting the monitors and shrink it after.
+            let grow_size = (cur_size.0.max(fb_size.0), cur_size.1.max(fb_size.1));
+            if grow_size != cur_size {
+                set_screen_size(grow_size)?;
+            }
+            for idx in removed.iter() {
+                allow_err!(xrandr(&["--delmonitor", &monitor_name(*idx)]));
+            }
+            for m in monitors.iter() {
+                set_monitor(m)?;
+            }
+            if fb_size != grow_size {
+                set_screen_size(fb_size)?;
+            }
+            if self.index_size.is_empty() {
+                self.base_size = None;
+            }
+            Ok(())
+        }
+    }
+
+    fn xrandr(args: &[&str]) -> ResultType<String> {
+        let output = Command::new("xrandr").args(args).output()?;
+        if !output.status.success() {
+            bail!(
+                "xrandr {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn get_screen_size() -> ResultType<(u32, u32)> {
+        match parse_screen_size(&xrandr(&["--query"])?) {
+            Some(size) => Ok(size),
+            None => bail!("Failed to get the screen size from xrandr"),
+        }
+    }
+
+    fn set_screen_size(size: (u32, u32)) -> ResultType<()> {
+        xrandr(&["--fb", &format!("{}x{}", size.0, size.1)])?;
+        Ok(())
+    }
+
+    // The physical size in millimeters.
+    fn size_mm(pixels: u32) -> u64 {
+        pixels as u64 * 254 / (DPI as u64 * 10)
+    }
+
+    fn set_monitor(m: &Monitor) -> ResultType<()> {
+        let geometry = format!(
+            "{}/{}x{}/{}+{}+{}",
+            m.width,
+            size_mm(m.width),
+            m.height,
+            size_mm(m.height),
+            m.x,
+            m.y
+        );
+        xrandr(&["--setmonitor", &m.name, &geometry, "none"])?;
+        Ok(())
+    }
+
+    fn parse_screen_size(output: &str) -> Option<(u32, u32)> {
+        let re = Regex::new(r"current (?P<width>\d+) x (?P<height>\d+)").ok()?;
+        let caps = re.captures(output)?;
+        Some((caps["width"].parse().ok()?, caps["height"].parse().ok()?))
+    }
+
+    // Monitor names in the output of `xrandr --listmonitors`.
+    //  Monitors: 2
+    //   0: +*DUMMY0 1920/508x1080/286+0+0  DUMMY0
+    //   1: FIREFOX-VD-1 1920/508x1080/286+1920+0  none
+    fn parse_monitor_names(output: &str) -> Vec<String> {
+        let Ok(re) = Regex::new(r"(?m)^\s*\d+:\s+[+*]*(?P<name>\S+)\s") else {
+            return vec![];
+        };
+        re.captures_iter(output)
+            .map(|caps| caps["name"].to_owned())
+            .collect()
+    }
+
+    // The virtual displays are placed in index order, to the right of the base framebuffer.
+    fn layout(
+        base_size: (u32, u32),
+        index_size: &BTreeMap<u32, (u32, u32)>,
+    ) -> ResultType<(Vec<Monitor>, (u32, u32))> {
+        let (mut x, mut height) = base_size;
+        let mut monitors = vec![];
+        for (idx, (w, h)) in index_size.iter() {
+            monitors.push(Monitor {
+                name: monitor_name(*idx),
+                x,
+                y: 0,
+                width: *w,
+                height: *h,
+            });
+            x = match x.checked_add(*w) {
+                Some(x) => x,
+                None => bail!("The framebuffer width overflows"),
+            };
+            height = height.max(*h);
+        }
+        Ok((monitors, (x, height)))
+    }
+
+    fn monitor_name_prefix() -> String {
+        format!(
+            "{}-VD-",
+            crate::get_app_name().to_uppercase().replace(' ', "-")
+        )
+    }
+
+    fn monitor_name(idx: u32) -> String {
+        format!("{}{}", monitor_name_prefix(), idx)
+    }
+
+    fn monitor_index(name: &str) -> Option<u32> {
+        name.strip_prefix(&monitor_name_prefix())?.parse().ok()
+    }
+
+    fn check_size(width: u32, height: u32) -> ResultType<(u32, u32)> {
+        if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+            bail!("Invalid virtual display mode {}x{}", width, height);
+        }
+        Ok((width, height))
+    }
+
+    fn mode_size(modes: &[MonitorMode]) -> ResultType<(u32, u32)> {
+        match modes.first() {
+            Some(m) => check_size(m.width, m.height),
+            None => Ok((DEFAULT_WIDTH, DEFAULT_HEIGHT)),
+        }
+    }
+
+    pub fn is_supported() -> bool {
+        *IS_SUPPORTED
+    }
+
+    // RandR 1.5 is required to add monitors.
+    fn check_supported() -> bool {
+        if !crate::platform::linux::is_x11() {
+            return false;
+        }
+        let Ok(output) = xrandr(&["--version"]) else {
+            return false;
+        };
+        let Ok(re) = Regex::new(r"RandR version (?P<major>\d+)\.(?P<minor>\d+)") else {
+            return false;
+        };
+        match re.captures(&output) {
+            Some(caps) => {
+                let major: u32 = caps["major"].parse().unwrap_or(0);
+                let minor: u32 = caps["minor"].parse().unwrap_or(0);
+                (major, minor) >= (1, 5)
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_virtual_displays() -> Vec<u32> {
+        VIRTUAL_DISPLAY_MANAGER
+            .lock()
+            .unwrap()
+            .index_size
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    pub fn plug_in_index_modes(idx: u32, modes: Vec<MonitorMode>) -> ResultType<()> {
+        if !(VIRTUAL_DISPLAY_START_FOR_PEER..VIRTUAL_DISPLAY_MAX_COUNT).contains(&idx) {
+            bail!("Invalid virtual display index {}", idx);
+        }
+        let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        if manager.index_size.contains_key(&idx) {
+            return Ok(());
+        }
+        manager.index_size.insert(idx, mode_size(&modes)?);
+        if let Err(e) = manager.apply(&[]) {
+            manager.index_size.remove(&idx);
+            allow_err!(manager.apply(&[idx]));
+            bail!("Plug in monitor failed {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn plug_in_peer_request(modes: Vec<Vec<MonitorMode>>) -> ResultType<Vec<u32>> {
+        let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        let mut indices: Vec<u32> = Vec::new();
+        for m in modes.iter() {
+            let size = mode_size(m)?;
+            if let Some(idx) = (VIRTUAL_DISPLAY_START_FOR_PEER..VIRTUAL_DISPLAY_MAX_COUNT)
+                .find(|idx| !manager.index_size.contains_key(idx))
+            {
+                manager.index_size.insert(idx, size);
+                indices.push(idx);
+            }
+        }
+        if let Err(e) = manager.apply(&[]) {
+            for idx in indices.iter() {
+                manager.index_size.remove(idx);
+            }
+            allow_err!(manager.apply(&indices));
+            bail!("Plug in monitor failed {}", e);
+        }
+        Ok(indices)
+    }
+
+    pub fn plug_out_peer_request(indices: &[u32]) -> ResultType<()> {
+        let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        let removed: Vec<u32> = indices
+            .iter()
+            .filter(|idx| manager.index_size.remove(*idx).is_some())
+            .cloned()
+            .collect();
+        if removed.is_empty() {
+            return Ok(());
+        }
+        manager.apply(&removed)
+    }
+
+    // Also removes the virtual displays left by a previous process.
+    pub fn reset_all() -> ResultType<()> {
+        let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        let mut removed: Vec<u32> = manager.index_size.keys().cloned().collect();
+        if let Ok(output) = xrandr(&["--listmonitors"]) {
+            for idx in parse_monitor_names(&output)
+                .iter()
+                .filter_map(|name| monitor_index(name))
+            {
+                if !removed.contains(&idx) {
+                    removed.push(idx);
+                }
+            }
+        }
+        if removed.is_empty() {
+            return Ok(());
+        }
+        manager.index_size.clear();
+        if manager.base_size.is_none() {
+            // The virtual displays are left by a previous process, only remove them.
+            for idx in removed.iter() {
+                allow_err!(xrandr(&["--delmonitor", &monitor_name(*idx)]));
+            }
+            return Ok(());
+        }
+        manager.apply(&removed)
+    }
+
+    pub fn is_virtual_display(name: &str) -> bool {
+        match monitor_index(name) {
+            Some(idx) => VIRTUAL_DISPLAY_MANAGER
+                .lock()
+                .unwrap()
+                .index_size
+                .contains_key(&idx),
+            None => false,
+        }
+    }
+
+    pub fn change_resolution_if_is_virtual_display(name: &str, w: u32, h: u32) -> Option<bool> {
+        let idx = monitor_index(name)?;
+        let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+        let old = *manager.index_size.get(&idx)?;
+        let size = match check_size(w, h) {
+            Ok(size) => size,
+            Err(e) => {
+                log::error!("Change virtual display {} failed: {}", idx, e);
+                return Some(false);
+            }
+        };
+        manager.index_size.insert(idx, size);
+        match manager.apply(&[]) {
+            Ok(_) => Some(true),
+            Err(e) => {
+                log::error!(
+                    "Change virtual display {} to {}x{} failed: {}",
+                    idx,
+                    w,
+                    h,
+                    e
+                );
+                manager.index_size.insert(idx, old);
+                allow_err!(manager.apply(&[]));
+                Some(false)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_layout() {
+            let mut index_size = BTreeMap::new();
+            assert_eq!(
+                layout((1920, 1080), &index_size).unwrap(),
+                (vec![], (1920, 1080))
+            );
+            index_size.insert(3, (1280, 720));
+            index_size.insert(1, (2560, 1440));
+            let (monitors, fb_size) = layout((1920, 1080), &index_size).unwrap();
+            assert_eq!(fb_size, (1920 + 2560 + 1280, 1440));
+            assert_eq!(monitors[0].name, monitor_name(1));
+            assert_eq!((monitors[0].x, monitors[0].width), (1920, 2560));
+            assert_eq!(monitors[1].name, monitor_name(3));
+            assert_eq!((monitors[1].x, monitors[1].height), (1920 + 2560, 720));
+            index_size.insert(2, (u32::MAX, 1080));
+            assert!(layout((1920, 1080), &index_size).is_err());
+        }
+
+        #[test]
+        fn test_size() {
+            let mode = |width, height| MonitorMode {
+                width,
+                height,
+                sync: 60,
+            };
+            assert_eq!(mode_size(&[]).unwrap(), (DEFAULT_WIDTH, DEFAULT_HEIGHT));
+            assert_eq!(mode_size(&[mode(2560, 1440)]).unwrap(), (2560, 1440));
+            assert!(mode_size(&[mode(0, 1080)]).is_err());
+            assert!(mode_size(&[mode(MAX_SIZE + 1, 1080)]).is_err());
+            // negative values of the peer
+            assert!(mode_size(&[mode(-1i32 as u32, 1080)]).is_err());
+            assert_eq!(size_mm(1920), 508);
+            assert_eq!(size_mm(u32::MAX), u32::MAX as u64 * 254 / 960);
+        }
+
+        #[test]
+        fn test_parse() {
+            let query = "Screen 0: minimum 8 x 8, current 4480 x 1440, maximum 32767 x 32767\n";
+            assert_eq!(parse_screen_size(query), Some((4480, 1440)));
+            let list = format!(
+                "Monitors: 2\n 0: +*DUMMY0 1920/508x1080/286+0+0  DUMMY0\n 1: {} 2560/677x1440/381+1920+0  none\n",
+                monitor_name(2)
+            );
+            let names = parse_monitor_names(&list);
+            assert_eq!(names, vec!["DUMMY0".to_owned(), monitor_name(2)]);
+            assert_eq!(monitor_index(&names[0]), None);
+            assert_eq!(monitor_index(&names[1]), Some(2));
+        }
+    }
+}
+
+#[cfg(windows)]
 mod windows {
     use std::ptr::null_mut;
     use winapi::{